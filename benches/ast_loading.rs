@@ -0,0 +1,108 @@
+//! Compare loading a large AST JSON file via a `String` against streaming it from a reader
+//!
+//! Run with `cargo bench --bench ast_loading`.
+
+use serde_json::{json, Value};
+use std::{
+    fs,
+    io::BufReader,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// Number of contracts in the generated fixture (about 20 MB of JSON)
+const CONTRACTS: usize = 2_000;
+
+/// Timed runs per loader
+const RUNS: u32 = 5;
+
+/// Build a source unit with many contracts, each with a few functions calling a dependency
+fn large_ast() -> Value {
+    let contracts: Vec<Value> = (0..CONTRACTS)
+        .map(|i| {
+            let functions: Vec<Value> = (0..20)
+                .map(|j| {
+                    json!({
+                        "nodeType": "FunctionDefinition",
+                        "name": format!("action{}", j),
+                        "kind": "function",
+                        "visibility": "external",
+                        "stateMutability": "nonpayable",
+                        "parameters": { "parameters": [] },
+                        "returnParameters": { "parameters": [] },
+                        "body": { "nodeType": "Block", "statements": [{
+                            "nodeType": "ExpressionStatement",
+                            "expression": {
+                                "nodeType": "FunctionCall",
+                                "arguments": [],
+                                "expression": {
+                                    "nodeType": "MemberAccess",
+                                    "memberName": "poke",
+                                    "expression": {
+                                        "nodeType": "Identifier",
+                                        "name": "dependency",
+                                        "typeDescriptions": { "typeString": "contract IDependency" },
+                                    },
+                                },
+                            },
+                        }]},
+                    })
+                })
+                .collect();
+            json!({
+                "nodeType": "ContractDefinition",
+                "name": format!("Contract{}", i),
+                "contractKind": "contract",
+                "baseContracts": [],
+                "nodes": functions,
+            })
+        })
+        .collect();
+
+    json!({ "nodeType": "SourceUnit", "absolutePath": "Large.sol", "nodes": contracts })
+}
+
+/// Read the whole file into a `String`, then parse it
+fn load_via_string(path: &Path) -> Value {
+    let content = fs::read_to_string(path).unwrap();
+    serde_json::from_str(&content).unwrap()
+}
+
+/// Parse the file straight from a buffered reader, as `generate_diagram_from_file` does
+fn load_via_reader(path: &Path) -> Value {
+    let file = fs::File::open(path).unwrap();
+    serde_json::from_reader(BufReader::new(file)).unwrap()
+}
+
+/// Average wall-clock time of a loader over `RUNS` runs
+fn time(path: &Path, loader: fn(&Path) -> Value) -> Duration {
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        std::hint::black_box(loader(path));
+    }
+    start.elapsed() / RUNS
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("large_ast.json");
+    fs::write(&path, large_ast().to_string()).unwrap();
+    let size = fs::metadata(&path).unwrap().len();
+
+    println!(
+        "fixture: {} contracts, {:.1} MB",
+        CONTRACTS,
+        size as f64 / 1e6
+    );
+    println!(
+        "read_to_string + from_str: {:?}",
+        time(&path, load_via_string)
+    );
+    println!(
+        "BufReader + from_reader:   {:?}",
+        time(&path, load_via_reader)
+    );
+
+    let diagram = sol2seq::generate_diagram_from_file(&path, sol2seq::Config::default()).unwrap();
+    assert!(diagram.contains("Contract0->>+IDependency: poke()"));
+}
@@ -4,7 +4,11 @@ use std::path::PathBuf;
 
 fn main() -> Result<()> {
     // Create a configuration
-    let config = Config { light_colors: true, output_file: Some(PathBuf::from("diagram.md")) };
+    let config = Config {
+        light_colors: true,
+        output_file: Some(PathBuf::from("diagram.md")),
+        ..Default::default()
+    };
 
     // Generate diagram from AST file
     // Replace "path/to/ast.json" with an actual file path to test
@@ -1,114 +1,476 @@
-use crate::{types::*, utils::*};
+use crate::{legacy, types::*, utils::*, Config, Sol2SeqError};
 use anyhow::{Context, Result};
+use itertools::Itertools;
 use serde_json::Value;
-use std::process::Command;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Participant owning file-level (free) functions
+const FREE_FUNCTIONS: &str = "FreeFunctions";
+
+/// Participant standing for `msg.sender` when `show_msg_sender` is enabled
+pub(crate) const CALLER: &str = "Caller";
+
+/// Shared state while walking function bodies
+struct BodyContext<'a> {
+    config: &'a Config,
+    /// Function definitions of every source unit, used to inline calls
+    functions: &'a FunctionIndex<'a>,
+    /// Ids of the function definitions currently being walked, to stop recursive inlining
+    visited: HashSet<i64>,
+    /// Current nesting depth of `process_function_body`
+    depth: usize,
+    /// Whether an external call has been rendered earlier in the entrypoint
+    external_call_made: bool,
+    /// Whether a state write after an external call has already been flagged
+    reentrancy_flagged: bool,
+}
+
+/// Function and event definitions across all source units
+#[derive(Default)]
+struct FunctionIndex<'a> {
+    /// Functions keyed by node id, the target of an identifier's `referencedDeclaration`,
+    /// along with the defining contract or `FreeFunctions`
+    by_id: HashMap<i64, (&'a str, &'a Value)>,
+    /// Functions keyed by `Contract.function` or `FreeFunctions.function`, for calls without
+    /// a declaration id; the first of several overloads wins
+    by_name: HashMap<String, &'a Value>,
+    /// Event definitions keyed by node id, the target of an emit's `referencedDeclaration`
+    events: HashMap<i64, &'a Value>,
+}
+
+impl<'a> FunctionIndex<'a> {
+    /// Find a function defined on `contract_name` or the nearest base that defines it
+    fn find(&self, data: &DiagramData, contract_name: &str, function: &str) -> Option<&'a Value> {
+        lineage(data, contract_name)
+            .iter()
+            .find_map(|info| self.by_name.get(&format!("{}.{}", info.name, function)).copied())
+    }
+}
 
 /// Parse AST JSON and extract contract information
-pub fn extract_contract_info(ast: &Value, show_storage_updates: bool) -> Result<DiagramData> {
+pub fn extract_contract_info(ast: &Value, config: &Config) -> Result<DiagramData> {
+    let ast = compact_ast(ast);
+    let source_units = source_units(&ast)?;
+    let data = collect_source_units(&source_units)?;
+
+    analyze_contracts(&source_units, data, config)
+}
+
+/// Parse AST JSON once and extract the information of each contract on its own
+///
+/// Interfaces, libraries and free functions have no entrypoints of their own and are
+/// skipped unless `config.contracts` names them explicitly.
+pub fn extract_contract_info_per_contract(
+    ast: &Value,
+    config: &Config,
+) -> Result<Vec<(String, DiagramData)>> {
+    let ast = compact_ast(ast);
+    let source_units = source_units(&ast)?;
+    let data = collect_source_units(&source_units)?;
+
+    let contract_names: Vec<String> = if config.contracts.is_empty() {
+        data.contracts
+            .values()
+            .filter(|info| {
+                !matches!(info.contract_type.as_str(), "interface" | "library" | "free functions")
+            })
+            .map(|info| info.name.clone())
+            .sorted()
+            .collect()
+    } else {
+        config.contracts.clone()
+    };
+
+    contract_names
+        .into_iter()
+        .map(|contract_name| {
+            let contract_config =
+                Config { contracts: vec![contract_name.clone()], ..config.clone() };
+            let contract_data = analyze_contracts(&source_units, data.clone(), &contract_config)?;
+            Ok((contract_name, contract_data))
+        })
+        .collect()
+}
+
+/// Legacy `children`/`attributes` ASTs from old solc are read as compact ASTs
+fn compact_ast(ast: &Value) -> Cow<'_, Value> {
+    if legacy::is_legacy_ast(ast) {
+        log::debug!("Translating legacy AST");
+        Cow::Owned(legacy::to_compact(ast))
+    } else {
+        Cow::Borrowed(ast)
+    }
+}
+
+/// First pass: collect all contracts, state variables, and events across every source
+fn collect_source_units(source_units: &[&Value]) -> Result<DiagramData> {
+    // Contract names by node id, to read `linearizedBaseContracts`
+    let contract_ids: HashMap<i64, &str> = source_units
+        .iter()
+        .flat_map(|source_unit| source_unit["nodes"].as_array().into_iter().flatten())
+        .filter(|node| node["nodeType"].as_str() == Some("ContractDefinition"))
+        .filter_map(|node| Some((node["id"].as_i64()?, node["name"].as_str()?)))
+        .collect();
+
     let mut data = DiagramData::default();
+    for source_unit in source_units {
+        collect_contracts_and_variables(source_unit, &mut data, &contract_ids)?;
+    }
+    resolve_inherited_functions(&mut data);
 
-    // Handle combined-json format
-    if let Some(sources) = ast.get("sources") {
-        for (_file_path, source) in sources.as_object().with_context(|| "sources is not an object")? {
-            if let Some(source_ast) = source.get("AST") {
-                // First pass: collect all contracts, state variables, and events
-                collect_contracts_and_variables(source_ast, &mut data)?;
+    Ok(data)
+}
 
-                // Add default participants
-                data.participants.insert("User".to_string());
-                data.participants.insert("Events".to_string());
-                data.participants.insert("TokenContract".to_string());
+/// Second pass: walk the function bodies of the configured contracts
+fn analyze_contracts(
+    source_units: &[&Value],
+    mut data: DiagramData,
+    config: &Config,
+) -> Result<DiagramData> {
+    // Only keep the requested contracts as participants; call targets are re-added below
+    if !config.contracts.is_empty() {
+        if let Some(missing) = config.contracts.iter().find(|c| !data.contracts.contains_key(*c)) {
+            return Err(anyhow::anyhow!("Contract not found in AST: {}", missing));
+        }
+        for contract_name in data.contracts.keys() {
+            if !config.contracts.contains(contract_name) {
+                data.participants.shift_remove(contract_name);
+            }
+        }
+    }
 
-                // Second pass: analyze function calls and interactions
-                process_functions_and_interactions(source_ast, &mut data, show_storage_updates)?;
+    // Index function and event definitions across all sources so internal and inherited calls
+    // resolve; free functions can be called from any file that imports them
+    let mut functions = FunctionIndex::default();
+    for node in source_units
+        .iter()
+        .flat_map(|source_unit| source_unit["nodes"].as_array().into_iter().flatten())
+    {
+        match node["nodeType"].as_str() {
+            Some("FunctionDefinition") => {
+                if let Some(id) = node["id"].as_i64() {
+                    functions.by_id.insert(id, (FREE_FUNCTIONS, node));
+                }
+                if let Some(name) = node["name"].as_str().filter(|name| !name.is_empty()) {
+                    functions.by_name.entry(format!("{}.{}", FREE_FUNCTIONS, name)).or_insert(node);
+                }
+            }
+            Some("EventDefinition") => {
+                if let Some(id) = node["id"].as_i64() {
+                    functions.events.insert(id, node);
+                }
+            }
+            Some("ContractDefinition") => {
+                let contract_name = node["name"].as_str().unwrap_or("Unknown");
+                for contract_node in node["nodes"].as_array().into_iter().flatten() {
+                    if contract_node["nodeType"].as_str() == Some("EventDefinition") {
+                        if let Some(id) = contract_node["id"].as_i64() {
+                            functions.events.insert(id, contract_node);
+                        }
+                    }
+                    if contract_node["nodeType"].as_str() != Some("FunctionDefinition") {
+                        continue;
+                    }
+                    if let Some(id) = contract_node["id"].as_i64() {
+                        functions.by_id.insert(id, (contract_name, contract_node));
+                    }
+                    if let Some(name) = contract_node["name"].as_str().filter(|n| !n.is_empty()) {
+                        functions
+                            .by_name
+                            .entry(format!("{}.{}", contract_name, name))
+                            .or_insert(contract_node);
+                    }
+                }
             }
+            _ => {}
+        }
+    }
+
+    for source_unit in source_units {
+        process_functions_and_interactions(source_unit, &mut data, config, &functions)?;
+    }
+
+    if !config.contracts.is_empty() {
+        let participants = &data.participants;
+        data.contracts.retain(|name, _| participants.contains(name));
+    }
+
+    if config.collapse_duplicates {
+        data.user_interactions = collapse_duplicate_lines(&data.user_interactions);
+        for interactions in data.contract_interactions.values_mut() {
+            *interactions = collapse_duplicate_lines(interactions);
         }
+    }
+
+    Ok(data)
+}
+
+/// Source units of an AST in any of the supported layouts
+fn source_units(ast: &Value) -> Result<Vec<&Value>> {
+    let is_standard_json = ast
+        .get("sources")
+        .and_then(|sources| sources.as_object())
+        .is_some_and(|sources| sources.values().any(|source| source.get("ast").is_some()));
+
+    let source_units: Vec<&Value> = if is_standard_json {
+        // Handle standard-json output, where each source carries a lowercase `ast`
+        log::debug!("Reading standard-json AST");
+        ast["sources"]
+            .as_object()
+            .with_context(|| "sources is not an object")?
+            .values()
+            .filter_map(|source| source.get("ast"))
+            .collect()
+    } else if let Some(sources) = ast.get("sources") {
+        // Handle combined-json format
+        log::debug!("Reading combined-json AST");
+        sources
+            .as_object()
+            .with_context(|| "sources is not an object")?
+            .values()
+            .filter_map(|source| source.get("AST"))
+            .collect()
     } else if let Some(source_units) = ast.get("source_units").and_then(|su| su.as_array()) {
         // Handle Aderyn format with source_units array
-        for source_unit in source_units {
-            if let Some(_nodes) = source_unit.get("nodes") {
-                // Create a copy of the source_unit to work with for each pass
-                let src_unit_copy = source_unit.clone();
-                
-                // First pass: collect all contracts, state variables, and events
-                collect_contracts_and_variables(&src_unit_copy, &mut data)?;
+        log::debug!("Reading Aderyn AST with {} source units", source_units.len());
+        source_units.iter().filter(|source_unit| source_unit.get("nodes").is_some()).collect()
+    } else if ast.get("nodes").is_some_and(Value::is_array) {
+        // Handle legacy format
+        log::debug!("Reading single source unit AST");
+        vec![ast]
+    } else {
+        return Err(Sol2SeqError::UnsupportedAstFormat(
+            "expected `sources`, `source_units` or a top-level `nodes` array".to_string(),
+        )
+        .into());
+    };
 
-                // Add default participants
-                data.participants.insert("User".to_string());
-                data.participants.insert("Events".to_string());
-                data.participants.insert("TokenContract".to_string());
+    Ok(source_units)
+}
 
-                // Second pass: analyze function calls and interactions
-                process_functions_and_interactions(&src_unit_copy, &mut data, show_storage_updates)?;
+/// Extract contract information and report what was found and what was missing
+pub fn validate_ast(ast: &Value, config: &Config) -> Result<ValidationReport> {
+    let data = extract_contract_info(ast, config)?;
+    let ast = compact_ast(ast);
+    let mut report = ValidationReport {
+        contracts: data.contracts.len(),
+        functions: data.contracts.values().map(|info| info.functions.len()).sum(),
+        interactions: data.user_interactions.len()
+            + data.contract_interactions.values().map(Vec::len).sum::<usize>(),
+        ..Default::default()
+    };
+
+    let contracts = source_units(&ast)?
+        .into_iter()
+        .flat_map(|source_unit| source_unit["nodes"].as_array().into_iter().flatten())
+        .filter(|node| node["nodeType"].as_str() == Some("ContractDefinition"));
+    for contract in contracts {
+        let contract_name = contract["name"].as_str().unwrap_or("Unknown");
+        for field in ["name", "contractKind", "nodes"] {
+            if contract.get(field).is_none() {
+                report.missing_fields.push(format!("{}.{}", contract_name, field));
             }
         }
-    } else {
-        // Handle legacy format
-        // First pass: collect all contracts, state variables, and events
-        collect_contracts_and_variables(ast, &mut data)?;
 
-        // Add default participants
-        data.participants.insert("User".to_string());
-        data.participants.insert("Events".to_string());
-        data.participants.insert("TokenContract".to_string());
+        let functions: Vec<&Value> = contract["nodes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|node| node["nodeType"].as_str() == Some("FunctionDefinition"))
+            .collect();
+        for function in &functions {
+            let function_name = function["name"].as_str().unwrap_or("?");
+            for field in ["name", "visibility", "parameters"] {
+                if function.get(field).is_none() {
+                    report
+                        .missing_fields
+                        .push(format!("{}.{}.{}", contract_name, function_name, field));
+                }
+            }
+        }
 
-        // Second pass: analyze function calls and interactions
-        process_functions_and_interactions(ast, &mut data, show_storage_updates)?;
+        // Interfaces have no bodies by design
+        let has_body = functions.iter().any(|function| function["body"]["statements"].is_array());
+        let is_interface = contract["contractKind"].as_str() == Some("interface");
+        if !functions.is_empty() && !has_body && !is_interface {
+            report.contracts_without_bodies.push(contract_name.to_string());
+        }
     }
 
-    Ok(data)
+    Ok(report)
+}
+
+/// Record the public/external functions each contract inherits from its bases
+///
+/// Bases are visited most-derived first; a function already defined or inherited under
+/// the same name shadows later ones, and inheritance cycles are ignored.
+fn resolve_inherited_functions(data: &mut DiagramData) {
+    let contract_names: Vec<String> = data.contracts.keys().cloned().collect();
+
+    for contract_name in contract_names {
+        let mut seen: HashSet<String> =
+            data.contracts[&contract_name].functions.iter().cloned().collect();
+        let mut inherited = Vec::new();
+
+        for base in lineage(data, &contract_name).into_iter().skip(1) {
+            for function in &base.function_details {
+                let callable = function.visibility == "public" || function.visibility == "external";
+                if callable && function.kind == "function" && seen.insert(function.name.clone()) {
+                    inherited.push((function.name.clone(), base.name.clone()));
+                }
+            }
+        }
+
+        if let Some(info) = data.contracts.get_mut(&contract_name) {
+            info.inherited_functions = inherited;
+        }
+    }
 }
 
 /// Process source units to collect contracts and variables
-fn collect_contracts_and_variables(ast: &Value, data: &mut DiagramData) -> Result<()> {
+fn collect_contracts_and_variables(
+    ast: &Value,
+    data: &mut DiagramData,
+    contract_ids: &HashMap<i64, &str>,
+) -> Result<()> {
     let nodes = ast["nodes"].as_array().with_context(|| "nodes is not an array")?;
 
+    // Index every variable declaration so identifiers can be typed via `referencedDeclaration`
+    index_declaration_types(ast, &mut data.declaration_types);
+
+    // File-level `using L for T;` directives apply to every contract in the file
+    let file_using_for: Vec<(String, String)> = nodes
+        .iter()
+        .filter(|node| node["nodeType"].as_str() == Some("UsingForDirective"))
+        .filter_map(parse_using_for)
+        .collect();
+
+    // File-level constants are shared by every contract and rendered as one note
+    for node in nodes {
+        if node["nodeType"].as_str() == Some("VariableDeclaration")
+            && node["constant"].as_bool() == Some(true)
+        {
+            let name = node["name"].as_str().unwrap_or("unknown").to_string();
+            data.constants.push((name, describe_expression(&node["value"])));
+        }
+    }
+
+    let source_file = ast["absolutePath"].as_str().unwrap_or("unknown");
+    let (mut contract_count, mut function_count) = (0, 0);
+
     for node in nodes {
         if node["nodeType"].as_str() == Some("ContractDefinition") {
             let contract_name = node["name"].as_str().unwrap_or("Unknown").to_string();
+            if node.get("name").is_none() {
+                log::warn!("Contract definition without a name in {}", source_file);
+            }
 
             data.participants.insert(contract_name.clone());
 
             // Create contract info
             let mut contract_info = ContractInfo {
                 name: contract_name.clone(),
-                contract_type: node["contractKind"].as_str().unwrap_or("contract").to_string(),
-                source_file: ast["absolutePath"].as_str().unwrap_or("unknown").to_string(),
+                contract_type: if node["abstract"].as_bool() == Some(true) {
+                    "abstract".to_string()
+                } else {
+                    node["contractKind"].as_str().unwrap_or("contract").to_string()
+                },
+                source_file: source_file.to_string(),
+                notice: natspec_notice(&node["documentation"]),
                 ..Default::default()
             };
 
-            // Check inheritance
+            // Apply library bindings declared at file level; the "uses" relationship is only
+            // added once the contract actually calls a bound function
+            for binding in &file_using_for {
+                if binding.1 != contract_name {
+                    contract_info.using_for.push(binding.clone());
+                }
+            }
+
+            // Check inheritance; the `is` order determines the linearization
             if let Some(base_contracts) = node["baseContracts"].as_array() {
-                for base in base_contracts {
+                for (order, base) in base_contracts.iter().enumerate() {
                     if let Some(base_name) = base
                         .get("baseName")
                         .and_then(|bn| bn.get("name"))
                         .and_then(|n| n.as_str())
                     {
                         contract_info.inherits_from.push(base_name.to_string());
+                        if let Some(arguments) = base["arguments"].as_array() {
+                            contract_info.base_arguments.insert(
+                                base_name.to_string(),
+                                arguments.iter().map(describe_expression).collect(),
+                            );
+                        }
                         data.contract_relationships.push(ContractRelationship {
                             source: contract_name.clone(),
                             target: base_name.to_string(),
                             relation_type: "inherits".to_string(),
+                            order: Some(order),
                         });
                     }
                 }
             }
 
+            // solc's C3 linearization, most derived first, without the contract itself
+            contract_info.linearized_bases = node["linearizedBaseContracts"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .skip(1)
+                .filter_map(|id| Some(contract_ids.get(&id.as_i64()?)?.to_string()))
+                .collect();
+
             // Collect events and state variables
             if let Some(contract_nodes) = node["nodes"].as_array() {
                 for contract_node in contract_nodes {
                     let node_type = contract_node["nodeType"].as_str().unwrap_or("");
 
                     match node_type {
+                        "UsingForDirective" => {
+                            if let Some(binding) = parse_using_for(contract_node) {
+                                bind_library(data, &mut contract_info, binding);
+                            }
+                        }
                         "EventDefinition" => {
                             let event_name = contract_node["name"]
                                 .as_str()
                                 .unwrap_or("UnknownEvent")
                                 .to_string();
                             data.events.push((contract_name.clone(), event_name.clone()));
+                            contract_info.event_parameters.insert(
+                                event_name.clone(),
+                                extract_parameters(&contract_node["parameters"]),
+                            );
                             contract_info.events.push(event_name);
                         }
+                        "ErrorDefinition" => {
+                            let error_name = contract_node["name"]
+                                .as_str()
+                                .unwrap_or("UnknownError")
+                                .to_string();
+                            data.errors.push((contract_name.clone(), error_name.clone()));
+                            contract_info.error_parameters.insert(
+                                error_name.clone(),
+                                extract_parameters(&contract_node["parameters"]),
+                            );
+                            contract_info.errors.push(error_name);
+                        }
+                        "FunctionDefinition" => {
+                            if let Some(function_name) = function_display_name(contract_node) {
+                                contract_info
+                                    .function_details
+                                    .push(function_info(contract_node, &function_name));
+                                contract_info.functions.push(function_name);
+                            }
+                        }
                         "VariableDeclaration" => {
                             let var_name =
                                 contract_node["name"].as_str().unwrap_or("unknown").to_string();
@@ -116,14 +478,26 @@ fn collect_contracts_and_variables(ast: &Value, data: &mut DiagramData) -> Resul
 
                             contract_info.variables.push((var_name.clone(), var_type.clone()));
 
+                            // Contract handles stored in a mapping reference the value type
+                            let mapped_contract = mapping_value_type(&contract_node["typeName"])
+                                .and_then(|value_type| contract_type_name(value_type, data));
+
                             // Check if this creates a relationship with another contract
-                            if data.participants.contains(&var_type)
+                            if let Some(target) = mapped_contract {
+                                data.contract_relationships.push(ContractRelationship {
+                                    source: contract_name.clone(),
+                                    target,
+                                    relation_type: "references".to_string(),
+                                    order: None,
+                                });
+                            } else if data.participants.contains(&var_type)
                                 || var_type.to_lowercase().contains("address")
                             {
                                 data.contract_relationships.push(ContractRelationship {
                                     source: contract_name.clone(),
                                     target: var_type.clone(),
                                     relation_type: "references".to_string(),
+                                    order: None,
                                 });
                             }
                         }
@@ -133,156 +507,506 @@ fn collect_contracts_and_variables(ast: &Value, data: &mut DiagramData) -> Resul
             }
 
             // Store the contract info
+            contract_count += 1;
+            function_count += contract_info.functions.len();
             data.contracts.insert(contract_name, contract_info);
+        } else if node["nodeType"].as_str() == Some("FunctionDefinition") {
+            // Free functions from every file share one synthetic lifeline
+            let Some(function_name) = function_display_name(node) else {
+                continue;
+            };
+            data.participants.insert(FREE_FUNCTIONS.to_string());
+            let free_functions =
+                data.contracts.entry(FREE_FUNCTIONS.to_string()).or_insert_with(|| ContractInfo {
+                    name: FREE_FUNCTIONS.to_string(),
+                    contract_type: "free functions".to_string(),
+                    source_file: source_file.to_string(),
+                    ..Default::default()
+                });
+            free_functions.function_details.push(function_info(node, &function_name));
+            free_functions.functions.push(function_name);
+            function_count += 1;
         }
     }
 
+    log::info!(
+        "Found {} contracts and {} functions in {}",
+        contract_count,
+        function_count,
+        source_file
+    );
+
     Ok(())
 }
 
+/// Innermost value type of a (possibly nested) mapping type name
+fn mapping_value_type(type_node: &Value) -> Option<&Value> {
+    if type_node["nodeType"].as_str() != Some("Mapping") {
+        return None;
+    }
+    let value_type = &type_node["valueType"];
+    mapping_value_type(value_type).or(Some(value_type))
+}
+
+/// Name of the contract or interface a type name refers to, if it is one
+///
+/// Contracts declared later in the source are not participants yet, so the `contract `
+/// type string is checked as well.
+fn contract_type_name(type_node: &Value, data: &DiagramData) -> Option<String> {
+    let name = extract_type_name(type_node);
+    let is_contract = type_node["typeDescriptions"]["typeString"]
+        .as_str()
+        .is_some_and(|type_string| type_string.starts_with("contract "));
+    (is_contract || data.participants.contains(&name)).then_some(name)
+}
+
 /// Process functions and extract interactions
-fn process_functions_and_interactions(ast: &Value, data: &mut DiagramData, show_storage_updates: bool) -> Result<()> {
+fn process_functions_and_interactions(
+    ast: &Value,
+    data: &mut DiagramData,
+    config: &Config,
+    functions: &FunctionIndex,
+) -> Result<()> {
     let nodes = ast["nodes"].as_array().with_context(|| "nodes is not an array")?;
 
     for node in nodes {
         if node["nodeType"].as_str() == Some("ContractDefinition") {
             let contract_name = node["name"].as_str().unwrap_or("Unknown").to_string();
+            if !config.contracts.is_empty() && !config.contracts.contains(&contract_name) {
+                continue;
+            }
 
-            // Process functions
-            if let Some(contract_nodes) = node["nodes"].as_array() {
-                for contract_node in contract_nodes {
-                    if contract_node["nodeType"].as_str() == Some("FunctionDefinition") {
-                        let function_name = if let Some(name) = contract_node["name"].as_str() {
-                            if name.is_empty()
-                                && contract_node["kind"].as_str() == Some("constructor")
-                            {
-                                "constructor".to_string()
-                            } else {
-                                name.to_string()
-                            }
-                        } else {
-                            continue;
-                        };
+            // Interfaces have no bodies to enter
+            if node["contractKind"].as_str() == Some("interface") {
+                continue;
+            }
 
-                        // Store function info
-                        if let Some(contract_info) = data.contracts.get_mut(&contract_name) {
-                            contract_info.functions.push(function_name.clone());
-                        }
+            // Process functions
+            for contract_node in node["nodes"].as_array().into_iter().flatten() {
+                if contract_node["nodeType"].as_str() == Some("FunctionDefinition") {
+                    process_entrypoint(
+                        &contract_name,
+                        contract_node,
+                        None,
+                        data,
+                        config,
+                        functions,
+                    );
+                }
+            }
 
-                        // Add interaction from user to public/external functions
-                        let visibility = contract_node["visibility"].as_str().unwrap_or("");
-                        if visibility == "public" || visibility == "external" {
-                            // Extract function parameters
-                            let mut params = Vec::new();
-                            let mut param_types = Vec::new();
-
-                            if let Some(parameters) = contract_node
-                                .get("parameters")
-                                .and_then(|p| p.get("parameters"))
-                                .and_then(|p| p.as_array())
-                            {
-                                for param in parameters {
-                                    let param_name =
-                                        param["name"].as_str().unwrap_or("").to_string();
-
-                                    // Extract parameter type
-                                    let mut param_type = "unknown".to_string();
-                                    if param.get("typeName").is_some() {
-                                        param_type = extract_type_name(&param["typeName"]);
-                                    }
+            // Inherited public functions are entrypoints of the derived contract too
+            let inherited = data
+                .contracts
+                .get(&contract_name)
+                .map(|info| info.inherited_functions.clone())
+                .unwrap_or_default();
+            for (function, base) in inherited {
+                let function_key = format!("{}.{}", base, function);
+                if let Some(function_node) = functions.by_name.get(&function_key) {
+                    process_entrypoint(
+                        &contract_name,
+                        function_node,
+                        Some(&base),
+                        data,
+                        config,
+                        functions,
+                    );
+                }
+            }
+        }
+    }
 
-                                    // Try to get type from typeDescriptions if still unknown
-                                    if param_type == "unknown" {
-                                        if let Some(type_desc) = param.get("typeDescriptions") {
-                                            if let Some(type_str) = type_desc
-                                                .get("typeString")
-                                                .and_then(|ts| ts.as_str())
-                                            {
-                                                param_type = type_str.to_string();
-                                            }
-                                        }
-                                    }
+    Ok(())
+}
 
-                                    if !param_name.is_empty() {
-                                        params.push(param_name);
-                                        param_types.push(param_type);
-                                    }
-                                }
-                            }
+/// Render the entrypoint arrow, notes and body of a function callable on a contract
+///
+/// `inherited_from` names the base defining the function when the contract inherits it.
+fn process_entrypoint(
+    contract_name: &str,
+    contract_node: &Value,
+    inherited_from: Option<&str>,
+    data: &mut DiagramData,
+    config: &Config,
+    functions: &FunctionIndex,
+) {
+    let Some(function_name) = function_display_name(contract_node) else {
+        return;
+    };
+
+    // Unimplemented functions have no body to enter
+    if contract_node["implemented"].as_bool() == Some(false) {
+        return;
+    }
 
-                            // Create message with parameter types
-                            let message = if params.is_empty() {
-                                format!("{}()", function_name)
-                            } else {
-                                let param_type_str: Vec<String> = params
-                                    .iter()
-                                    .zip(param_types.iter())
-                                    .map(|(name, typ)| format!("{}: {}", name, typ))
-                                    .collect();
-                                format!("{}({})", function_name, param_type_str.join(", "))
-                            };
+    // Add interaction from user to entrypoint functions; constructors run
+    // at deployment whatever their visibility (`internal` when abstract)
+    let visibility = contract_node["visibility"].as_str().unwrap_or("");
+    let is_constructor = contract_node["kind"].as_str() == Some("constructor");
+    if !is_constructor && !config.include_visibilities.iter().any(|v| v == visibility) {
+        return;
+    }
 
-                            // Add note about function purpose
-                            let function_purpose = get_function_purpose(&function_name);
-                            if let Some(purpose) = function_purpose {
-                                data.user_interactions.push(format!(
-                                    "Note over User,{}: {}",
-                                    contract_name, purpose
-                                ));
-                            }
+    // Extract function parameters
+    let mut params = Vec::new();
+    let mut param_types = Vec::new();
 
-                            // Add user interaction
-                            data.user_interactions
-                                .push(format!("User->>+{}: {}", contract_name, message));
+    if let Some(parameters) =
+        contract_node.get("parameters").and_then(|p| p.get("parameters")).and_then(|p| p.as_array())
+    {
+        for param in parameters {
+            let param_name = param["name"].as_str().unwrap_or("").to_string();
 
-                            // Process function body for internal interactions
-                            if let Some(body) = contract_node.get("body") {
-                                if let Some(statements) =
-                                    body.get("statements").and_then(|s| s.as_array())
-                                {
-                                    let function_key =
-                                        format!("{}.{}", contract_name, function_name);
-                                    let body_interactions = process_function_body(
-                                        &contract_name,
-                                        &function_name,
-                                        statements,
-                                        data,
-                                        show_storage_updates,
-                                    );
-                                    data.contract_interactions
-                                        .insert(function_key, body_interactions);
-                                }
-                            }
+            // Extract parameter type
+            let mut param_type = "unknown".to_string();
+            if param.get("typeName").is_some() {
+                param_type = extract_type_name(&param["typeName"]);
+            }
 
-                            // Add return value
-                            let return_type = extract_return_type(contract_node);
-                            if let Some(ret_type) = return_type {
-                                data.user_interactions.push(format!(
-                                    "{}-->>-User: return {}",
-                                    contract_name, ret_type
-                                ));
-                            } else {
-                                // Check for view/pure functions
-                                let state_mutability =
-                                    contract_node["stateMutability"].as_str().unwrap_or("");
-                                if state_mutability == "view" || state_mutability == "pure" {
-                                    data.user_interactions.push(format!(
-                                        "{}-->>-User: return (view function)",
-                                        contract_name
-                                    ));
-                                } else {
-                                    data.user_interactions
-                                        .push(format!("{}-->>-User: return", contract_name));
-                                }
-                            }
-                        }
+            // Try to get type from typeDescriptions if still unknown
+            if param_type == "unknown" {
+                if let Some(type_desc) = param.get("typeDescriptions") {
+                    if let Some(type_str) = type_desc.get("typeString").and_then(|ts| ts.as_str()) {
+                        param_type = type_str.to_string();
                     }
                 }
             }
+
+            if !param_name.is_empty() {
+                params.push(param_name);
+                param_types.push(param_type);
+            }
         }
     }
 
-    Ok(())
+    // Create message with parameter types
+    let message = if params.is_empty() {
+        format!("{}()", function_name)
+    } else {
+        let param_type_str: Vec<String> = params
+            .iter()
+            .zip(param_types.iter())
+            .map(|(name, typ)| format!("{}: {}", name, typ))
+            .collect();
+        format!("{}({})", function_name, param_type_str.join(", "))
+    };
+
+    // Special entrypoints invoked by deployment, plain ETH sends and
+    // unknown selectors
+    let message = match contract_node["kind"].as_str() {
+        Some("constructor") => format!("deploy / {}", message),
+        Some("receive") => "receive() [ETH transfer]".to_string(),
+        Some("fallback") if params.is_empty() => "fallback(bytes)".to_string(),
+        Some("function") if config.show_selectors => {
+            match entrypoint_selector(contract_node, &function_name, data) {
+                Some(selector) => format!("{} [{}]", message, selector),
+                None => message,
+            }
+        }
+        _ => message,
+    };
+
+    // Payable entrypoints carry ETH; `receive` already says so
+    let is_payable = contract_node["stateMutability"].as_str() == Some("payable")
+        && contract_node["kind"].as_str() != Some("receive");
+    let message = if is_payable { format!("{} [payable]", message) } else { message };
+    let message = match inheritance_tags(contract_node, &function_name) {
+        Some(tags) => format!("{} {}", message, tags),
+        None => message,
+    };
+    let message = match inherited_from {
+        Some(base) => format!("{} (inherited from {})", message, base),
+        None => message,
+    };
+
+    // Route the call from the actor mapped to this function
+    let actor = resolve_actor(&function_name, &config.actor_map);
+    data.participants.insert(actor.clone());
+
+    // Add note about function purpose, preferring the NatSpec `@notice`
+    let function_purpose = natspec_notice(&contract_node["documentation"])
+        .or_else(|| get_function_purpose(&function_name, &config.function_purposes));
+    if let Some(purpose) = function_purpose {
+        data.user_interactions.push(format!("Note over {},{}: {}", actor, contract_name, purpose));
+    }
+
+    let entrypoint_note = match contract_node["kind"].as_str() {
+        Some("receive") => Some("receive handles plain ETH transfers"),
+        Some("fallback") => Some("fallback handles unmatched calls"),
+        _ => None,
+    };
+    if let Some(note) = entrypoint_note {
+        data.user_interactions.push(format!("Note over {},{}: {}", actor, contract_name, note));
+    }
+    if is_payable {
+        let note = if uses_msg_value(&contract_node["body"]) {
+            "sends ETH (uses msg.value)"
+        } else {
+            "sends ETH"
+        };
+        data.user_interactions.push(format!("Note over {},{}: {}", actor, contract_name, note));
+    }
+
+    // Add notes for modifiers gating the function
+    for modifier in modifier_names(contract_node, data) {
+        let note = match get_modifier_purpose(&modifier) {
+            Some(purpose) => format!("requires {} ({})", modifier, purpose),
+            None => format!("requires {}", modifier),
+        };
+        data.user_interactions.push(format!("Note over {},{}: {}", actor, contract_name, note));
+    }
+
+    // Add user interaction; compact view/pure calls carry their return
+    let state_mutability = contract_node["stateMutability"].as_str().unwrap_or("");
+    let is_view = state_mutability == "view" || state_mutability == "pure";
+    let return_type = extract_return_type(contract_node);
+    let compact = config.compact_view_returns && is_view;
+    let arrow = if compact {
+        let returns = match &return_type {
+            Some(ret_type) => format!(": returns {}", ret_type),
+            None => String::new(),
+        };
+        format!("{}->>{}: {}{}", actor, contract_name, message, returns)
+    } else {
+        format!("{}->>+{}: {}", actor, contract_name, message)
+    };
+    // Remember which body belongs after the arrow when bodies are inlined
+    data.entrypoint_keys.insert(arrow.clone(), format!("{}.{}", contract_name, function_name));
+    data.user_interactions.push(arrow);
+
+    // Process function body for internal interactions
+    if let Some(body) = contract_node.get("body") {
+        if let Some(statements) = body.get("statements").and_then(|s| s.as_array()) {
+            let function_key = format!("{}.{}", contract_name, function_name);
+            let mut context = BodyContext {
+                config,
+                functions,
+                visited: HashSet::from([definition_id(contract_node)]),
+                depth: 0,
+                external_call_made: false,
+                reentrancy_flagged: false,
+            };
+            // Base constructors run before the constructor body
+            let mut body_interactions =
+                render_base_constructor_calls(contract_name, &function_name, contract_node, data);
+            body_interactions.extend(process_function_body(
+                contract_name,
+                &function_name,
+                statements,
+                data,
+                &mut context,
+            ));
+            data.contract_interactions.insert(function_key, body_interactions);
+        }
+    } else {
+        log::warn!("{}.{} has no body; its calls are not shown", contract_name, function_name);
+    }
+
+    // Add return value
+    if compact {
+        // Already folded into the call arrow
+    } else if let Some(ret_type) = return_type {
+        data.user_interactions
+            .push(format!("{}-->>-{}: return {}", contract_name, actor, ret_type));
+    } else {
+        // Check for view/pure functions
+        if is_view {
+            data.user_interactions
+                .push(format!("{}-->>-{}: return (view function)", contract_name, actor));
+        } else {
+            data.user_interactions.push(format!("{}-->>-{}: return", contract_name, actor));
+        }
+    }
+}
+
+/// Render the base constructors a constructor invokes, e.g. `Vault->>Base: constructor(owner)`
+///
+/// Calls follow the contract's `is` list, the order in which Solidity runs them.
+fn render_base_constructor_calls(
+    contract_name: &str,
+    function_name: &str,
+    function: &Value,
+    data: &mut DiagramData,
+) -> Vec<String> {
+    if function["kind"].as_str() != Some("constructor") {
+        return Vec::new();
+    }
+    let bases = data
+        .contracts
+        .get(contract_name)
+        .map(|info| info.inherits_from.clone())
+        .unwrap_or_default();
+
+    let invocations: Vec<(&str, &Value)> = function["modifiers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|modifier| {
+            let name = modifier["modifierName"]["name"].as_str()?;
+            let is_base = modifier["kind"].as_str() == Some("baseConstructorSpecifier")
+                || bases.iter().any(|base| base == name);
+            is_base.then_some((name, modifier))
+        })
+        .sorted_by_key(|(name, _)| bases.iter().position(|base| base == name).unwrap_or(usize::MAX))
+        .collect();
+
+    let mut lines = Vec::new();
+    for (base, invocation) in invocations {
+        data.participants.insert(base.to_string());
+        let arguments = format_arguments(invocation, data);
+        lines.push(format!("{}->>{}: constructor({})", contract_name, base, arguments));
+        record_call(data, contract_name, function_name, base, "constructor");
+    }
+    lines
+}
+
+/// Name of the variable an assignment target is rooted at, e.g. `balances` in `balances[a].x`
+fn storage_root(target: &Value) -> Option<&str> {
+    match target["nodeType"].as_str()? {
+        "Identifier" => target["name"].as_str(),
+        "IndexAccess" => storage_root(&target["baseExpression"]),
+        "MemberAccess" => storage_root(&target["expression"]),
+        _ => None,
+    }
+}
+
+/// Check whether a name is a state variable of a contract or one of its bases
+fn is_state_variable(data: &DiagramData, contract_name: &str, name: &str) -> bool {
+    lineage(data, contract_name)
+        .iter()
+        .any(|info| info.variables.iter().any(|(var, _)| var == name))
+}
+
+/// A contract followed by all of its (transitive) base contracts that are known
+///
+/// Follows solc's `linearizedBaseContracts` when the AST has it; otherwise bases are
+/// walked depth-first from the last (most derived) entry of each `is` list.
+fn lineage<'a>(data: &'a DiagramData, contract_name: &'a str) -> Vec<&'a ContractInfo> {
+    if let Some(info) = data.contracts.get(contract_name) {
+        if !info.linearized_bases.is_empty() {
+            let bases = info.linearized_bases.iter().filter_map(|base| data.contracts.get(base));
+            return std::iter::once(info).chain(bases).collect();
+        }
+    }
+
+    let mut pending = vec![contract_name];
+    let mut seen = HashSet::new();
+    let mut contracts = Vec::new();
+
+    while let Some(current) = pending.pop() {
+        if !seen.insert(current) {
+            continue;
+        }
+        if let Some(info) = data.contracts.get(current) {
+            contracts.push(info);
+            pending.extend(info.inherits_from.iter().map(String::as_str));
+        }
+    }
+
+    contracts
+}
+
+/// Find the declared parameters of an event visible from a contract
+///
+/// Only events declared on the contract or its bases are considered.
+fn find_event_parameters<'a>(
+    data: &'a DiagramData,
+    contract_name: &'a str,
+    event_name: &str,
+) -> Option<&'a Vec<Parameter>> {
+    lineage(data, contract_name).into_iter().find_map(|info| info.event_parameters.get(event_name))
+}
+
+/// Callable signature of a function definition
+fn function_info(function: &Value, name: &str) -> FunctionInfo {
+    FunctionInfo {
+        name: name.to_string(),
+        kind: function["kind"].as_str().unwrap_or("function").to_string(),
+        visibility: function["visibility"].as_str().unwrap_or("").to_string(),
+        state_mutability: function["stateMutability"].as_str().unwrap_or("nonpayable").to_string(),
+        parameters: extract_parameters(&function["parameters"]),
+        returns: extract_parameters(&function["returnParameters"]),
+    }
+}
+
+/// Name of a function definition, using its kind for unnamed constructor/receive/fallback
+fn function_display_name(function: &Value) -> Option<String> {
+    let name = function["name"].as_str()?;
+    match function["kind"].as_str() {
+        Some(kind @ ("constructor" | "receive" | "fallback")) if name.is_empty() => {
+            Some(kind.to_string())
+        }
+        _ => Some(name.to_string()),
+    }
+}
+
+/// 4-byte selector of a function, preferring the one solc recorded in the AST
+///
+/// Without one, the selector is computed from the signature unless a parameter type has no
+/// known ABI form.
+fn entrypoint_selector(function: &Value, name: &str, data: &DiagramData) -> Option<String> {
+    if let Some(selector) = function["functionSelector"].as_str() {
+        return Some(format!("0x{}", selector));
+    }
+
+    let abi_types: Vec<String> = function["parameters"]["parameters"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|param| {
+            let type_name = match param["typeDescriptions"]["typeString"].as_str() {
+                Some(type_string) => type_string.to_string(),
+                None => extract_type_name(&param["typeName"]),
+            };
+            canonical_abi_type(&type_name, &data.contracts)
+        })
+        .collect::<Option<_>>()?;
+
+    Some(function_selector(&format!("{}({})", name, abi_types.join(","))))
+}
+
+/// Describe where a function sits in the inheritance chain, e.g. `(overrides Base.deposit)`
+///
+/// Explicit `override(A, B)` lists name each base; the implicit form is just `(override)`.
+fn inheritance_tags(function: &Value, function_name: &str) -> Option<String> {
+    let mut tags = Vec::new();
+    if function["virtual"].as_bool() == Some(true) {
+        tags.push("(virtual)".to_string());
+    }
+    if function["overrides"].is_object() {
+        let bases: Vec<&str> = function["overrides"]["overrides"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|base| base["name"].as_str())
+            .collect();
+        if bases.is_empty() {
+            tags.push("(override)".to_string());
+        } else {
+            let targets = bases.iter().map(|base| format!("{}.{}", base, function_name)).join(", ");
+            tags.push(format!("(overrides {})", targets));
+        }
+    }
+
+    (!tags.is_empty()).then(|| tags.join(" "))
+}
+
+/// Names of the modifiers invoked on a function, skipping base constructor calls
+///
+/// Older ASTs carry no invocation `kind`, so names of known contracts are skipped too.
+fn modifier_names(function: &Value, data: &DiagramData) -> Vec<String> {
+    function["modifiers"]
+        .as_array()
+        .map(|modifiers| {
+            modifiers
+                .iter()
+                .filter(|m| m["kind"].as_str() != Some("baseConstructorSpecifier"))
+                .filter_map(|m| m["modifierName"]["name"].as_str().map(String::from))
+                .filter(|name| !data.contracts.contains_key(name))
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
 /// Process a function body and extract interactions
@@ -290,13 +1014,106 @@ fn process_function_body(
     contract_name: &str,
     function_name: &str,
     statements: &[Value],
+    data: &mut DiagramData,
+    context: &mut BodyContext,
+) -> Vec<String> {
+    let config = context.config;
+
+    // Bail out on pathologically deep nesting instead of overflowing the stack
+    if context.depth >= config.max_body_depth {
+        return vec![format!(
+            "Note over {}: {} (max depth reached)",
+            contract_name, function_name
+        )];
+    }
+    context.depth += 1;
+    let interactions = process_statements(contract_name, function_name, statements, data, context);
+    context.depth -= 1;
+
+    interactions
+}
+
+/// Bounds of a `for` loop over `var_name` when its limit is a literal or a state variable
+///
+/// `i < 10` gives `(0..10)` and `i <= max` gives `(0..=max)`, starting from the declared
+/// initial value; `i < users.length` over a state array gives `in users`.
+fn loop_bounds(
+    statement: &Value,
+    var_name: &str,
+    contract_name: &str,
     data: &DiagramData,
-    show_storage_updates: bool,
+) -> Option<String> {
+    let condition = &statement["condition"];
+    if condition["nodeType"].as_str() != Some("BinaryOperation")
+        || condition["leftExpression"]["name"].as_str() != Some(var_name)
+    {
+        return None;
+    }
+    let range = match condition["operator"].as_str() {
+        Some("<") => "..",
+        Some("<=") => "..=",
+        _ => return None,
+    };
+
+    let bound = &condition["rightExpression"];
+    let is_state_identifier = |expression: &Value| {
+        expression["nodeType"].as_str() == Some("Identifier")
+            && expression["name"]
+                .as_str()
+                .is_some_and(|name| is_state_variable(data, contract_name, name))
+    };
+    if bound["nodeType"].as_str() == Some("MemberAccess")
+        && bound["memberName"].as_str() == Some("length")
+        && range == ".."
+        && is_state_identifier(&bound["expression"])
+    {
+        return Some(format!("in {}", describe_expression(&bound["expression"])));
+    }
+    if bound["nodeType"].as_str() != Some("Literal") && !is_state_identifier(bound) {
+        return None;
+    }
+
+    let start = match statement["initializationExpression"].get("initialValue") {
+        Some(initial) if initial.is_object() => describe_expression(initial),
+        _ => "0".to_string(),
+    };
+    Some(format!("({}{}{})", start, range, describe_expression(bound)))
+}
+
+/// Extract the indented interactions of a branch body, either a block or a single statement
+fn process_branch(
+    contract_name: &str,
+    function_name: &str,
+    body: &Value,
+    data: &mut DiagramData,
+    context: &mut BodyContext,
+) -> Vec<String> {
+    let statements = match body["statements"].as_array() {
+        Some(statements) => statements.as_slice(),
+        None if body.get("nodeType").is_some() => std::slice::from_ref(body),
+        None => return Vec::new(),
+    };
+
+    process_function_body(contract_name, function_name, statements, data, context)
+        .into_iter()
+        .map(|line| format!("    {}", line))
+        .collect()
+}
+
+/// Extract interactions from a list of statements
+fn process_statements(
+    contract_name: &str,
+    function_name: &str,
+    statements: &[Value],
+    data: &mut DiagramData,
+    context: &mut BodyContext,
 ) -> Vec<String> {
+    let config = context.config;
     let mut interactions = Vec::new();
 
     for statement in statements {
         let node_type = statement["nodeType"].as_str().unwrap_or("");
+        let first_line = interactions.len();
 
         match node_type {
             "ForStatement" => {
@@ -319,11 +1136,25 @@ fn process_function_body(
                                             format!("For each {}: {}", var_name, loop_var_type);
                                     }
                                 }
+
+                                // Statically known bounds say how often the loop runs
+                                if let Some(bounds) =
+                                    loop_bounds(statement, var_name, contract_name, data)
+                                {
+                                    loop_description = format!("for each {} {}", var_name, bounds);
+                                }
                             }
                         }
                     }
                 }
 
+                // Without a declared loop variable, describe the condition instead
+                if statement["initializationExpression"]["declarations"].is_null() {
+                    if let Some(condition) = statement.get("condition").filter(|c| c.is_object()) {
+                        loop_description = format!("while {}", describe_expression(condition));
+                    }
+                }
+
                 // Start loop block
                 interactions.push(format!("loop {}", loop_description));
 
@@ -331,15 +1162,25 @@ fn process_function_body(
                 if let Some(body) = statement.get("body") {
                     if let Some(body_statements) = body.get("statements").and_then(|s| s.as_array())
                     {
-                        let loop_body =
-                            process_function_body(contract_name, function_name, body_statements, data, show_storage_updates);
+                        let loop_body = process_function_body(
+                            contract_name,
+                            function_name,
+                            body_statements,
+                            data,
+                            context,
+                        );
                         for line in loop_body {
                             interactions.push(format!("    {}", line));
                         }
                     } else if body.get("nodeType").is_some() {
                         // Handle single statement body
-                        let loop_body =
-                            process_function_body(contract_name, function_name, &[body.clone()], data, show_storage_updates);
+                        let loop_body = process_function_body(
+                            contract_name,
+                            function_name,
+                            std::slice::from_ref(body),
+                            data,
+                            context,
+                        );
                         for line in loop_body {
                             interactions.push(format!("    {}", line));
                         }
@@ -349,83 +1190,52 @@ fn process_function_body(
                 // End loop block
                 interactions.push("end".to_string());
             }
+            "WhileStatement" | "DoWhileStatement" => {
+                let condition = describe_expression(&statement["condition"]);
+                interactions.push(format!("loop while {}", condition));
+                interactions.extend(process_branch(
+                    contract_name,
+                    function_name,
+                    &statement["body"],
+                    data,
+                    context,
+                ));
+                interactions.push("end".to_string());
+            }
             "IfStatement" => {
-                // Handle if statements
-                let mut condition_description = "if condition".to_string();
-
-                if let Some(condition) = statement.get("condition") {
-                    if condition["nodeType"].as_str() == Some("BinaryOperation") {
-                        if let (Some(left), Some(right), Some(op)) = (
-                            condition.get("leftExpression"),
-                            condition.get("rightExpression"),
-                            condition.get("operator").and_then(|o| o.as_str()),
-                        ) {
-                            if let Some(left_name) = left.get("name").and_then(|n| n.as_str()) {
-                                // Fix the temporary reference issue
-                                if let Some(right_val_str) =
-                                    right.get("value").and_then(|v| v.as_str())
-                                {
-                                    condition_description =
-                                        format!("if {} {} {}", left_name, op, right_val_str);
-                                } else if let Some(right_val_num) =
-                                    right.get("value").and_then(|v| v.as_u64())
-                                {
-                                    condition_description =
-                                        format!("if {} {} {}", left_name, op, right_val_num);
-                                }
-                            }
-                        }
-                    }
-                }
-
-                interactions.push(format!("alt {}", condition_description));
-
-                // Process true body
-                if let Some(true_body) = statement.get("trueBody") {
-                    if let Some(true_statements) =
-                        true_body.get("statements").and_then(|s| s.as_array())
-                    {
-                        let body =
-                            process_function_body(contract_name, function_name, true_statements, data, show_storage_updates);
-                        for line in body {
-                            interactions.push(format!("    {}", line));
-                        }
-                    } else if true_body.get("nodeType").is_some() {
-                        let body = process_function_body(contract_name, function_name, &[
-                            true_body.clone(),
-                        ], data, show_storage_updates);
-                        for line in body {
-                            interactions.push(format!("    {}", line));
-                        }
-                    }
-                }
-
-                // Process false body
-                if let Some(false_body) = statement.get("falseBody") {
-                    if false_body.is_object() {
-                        interactions.push("else".to_string());
-
-                        if let Some(false_statements) =
-                            false_body.get("statements").and_then(|s| s.as_array())
-                        {
-                            let body = process_function_body(
+                // Handle if statements; `else if` chains stay branches of one `alt` block
+                let condition_label = |branch: &Value| match branch.get("condition") {
+                    Some(condition) => format!("if {}", describe_expression(condition)),
+                    None => "if condition".to_string(),
+                };
+
+                let mut branch = statement;
+                interactions.push(format!("alt {}", condition_label(branch)));
+                loop {
+                    interactions.extend(process_branch(
+                        contract_name,
+                        function_name,
+                        &branch["trueBody"],
+                        data,
+                        context,
+                    ));
+
+                    let false_body = &branch["falseBody"];
+                    if false_body["nodeType"].as_str() == Some("IfStatement") {
+                        interactions.push(format!("else {}", condition_label(false_body)));
+                        branch = false_body;
+                    } else {
+                        if false_body.is_object() {
+                            interactions.push("else".to_string());
+                            interactions.extend(process_branch(
                                 contract_name,
                                 function_name,
-                                false_statements,
+                                false_body,
                                 data,
-                                show_storage_updates,
-                            );
-                            for line in body {
-                                interactions.push(format!("    {}", line));
-                            }
-                        } else if false_body.get("nodeType").is_some() {
-                            let body = process_function_body(contract_name, function_name, &[
-                                false_body.clone(),
-                            ], data, show_storage_updates);
-                            for line in body {
-                                interactions.push(format!("    {}", line));
-                            }
+                                context,
+                            ));
                         }
+                        break;
                     }
                 }
 
@@ -436,42 +1246,30 @@ fn process_function_body(
                 if let Some(event_call) = statement.get("eventCall") {
                     if let Some(expression) = event_call.get("expression") {
                         if let Some(event_name) = expression.get("name").and_then(|n| n.as_str()) {
-                            let mut args = Vec::new();
-                            let mut args_with_types = Vec::new();
-
-                            if let Some(arguments) =
-                                event_call.get("arguments").and_then(|a| a.as_array())
-                            {
-                                for arg in arguments {
-                                    if arg["nodeType"].as_str() == Some("Identifier") {
-                                        if let Some(arg_name) =
-                                            arg.get("name").and_then(|n| n.as_str())
-                                        {
-                                            args.push(arg_name.to_string());
-                                            let arg_type = guess_type_from_name(arg_name);
-                                            args_with_types
-                                                .push(format!("{}: {}", arg_name, arg_type));
-                                        }
-                                    } else if arg["nodeType"].as_str() == Some("Literal") {
-                                        if let Some(value) = arg.get("value").map(|v| v.to_string())
-                                        {
-                                            args.push(value.clone());
-                                            let literal_type = get_literal_type(arg);
-                                            args_with_types
-                                                .push(format!("{}: {}", value, literal_type));
-                                        }
-                                    }
+                            // Prefer the declared parameters over guessing from the arguments; an
+                            // emit bound to an unknown declaration shows its arguments
+                            let declared = match expression["referencedDeclaration"].as_i64() {
+                                Some(id) => context
+                                    .functions
+                                    .events
+                                    .get(&id)
+                                    .map(|event| extract_parameters(&event["parameters"])),
+                                None => {
+                                    find_event_parameters(data, contract_name, event_name).cloned()
                                 }
-                            }
-
-                            let arg_str = if !args_with_types.is_empty() {
-                                args_with_types.join(", ")
-                            } else if !args.is_empty() {
-                                args.join(", ")
-                            } else {
-                                String::new()
+                            };
+                            let arg_str = match declared {
+                                Some(parameters) => parameters
+                                    .iter()
+                                    .map(|p| {
+                                        let tag = if p.indexed { " idx" } else { "" };
+                                        format!("{}: {}{}", p.name, p.param_type, tag)
+                                    })
+                                    .join(", "),
+                                None => format_arguments(event_call, data),
                             };
 
+                            data.participants.insert("Events".to_string());
                             interactions.push(format!(
                                 "{}->>Events: emit {}({})",
                                 contract_name, event_name, arg_str
@@ -480,409 +1278,1594 @@ fn process_function_body(
                     }
                 }
             }
+            "ExpressionStatement" if is_member_call_assignment(&statement["expression"]) => {
+                // `x = pool.get()` and `(x, y) = pool.getReserves()` render like declarations
+                let expression = &statement["expression"];
+                let left = &expression["leftHandSide"];
+                let targets: Vec<Value> = match left["nodeType"].as_str() {
+                    Some("TupleExpression") => left["components"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .map(|component| {
+                            if component.is_null() {
+                                Value::Null
+                            } else {
+                                serde_json::json!({ "name": describe_expression(component) })
+                            }
+                        })
+                        .collect(),
+                    _ => vec![serde_json::json!({ "name": describe_expression(left) })],
+                };
+                let declaration = serde_json::json!({
+                    "nodeType": "VariableDeclarationStatement",
+                    "declarations": targets,
+                    "initialValue": expression["rightHandSide"],
+                });
+                interactions.extend(process_statements(
+                    contract_name,
+                    function_name,
+                    &[declaration],
+                    data,
+                    context,
+                ));
+                let left = &expression["leftHandSide"];
+                let warning = reentrancy_warning(contract_name, left, &interactions, data, context);
+                interactions.extend(warning);
+                interactions.extend(storage_update_note(contract_name, expression, data, config));
+            }
             "ExpressionStatement" => {
                 // Handle function calls and assignments
                 if let Some(expression) = statement.get("expression") {
-                    // Handle assignments (storage updates)
+                    // Contract deployments, possibly assigned to a variable
+                    let deployment = if expression["nodeType"].as_str() == Some("Assignment") {
+                        &expression["rightHandSide"]
+                    } else {
+                        expression
+                    };
+                    interactions.extend(render_deployment(
+                        contract_name,
+                        function_name,
+                        deployment,
+                        data,
+                    ));
+
+                    // Low-level calls replace the generic member-call rendering below
+                    let low_level = render_low_level_call(
+                        contract_name,
+                        function_name,
+                        deployment,
+                        data,
+                        config,
+                    );
+                    let is_low_level = !low_level.is_empty();
+                    interactions.extend(low_level);
+
+                    // Internal calls to functions of the same contract
+                    interactions.extend(render_internal_call(
+                        contract_name,
+                        function_name,
+                        deployment,
+                        data,
+                        context,
+                    ));
+
+                    // Handle assignments to this contract's state variables (storage updates)
                     if expression["nodeType"].as_str() == Some("Assignment") {
-                        if let Some(left) = expression.get("leftHandSide") {
-                            // Look for state variable assignments (could be direct or through member access)
-                            let mut var_name = None;
-                            let mut is_state_var = false;
-                            
-                            // Direct state variable assignment
-                            if left["nodeType"].as_str() == Some("Identifier") {
-                                if let Some(name) = left.get("name").and_then(|n| n.as_str()) {
-                                    var_name = Some(name.to_string());
-                                    
-                                    // Check if this is a state variable by seeing if it's a contract variable
-                                    for (_contract_name, contract_info) in &data.contracts {
-                                        for (var, _) in &contract_info.variables {
-                                            if var == name {
-                                                is_state_var = true;
-                                                break;
-                                            }
-                                        }
-                                        if is_state_var {
-                                            break;
-                                        }
-                                    }
-                                }
-                            }
-                            // Member access (e.g., this.balance, mapping[key], etc.)
-                            else if left["nodeType"].as_str() == Some("MemberAccess") {
-                                if let Some(member_name) = left.get("memberName").and_then(|n| n.as_str()) {
-                                    var_name = Some(member_name.to_string());
-                                    
-                                    // Check if it's a state variable member
-                                    if let Some(expr) = left.get("expression") {
-                                        if expr["nodeType"].as_str() == Some("Identifier") {
-                                            if expr.get("name").and_then(|n| n.as_str()) == Some("this") {
-                                                is_state_var = true;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            // Indexed access (e.g., array[index], mapping[key])
-                            else if left["nodeType"].as_str() == Some("IndexAccess") {
-                                if let Some(base) = left.get("baseExpression") {
-                                    if base["nodeType"].as_str() == Some("Identifier") {
-                                        if let Some(name) = base.get("name").and_then(|n| n.as_str()) {
-                                            var_name = Some(format!("{}[index]", name));
-                                            
-                                            // Check if this is a state variable
-                                            for (_contract_name, contract_info) in &data.contracts {
-                                                for (var, _) in &contract_info.variables {
-                                                    if var == name {
-                                                        is_state_var = true;
-                                                        break;
-                                                    }
-                                                }
-                                                if is_state_var {
-                                                    break;
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            
-                            // If we have a state variable assignment, add it to interactions
-                            if let Some(name) = var_name {
-                                if is_state_var && show_storage_updates {
-                                    let operator = expression["operator"].as_str().unwrap_or("=");
-                                    
-                                    // Get the right side expression
-                                    let mut value_str = "new value".to_string();
-                                    if let Some(right) = expression.get("rightHandSide") {
-                                        if right["nodeType"].as_str() == Some("Identifier") {
-                                            if let Some(right_name) = right.get("name").and_then(|n| n.as_str()) {
-                                                value_str = right_name.to_string();
-                                            }
-                                        } else if right["nodeType"].as_str() == Some("Literal") {
-                                            if let Some(value) = right.get("value").map(|v| v.to_string()) {
-                                                value_str = value;
-                                            }
-                                        }
-                                    }
-                                    
-                                    interactions.push(format!(
-                                        "Note right of {}: Storage update: {} {} {}",
-                                        contract_name, name, operator, value_str
-                                    ));
-                                }
-                            }
+                        interactions.extend(reentrancy_warning(
+                            contract_name,
+                            &expression["leftHandSide"],
+                            &interactions,
+                            data,
+                            context,
+                        ));
+                        interactions.extend(storage_update_note(
+                            contract_name,
+                            expression,
+                            data,
+                            config,
+                        ));
+                    }
+                    // `delete x` and `array.push(x)` / `array.pop()` mutate storage in place
+                    else if let Some(mutation) = storage_mutation(contract_name, expression, data) {
+                        let target = match expression["nodeType"].as_str() {
+                            Some("UnaryOperation") => &expression["subExpression"],
+                            _ => &expression["expression"]["expression"],
+                        };
+                        interactions.extend(reentrancy_warning(
+                            contract_name,
+                            target,
+                            &interactions,
+                            data,
+                            context,
+                        ));
+                        if config.show_storage_updates {
+                            interactions.push(format!(
+                                "Note over {}: storage: {}",
+                                contract_name, mutation
+                            ));
                         }
                     }
+                    // Hashing and ABI encoding get a note; no built-in gets a lifeline
+                    else if is_notable_builtin_call(expression) {
+                        interactions.push(format!(
+                            "Note over {}: {}",
+                            contract_name,
+                            describe_expression(expression)
+                        ));
+                    }
                     // Handle function calls
-                    else if expression["nodeType"].as_str() == Some("FunctionCall") {
+                    else if expression["nodeType"].as_str() == Some("FunctionCall") && !is_low_level {
                         if let Some(call_expr) = expression.get("expression") {
                             if call_expr["nodeType"].as_str() == Some("MemberAccess") {
-                                let member_name =
-                                    call_expr["memberName"].as_str().unwrap_or("unknown");
-
-                                if let Some(base_expr) = call_expr.get("expression") {
-                                    if base_expr["nodeType"].as_str() == Some("Identifier") {
-                                        let target_name =
-                                            base_expr["name"].as_str().unwrap_or("Unknown");
-
-                                        // Extract arguments
-                                        let mut args = Vec::new();
-                                        let mut args_with_types = Vec::new();
-
-                                        if let Some(arguments) =
-                                            expression.get("arguments").and_then(|a| a.as_array())
-                                        {
-                                            for arg in arguments {
-                                                if arg["nodeType"].as_str() == Some("Identifier") {
-                                                    if let Some(arg_name) =
-                                                        arg.get("name").and_then(|n| n.as_str())
-                                                    {
-                                                        args.push(arg_name.to_string());
-                                                        let arg_type =
-                                                            guess_type_from_name(arg_name);
-                                                        args_with_types.push(format!(
-                                                            "{}: {}",
-                                                            arg_name, arg_type
-                                                        ));
-                                                    }
-                                                } else if arg["nodeType"].as_str()
-                                                    == Some("Literal")
-                                                {
-                                                    if let Some(value) =
-                                                        arg.get("value").map(|v| v.to_string())
-                                                    {
-                                                        args.push(value.clone());
-                                                        let literal_type = get_literal_type(arg);
-                                                        args_with_types.push(format!(
-                                                            "{}: {}",
-                                                            value, literal_type
-                                                        ));
-                                                    }
-                                                }
-                                            }
-                                        }
-
-                                        let arg_str = if !args_with_types.is_empty() {
-                                            args_with_types.join(", ")
-                                        } else if !args.is_empty() {
+                                interactions.extend(render_member_call(
+                                    contract_name,
+                                    function_name,
+                                    expression,
+                                    None,
+                                    data,
+                                    context,
+                                ));
+                            } else if call_expr["nodeType"].as_str() == Some("Identifier") {
+                                let args: Vec<String> = expression["arguments"]
+                                    .as_array()
+                                    .map(|args| args.iter().map(describe_expression).collect())
+                                    .unwrap_or_default();
+
+                                match call_expr["name"].as_str().unwrap_or("") {
+                                    // Validation guards, including the reason string when present
+                                    guard @ ("require" | "assert") => {
+                                        interactions.push(format!(
+                                            "Note over {}: {}({})",
+                                            contract_name,
+                                            guard,
                                             args.join(", ")
-                                        } else {
-                                            String::new()
-                                        };
-
-                                        // Get function purpose
-                                        let func_purpose = get_function_purpose(member_name);
-
-                                        // Process based on function type
-                                        if member_name == "transfer" || member_name == "send" {
-                                            if let Some(purpose) = func_purpose {
-                                                interactions.push(format!(
-                                                    "Note right of {}: {}",
-                                                    contract_name, purpose
-                                                ));
-                                            }
-                                            interactions.push(format!(
-                                                "{}->>+{}: {}({})",
-                                                contract_name, target_name, member_name, arg_str
-                                            ));
-                                            interactions.push(format!(
-                                                "{}-->>-{}: return (success)",
-                                                target_name, contract_name
-                                            ));
-                                        } else if (member_name == "transferFrom"
-                                            || member_name == "transfer")
-                                            && target_name.to_lowercase().contains("token")
-                                        {
-                                            if let Some(purpose) = func_purpose {
-                                                interactions.push(format!(
-                                                    "Note right of {}: {}",
-                                                    contract_name, purpose
-                                                ));
-                                            }
-                                            interactions.push(format!(
-                                                "{}->>+TokenContract: {}({})",
-                                                contract_name, member_name, arg_str
-                                            ));
-                                            interactions.push(format!(
-                                                "TokenContract-->>-{}: return (success)",
-                                                contract_name
-                                            ));
-                                        } else {
-                                            if let Some(purpose) = func_purpose {
-                                                interactions.push(format!(
-                                                    "Note right of {}: {}",
-                                                    contract_name, purpose
-                                                ));
-                                            }
-                                            interactions.push(format!(
-                                                "{}->>+{}: {}({})",
-                                                contract_name, target_name, member_name, arg_str
-                                            ));
-                                            interactions.push(format!(
-                                                "{}-->>-{}: return",
-                                                target_name, contract_name
-                                            ));
-                                        }
-                                    } else if base_expr["nodeType"].as_str() == Some("FunctionCall")
-                                        && base_expr.get("kind").and_then(|k| k.as_str())
-                                            == Some("typeConversion")
-                                    {
-                                        // Handle special cases like address(this).balance
-                                        let mut args = Vec::new();
-                                        let mut args_with_types = Vec::new();
-
-                                        if let Some(arguments) =
-                                            expression.get("arguments").and_then(|a| a.as_array())
-                                        {
-                                            for arg in arguments {
-                                                if arg["nodeType"].as_str() == Some("Identifier") {
-                                                    if let Some(arg_name) =
-                                                        arg.get("name").and_then(|n| n.as_str())
-                                                    {
-                                                        args.push(arg_name.to_string());
-                                                        let arg_type =
-                                                            guess_type_from_name(arg_name);
-                                                        args_with_types.push(format!(
-                                                            "{}: {}",
-                                                            arg_name, arg_type
-                                                        ));
-                                                    }
-                                                } else if arg["nodeType"].as_str()
-                                                    == Some("Literal")
-                                                {
-                                                    if let Some(value) =
-                                                        arg.get("value").map(|v| v.to_string())
-                                                    {
-                                                        args.push(value.clone());
-                                                        let literal_type = get_literal_type(arg);
-                                                        args_with_types.push(format!(
-                                                            "{}: {}",
-                                                            value, literal_type
-                                                        ));
-                                                    }
-                                                }
-                                            }
-                                        }
-
-                                        let special_arg_str = if !args_with_types.is_empty() {
-                                            args_with_types.join(", ")
-                                        } else if !args.is_empty() {
+                                        ));
+                                    }
+                                    // Bare revert() without a reason
+                                    "revert" if args.is_empty() => {
+                                        interactions
+                                            .push(format!("Note over {}: revert", contract_name));
+                                    }
+                                    "revert" => {
+                                        interactions.push(format!(
+                                            "Note over {}: revert({})",
+                                            contract_name,
                                             args.join(", ")
-                                        } else {
-                                            String::new()
-                                        };
-
-                                        if member_name == "transfer"
-                                            || member_name == "send"
-                                            || member_name == "call"
-                                        {
-                                            interactions.push(format!(
-                                                "{}->>+Recipient: ETH {}({})",
-                                                contract_name, member_name, special_arg_str
-                                            ));
-                                            interactions.push(format!(
-                                                "Recipient-->>-{}: return (success)",
-                                                contract_name
-                                            ));
-                                        }
+                                        ));
                                     }
+                                    _ => {}
                                 }
                             }
                         }
                     }
                 }
             }
+            "TryStatement" => {
+                interactions.push("alt try".to_string());
+
+                // The external call itself renders like any other call statement
+                let call_statement = serde_json::json!({
+                    "nodeType": "ExpressionStatement",
+                    "expression": statement["externalCall"].clone(),
+                });
+                let call_lines = process_function_body(
+                    contract_name,
+                    function_name,
+                    &[call_statement],
+                    data,
+                    context,
+                );
+                for line in call_lines {
+                    interactions.push(format!("    {}", line));
+                }
+
+                // The first clause is the success block, the rest are catch clauses
+                if let Some(clauses) = statement["clauses"].as_array() {
+                    for (index, clause) in clauses.iter().enumerate() {
+                        if index > 0 {
+                            let params = extract_parameters(&clause["parameters"])
+                                .iter()
+                                .map(|p| format!("{} {}", p.param_type, p.name).trim().to_string())
+                                .collect::<Vec<_>>();
+                            let error_name = clause["errorName"].as_str().unwrap_or("");
+                            if params.is_empty() && error_name.is_empty() {
+                                interactions.push("else catch".to_string());
+                            } else {
+                                interactions.push(format!(
+                                    "else catch {}({})",
+                                    error_name,
+                                    params.join(", ")
+                                ));
+                            }
+                        }
+
+                        if let Some(clause_statements) =
+                            clause["block"]["statements"].as_array()
+                        {
+                            let body = process_function_body(
+                                contract_name,
+                                function_name,
+                                clause_statements,
+                                data,
+                                context,
+                            );
+                            for line in body {
+                                interactions.push(format!("    {}", line));
+                            }
+                        }
+                    }
+                }
+
+                interactions.push("end".to_string());
+            }
+            "RevertStatement" => {
+                // Custom error reverts (Solidity >= 0.8.4)
+                let error_call = &statement["errorCall"];
+                let error_name = describe_expression(&error_call["expression"]);
+                let args: Vec<String> = error_call["arguments"]
+                    .as_array()
+                    .map(|args| args.iter().map(describe_expression).collect())
+                    .unwrap_or_default();
+                interactions.push(format!(
+                    "Note over {}: revert {}({})",
+                    contract_name,
+                    error_name,
+                    args.join(", ")
+                ));
+            }
+            "Throw" => {
+                // Legacy (pre-0.5) throw statement
+                interactions.push(format!("Note over {}: throw", contract_name));
+            }
+            "UncheckedBlock" => {
+                // Overflow is not checked inside; the contained calls still render
+                interactions.push(format!("Note over {}: unchecked arithmetic", contract_name));
+                if let Some(inner) = statement["statements"].as_array() {
+                    interactions.extend(process_function_body(
+                        contract_name,
+                        function_name,
+                        inner,
+                        data,
+                        context,
+                    ));
+                }
+            }
+            "InlineAssembly" => {
+                // Flag assembly instead of skipping it, listing its security-relevant opcodes
+                let opcodes = assembly_opcodes(statement);
+                if opcodes.is_empty() {
+                    interactions.push(format!("Note over {}: inline assembly", contract_name));
+                } else {
+                    interactions.push(format!(
+                        "Note over {}: inline assembly ({})",
+                        contract_name,
+                        opcodes.join(", ")
+                    ));
+                }
+            }
+            "Return" if !statement["expression"].is_null() => {
+                // `return pool.get()` and `return flag ? a() : b()` render like declarations
+                let declaration = serde_json::json!({
+                    "nodeType": "VariableDeclarationStatement",
+                    "declarations": [],
+                    "initialValue": statement["expression"],
+                });
+                interactions.extend(process_statements(
+                    contract_name,
+                    function_name,
+                    &[declaration],
+                    data,
+                    context,
+                ));
+            }
+            "VariableDeclarationStatement"
+                if statement["initialValue"]["nodeType"].as_str() == Some("TupleExpression") =>
+            {
+                // `(uint a, uint b) = (x.f(), y.g())` declares each variable from its own call
+                let declarations = statement["declarations"].as_array().into_iter().flatten();
+                let values = statement["initialValue"]["components"].as_array();
+                let values = values.into_iter().flatten();
+                for (declaration, value) in declarations.zip(values) {
+                    let single = serde_json::json!({
+                        "nodeType": "VariableDeclarationStatement",
+                        "declarations": [declaration],
+                        "initialValue": value,
+                    });
+                    interactions.extend(process_statements(
+                        contract_name,
+                        function_name,
+                        &[single],
+                        data,
+                        context,
+                    ));
+                }
+            }
+            "VariableDeclarationStatement"
+                if statement["initialValue"]["nodeType"].as_str() == Some("Conditional") =>
+            {
+                // `x = cond ? a() : b()` notes both branches, then renders each branch's call
+                let declarations = &statement["declarations"];
+                let names = declarations
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|decl| decl["name"].as_str().unwrap_or("_"))
+                    .join(", ");
+                let conditional = &statement["initialValue"];
+                let target = if names.is_empty() { "return".to_string() } else { names + " =" };
+                interactions.push(format!(
+                    "Note over {}: {} {}",
+                    contract_name,
+                    target,
+                    describe_expression(conditional)
+                ));
+
+                for branch in [&conditional["trueExpression"], &conditional["falseExpression"]] {
+                    if branch["nodeType"].as_str() != Some("FunctionCall") {
+                        continue;
+                    }
+                    let single = serde_json::json!({
+                        "nodeType": "VariableDeclarationStatement",
+                        "declarations": declarations,
+                        "initialValue": branch,
+                    });
+                    let lines =
+                        process_statements(contract_name, function_name, &[single], data, context);
+                    interactions.extend(lines.into_iter().map(|line| {
+                        if line.contains(": return → ") {
+                            format!("{} (conditional)", line)
+                        } else {
+                            line
+                        }
+                    }));
+                }
+            }
             "VariableDeclarationStatement" => {
                 // Handle variable declarations with function calls
                 if let Some(init_value) = statement.get("initialValue") {
-                    if init_value["nodeType"].as_str() == Some("FunctionCall") {
-                        if let Some(call_expr) = init_value.get("expression") {
-                            if call_expr["nodeType"].as_str() == Some("MemberAccess") {
-                                let member_name =
-                                    call_expr["memberName"].as_str().unwrap_or("unknown");
-
-                                if let Some(base_expr) = call_expr.get("expression") {
-                                    if base_expr["nodeType"].as_str() == Some("Identifier") {
-                                        let target_name =
-                                            base_expr["name"].as_str().unwrap_or("Unknown");
-
-                                        // Extract arguments
-                                        let mut args = Vec::new();
-                                        let mut args_with_types = Vec::new();
-
-                                        if let Some(arguments) =
-                                            init_value.get("arguments").and_then(|a| a.as_array())
-                                        {
-                                            for arg in arguments {
-                                                if arg["nodeType"].as_str() == Some("Identifier") {
-                                                    if let Some(arg_name) =
-                                                        arg.get("name").and_then(|n| n.as_str())
-                                                    {
-                                                        args.push(arg_name.to_string());
-                                                        let arg_type =
-                                                            guess_type_from_name(arg_name);
-                                                        args_with_types.push(format!(
-                                                            "{}: {}",
-                                                            arg_name, arg_type
-                                                        ));
-                                                    }
-                                                } else if arg["nodeType"].as_str()
-                                                    == Some("Literal")
-                                                {
-                                                    if let Some(value) =
-                                                        arg.get("value").map(|v| v.to_string())
-                                                    {
-                                                        args.push(value.clone());
-                                                        let literal_type = get_literal_type(arg);
-                                                        args_with_types.push(format!(
-                                                            "{}: {}",
-                                                            value, literal_type
-                                                        ));
-                                                    }
-                                                }
-                                            }
-                                        }
-
-                                        let arg_str = if !args_with_types.is_empty() {
-                                            args_with_types.join(", ")
-                                        } else if !args.is_empty() {
-                                            args.join(", ")
-                                        } else {
-                                            String::new()
-                                        };
-
-                                        // Extract variable names being assigned
-                                        let mut var_names = Vec::new();
-                                        if let Some(declarations) =
-                                            statement.get("declarations").and_then(|d| d.as_array())
-                                        {
-                                            for decl in declarations {
-                                                if let Some(name) =
-                                                    decl.get("name").and_then(|n| n.as_str())
-                                                {
-                                                    var_names.push(name.to_string());
-                                                }
-                                            }
-                                        }
-
-                                        let var_str = if !var_names.is_empty() {
-                                            var_names.join(", ")
-                                        } else {
-                                            "result".to_string()
-                                        };
+                    interactions.extend(render_deployment(
+                        contract_name,
+                        function_name,
+                        init_value,
+                        data,
+                    ));
+
+                    // Low-level calls replace the generic member-call rendering below
+                    let low_level = render_low_level_call(
+                        contract_name,
+                        function_name,
+                        init_value,
+                        data,
+                        config,
+                    );
+                    let is_low_level = !low_level.is_empty();
+                    interactions.extend(low_level);
+                    interactions.extend(render_internal_call(
+                        contract_name,
+                        function_name,
+                        init_value,
+                        data,
+                        context,
+                    ));
+
+                    // Built-ins such as `abi.encode` or `keccak256` get no lifeline
+                    let is_builtin = is_builtin_call(init_value);
+                    if is_builtin && is_notable_builtin_call(init_value) {
+                        let names = statement["declarations"]
+                            .as_array()
+                            .into_iter()
+                            .flatten()
+                            .map(|decl| decl["name"].as_str().unwrap_or("_"))
+                            .join(", ");
+                        interactions.push(format!(
+                            "Note over {}: {} = {}",
+                            contract_name,
+                            names,
+                            describe_expression(init_value)
+                        ));
+                    }
 
-                                        interactions.push(format!(
-                                            "{}->>+{}: {}({})",
-                                            contract_name, target_name, member_name, arg_str
-                                        ));
-                                        interactions.push(format!(
-                                            "{}-->>-{}: return → {}",
-                                            target_name, contract_name, var_str
-                                        ));
-                                    }
-                                }
-                            }
-                        }
+                    if init_value["nodeType"].as_str() == Some("FunctionCall")
+                        && !is_low_level
+                        && !is_builtin
+                    {
+                        // Extract variable names being assigned; skipped tuple slots
+                        // like `(, b)` show as `_`
+                        let var_names: Vec<&str> = statement["declarations"]
+                            .as_array()
+                            .into_iter()
+                            .flatten()
+                            .map(|decl| decl["name"].as_str().unwrap_or("_"))
+                            .collect();
+                        let var_str = match var_names.as_slice() {
+                            [] => "result".to_string(),
+                            [name] => name.to_string(),
+                            names => format!("({})", names.join(", ")),
+                        };
+                        interactions.extend(render_member_call(
+                            contract_name,
+                            function_name,
+                            init_value,
+                            Some(&var_str),
+                            data,
+                            context,
+                        ));
                     }
                 }
             }
             _ => {}
         }
+
+        if interactions[first_line..].iter().any(|line| is_external_call(line)) {
+            context.external_call_made = true;
+        }
+    }
+
+    interactions
+}
+
+/// Yul builtins worth flagging in inline assembly: external calls, deployments and storage
+const NOTABLE_OPCODES: &[&str] = &[
+    "call",
+    "callcode",
+    "delegatecall",
+    "staticcall",
+    "create",
+    "create2",
+    "sstore",
+    "tstore",
+    "selfdestruct",
+];
+
+/// Notable Yul builtins called in an `InlineAssembly` statement, in order of first use
+///
+/// Reads the Yul `AST` of solc >= 0.6 and falls back to the `operations` source text.
+fn assembly_opcodes(assembly: &Value) -> Vec<String> {
+    let mut opcodes = Vec::new();
+    if assembly["AST"].is_object() {
+        collect_yul_calls(&assembly["AST"], &mut opcodes);
+    } else if let Some(operations) = assembly["operations"].as_str() {
+        // Identifiers directly followed by `(` are calls
+        let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+        let mut rest = operations;
+        while let Some(start) = rest.find(is_ident) {
+            rest = &rest[start..];
+            let end = rest.find(|c: char| !is_ident(c)).unwrap_or(rest.len());
+            let (token, after) = rest.split_at(end);
+            if after.trim_start().starts_with('(') {
+                opcodes.push(token.to_string());
+            }
+            rest = after;
+        }
+    }
+
+    opcodes
+        .into_iter()
+        .filter(|opcode| NOTABLE_OPCODES.contains(&opcode.as_str()))
+        .unique()
+        .collect()
+}
+
+/// Collect the names of all Yul function calls below a node
+fn collect_yul_calls(node: &Value, calls: &mut Vec<String>) {
+    match node {
+        Value::Object(map) => {
+            if map.get("nodeType").and_then(Value::as_str) == Some("YulFunctionCall") {
+                if let Some(name) = node["functionName"]["name"].as_str() {
+                    calls.push(name.to_string());
+                }
+            }
+            map.values().for_each(|value| collect_yul_calls(value, calls));
+        }
+        Value::Array(values) => values.iter().for_each(|value| collect_yul_calls(value, calls)),
+        _ => {}
+    }
+}
+
+/// Whether an expression assigns the result of a member call, e.g. `(a, b) = pool.get()`,
+/// directly or from either branch of a ternary
+fn is_member_call_assignment(expression: &Value) -> bool {
+    let is_member_call = |value: &Value| {
+        value["nodeType"].as_str() == Some("FunctionCall")
+            && value["expression"]["nodeType"].as_str() == Some("MemberAccess")
+    };
+    let right = &expression["rightHandSide"];
+    let is_conditional_call = right["nodeType"].as_str() == Some("Conditional")
+        && (is_member_call(&right["trueExpression"]) || is_member_call(&right["falseExpression"]));
+    expression["nodeType"].as_str() == Some("Assignment")
+        && (is_member_call(right) || is_conditional_call)
+}
+
+/// Note for an assignment to one of the contract's state variables, if updates are shown
+fn storage_update_note(
+    contract_name: &str,
+    assignment: &Value,
+    data: &DiagramData,
+    config: &Config,
+) -> Option<String> {
+    let left = &assignment["leftHandSide"];
+    let is_state_var =
+        storage_root(left).is_some_and(|root| is_state_variable(data, contract_name, root));
+
+    (is_state_var && config.show_storage_updates).then(|| {
+        format!(
+            "Note over {}: storage: {} {} {}",
+            contract_name,
+            describe_expression(left),
+            assignment["operator"].as_str().unwrap_or("="),
+            describe_expression(&assignment["rightHandSide"])
+        )
+    })
+}
+
+/// Check whether a line is a call into another participant, e.g. `Vault->>+Token: transfer()`
+fn is_external_call(line: &str) -> bool {
+    line.trim().split_once(':').is_some_and(|(head, _)| {
+        head.split_once("->>+").is_some_and(|(from, to)| !from.ends_with('-') && from != to)
+    })
+}
+
+/// Warning for the first write to a state variable after an external call, if flagged
+///
+/// Calls rendered earlier in this statement list count as well as those already recorded
+/// in the context, so a write right after a call in the same block is caught.
+fn reentrancy_warning(
+    contract_name: &str,
+    target: &Value,
+    interactions: &[String],
+    data: &DiagramData,
+    context: &mut BodyContext,
+) -> Option<String> {
+    if !context.config.flag_reentrancy || context.reentrancy_flagged {
+        return None;
+    }
+    let writes_state =
+        storage_root(target).is_some_and(|root| is_state_variable(data, contract_name, root));
+    let after_call =
+        context.external_call_made || interactions.iter().any(|line| is_external_call(line));
+
+    (writes_state && after_call).then(|| {
+        context.reentrancy_flagged = true;
+        format!("Note over {}: ⚠ state change after external call", contract_name)
+    })
+}
+
+/// Describe a `delete` or storage array `push`/`pop` expression, e.g. `push to users`
+///
+/// `push` and `pop` count when the receiver is an array type or rooted in a state variable,
+/// so a contract exposing its own `push` function still gets a call arrow.
+fn storage_mutation(contract_name: &str, expression: &Value, data: &DiagramData) -> Option<String> {
+    let rooted_in_state = |target: &Value| {
+        storage_root(target).is_some_and(|root| is_state_variable(data, contract_name, root))
+    };
+
+    match expression["nodeType"].as_str()? {
+        "UnaryOperation" if expression["operator"].as_str() == Some("delete") => {
+            let target = &expression["subExpression"];
+            rooted_in_state(target).then(|| format!("delete {}", describe_expression(target)))
+        }
+        "FunctionCall" if expression["expression"]["nodeType"].as_str() == Some("MemberAccess") => {
+            let member = &expression["expression"];
+            let array = &member["expression"];
+            let type_string = array["typeDescriptions"]["typeString"].as_str().unwrap_or("");
+            let is_array = type_string.contains("[]") || type_string.starts_with("bytes storage");
+            if type_string.starts_with("contract ") || !(is_array || rooted_in_state(array)) {
+                return None;
+            }
+            match member["memberName"].as_str()? {
+                "push" => Some(format!("push to {}", describe_expression(array))),
+                "pop" => Some(format!("pop from {}", describe_expression(array))),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Render a member call `receiver.member(args)` by what the receiver is
+///
+/// Covers `this`, libraries bound by `using for`, library and static calls on a type,
+/// token transfers, contract instances, type conversions and chained receivers. `returns`
+/// names the variables receiving the result, shown on the return arrow.
+fn render_member_call(
+    contract_name: &str,
+    function_name: &str,
+    call: &Value,
+    returns: Option<&str>,
+    data: &mut DiagramData,
+    context: &mut BodyContext,
+) -> Vec<String> {
+    let call_expr = &call["expression"];
+    if call["nodeType"].as_str() != Some("FunctionCall")
+        || call_expr["nodeType"].as_str() != Some("MemberAccess")
+        || is_builtin_call(call)
+    {
+        return Vec::new();
+    }
+
+    let config = context.config;
+    let returned = |label: &str| match returns {
+        Some(names) => format!("{} → {}", label, names),
+        None => label.to_string(),
+    };
+    // Library calls have no return arrow, so the receiving variables follow the call
+    let received = returns.map(|names| format!(" → {}", names)).unwrap_or_default();
+    let mut interactions = Vec::new();
+    let member_name = call_expr["memberName"].as_str().unwrap_or("unknown");
+
+    if let Some(base_expr) = call_expr.get("expression") {
+        if is_this(base_expr) {
+            interactions.extend(render_this_call(
+                contract_name,
+                function_name,
+                member_name,
+                call,
+                data,
+            ));
+        } else if base_expr["nodeType"].as_str() == Some("Identifier") {
+            let variable_name = base_expr["name"].as_str().unwrap_or("Unknown");
+
+            // Lifelines follow the receiver's contract type when known
+            let receiver_type = resolve_receiver_type(base_expr, data);
+            if let Some(receiver_type) = &receiver_type {
+                data.participants.insert(receiver_type.clone());
+            }
+            let target_name = receiver_type.as_deref().unwrap_or(variable_name);
+
+            // Extract arguments
+            let arg_str = format_arguments(call, data);
+
+            // Get function purpose
+            let func_purpose = get_function_purpose(member_name, &config.function_purposes);
+
+            // Process based on function type
+            if let Some(library) =
+                find_using_for_library(contract_name, base_expr, member_name, data)
+            {
+                record_library_use(data, contract_name, &library);
+                // Bound library functions receive the value as first argument
+                let library_args = if arg_str.is_empty() {
+                    target_name.to_string()
+                } else {
+                    format!("{}, {}", target_name, arg_str)
+                };
+                interactions.push(format!(
+                    "{}->>{}: {}({}){}",
+                    contract_name, library, member_name, library_args, received
+                ));
+                record_call(data, contract_name, function_name, &library, member_name);
+            } else if is_library_reference(base_expr, data) {
+                // Library code runs in the caller's context, e.g. Math.max(a, b)
+                interactions.push(format!(
+                    "Note right of {}: library call (no separate context)",
+                    contract_name
+                ));
+                interactions.push(format!(
+                    "{}->>{}: {}({}){}",
+                    contract_name, target_name, member_name, arg_str, received
+                ));
+                record_call(data, contract_name, function_name, target_name, member_name);
+                data.participants.insert(target_name.to_string());
+            } else if is_type_reference(base_expr, contract_name, data) {
+                // Static call on a contract type, e.g. Base.hook()
+                interactions.push(format!("Note right of {}: static call", contract_name));
+                interactions.push(format!(
+                    "{}->>+{}: {}({})",
+                    contract_name, target_name, member_name, arg_str
+                ));
+                record_call(data, contract_name, function_name, target_name, member_name);
+                interactions.push(format!(
+                    "{}-->>-{}: {}",
+                    target_name,
+                    contract_name,
+                    returned("return")
+                ));
+                data.participants.insert(target_name.to_string());
+            } else if member_name == "transfer" || member_name == "send" {
+                if let Some(purpose) = func_purpose {
+                    interactions.push(format!("Note right of {}: {}", contract_name, purpose));
+                }
+                interactions.push(format!(
+                    "{}->>+{}: {}({})",
+                    contract_name, target_name, member_name, arg_str
+                ));
+                record_call(data, contract_name, function_name, target_name, member_name);
+                interactions.push(format!(
+                    "{}-->>-{}: {}",
+                    target_name,
+                    contract_name,
+                    returned("return (success)")
+                ));
+            } else if (member_name == "transferFrom"
+                || member_name == "transfer"
+                || member_name == "safeTransferFrom"
+                || member_name == "safeTransfer")
+                && variable_name.to_lowercase().contains("token")
+            {
+                if let Some(purpose) = func_purpose {
+                    interactions.push(format!("Note right of {}: {}", contract_name, purpose));
+                }
+                let token = &config.token_participant;
+                data.participants.insert(token.clone());
+                interactions
+                    .push(format!("{}->>+{}: {}({})", contract_name, token, member_name, arg_str));
+                record_call(data, contract_name, function_name, token, member_name);
+
+                // Safe transfers call back into the `to` address;
+                // ERC1155's form is (from, to, id, value, data)
+                if member_name == "safeTransferFrom" {
+                    let arguments = call["arguments"].as_array().map_or(&[][..], Vec::as_slice);
+                    let recipient = match arguments.get(1) {
+                        Some(to) => transfer_recipient(to, contract_name, data, config),
+                        None => config.recipient_participant.clone(),
+                    };
+                    let callback = if arguments.len() == 5 {
+                        "onERC1155Received(operator, from, id, value, data)"
+                    } else {
+                        "onERC721Received(operator, from, tokenId, data)"
+                    };
+                    interactions.push(format!("{}->>+{}: {}", token, recipient, callback));
+                    interactions.push(format!("{}-->>-{}: return (selector)", recipient, token));
+                    data.participants.insert(recipient);
+                }
+
+                interactions.push(format!(
+                    "{}-->>-{}: {}",
+                    token,
+                    contract_name,
+                    returned("return (success)")
+                ));
+            } else {
+                if let Some(purpose) = func_purpose {
+                    interactions.push(format!("Note right of {}: {}", contract_name, purpose));
+                }
+                interactions.push(format!(
+                    "{}->>+{}: {}({})",
+                    contract_name, target_name, member_name, arg_str
+                ));
+                record_call(data, contract_name, function_name, target_name, member_name);
+                interactions.extend(render_callee_body(target_name, member_name, data, context));
+                interactions.push(format!(
+                    "{}-->>-{}: {}",
+                    target_name,
+                    contract_name,
+                    returned("return")
+                ));
+            }
+        } else if base_expr["nodeType"].as_str() == Some("FunctionCall")
+            && base_expr.get("kind").and_then(|k| k.as_str()) == Some("typeConversion")
+        {
+            // Handle special cases like address(this).balance
+            let special_arg_str = format_arguments(call, data);
+            let resolved_target = resolve_conversion_target(base_expr, contract_name, data);
+
+            if base_expr["expression"]["nodeType"].as_str() == Some("Identifier") {
+                // Converted to a contract type, e.g. IERC20(address(vault)).transfer(...)
+                let target = resolved_target.unwrap_or_else(|| "Unknown".to_string());
+                data.participants.insert(target.clone());
+                interactions.push(format!(
+                    "{}->>+{}: {}({})",
+                    contract_name, target, member_name, special_arg_str
+                ));
+                record_call(data, contract_name, function_name, &target, member_name);
+                interactions.push(format!(
+                    "{}-->>-{}: {}",
+                    target,
+                    contract_name,
+                    returned("return")
+                ));
+            } else if member_name == "transfer" || member_name == "send" {
+                // Only known contracts get their own lifeline for ETH sends
+                let recipient = if config.show_msg_sender && is_msg_sender(base_expr) {
+                    CALLER.to_string()
+                } else {
+                    resolved_target
+                        .filter(|t| data.contracts.contains_key(t))
+                        .unwrap_or_else(|| config.recipient_participant.clone())
+                };
+                data.participants.insert(recipient.clone());
+                interactions.push(format!(
+                    "{}->>+{}: ETH {}({})",
+                    contract_name, recipient, member_name, special_arg_str
+                ));
+                record_call(data, contract_name, function_name, &recipient, member_name);
+                interactions.push(format!(
+                    "{}-->>-{}: {}",
+                    recipient,
+                    contract_name,
+                    returned("return (success)")
+                ));
+            }
+        } else if matches!(
+            base_expr["nodeType"].as_str(),
+            Some("MemberAccess" | "FunctionCall" | "IndexAccess")
+        ) {
+            // Chained receivers, e.g. registry.getVault(id).deposit(x):
+            // calls producing the receiver run first
+            if base_expr["nodeType"].as_str() == Some("FunctionCall") {
+                let receiver_call = serde_json::json!({
+                    "nodeType": "ExpressionStatement",
+                    "expression": base_expr,
+                });
+                interactions.extend(process_function_body(
+                    contract_name,
+                    function_name,
+                    &[receiver_call],
+                    data,
+                    context,
+                ));
+            }
+
+            let target = if config.show_msg_sender && is_msg_sender(base_expr) {
+                data.participants.insert(CALLER.to_string());
+                CALLER.to_string()
+            } else {
+                resolve_receiver_type(base_expr, data)
+                    .unwrap_or_else(|| receiver_identifier(base_expr))
+            };
+            let arg_str = format_arguments(call, data);
+            interactions
+                .push(format!("{}->>+{}: {}({})", contract_name, target, member_name, arg_str));
+            record_call(data, contract_name, function_name, &target, member_name);
+            interactions.extend(render_callee_body(&target, member_name, data, context));
+            interactions.push(format!("{}-->>-{}: {}", target, contract_name, returned("return")));
+        }
     }
 
     interactions
 }
 
+/// Render a call to an internal or free function and inline its body
+///
+/// Returns no lines when the call targets neither a function of `contract_name` nor a
+/// free function.
+fn render_internal_call(
+    contract_name: &str,
+    function_name: &str,
+    call: &Value,
+    data: &mut DiagramData,
+    context: &mut BodyContext,
+) -> Vec<String> {
+    if call["nodeType"].as_str() != Some("FunctionCall")
+        || call["expression"]["nodeType"].as_str() != Some("Identifier")
+    {
+        return Vec::new();
+    }
+
+    let Some(callee) = call["expression"]["name"].as_str() else {
+        return Vec::new();
+    };
+    // Resolve the definition the compiler bound the call to, which tells overloads and
+    // same-named free functions apart and reaches inherited functions. Without ids, search the
+    // contract and its bases by name; contract functions shadow free functions of the same name
+    let resolved = match call["expression"]["referencedDeclaration"].as_i64() {
+        Some(id) => context.functions.by_id.get(&id).map(|(owner, node)| (*owner, *node)),
+        None => context
+            .functions
+            .find(data, contract_name, callee)
+            .map(|node| (contract_name, node))
+            .or_else(|| {
+                let free_function = format!("{}.{}", FREE_FUNCTIONS, callee);
+                context.functions.by_name.get(&free_function).map(|node| (FREE_FUNCTIONS, *node))
+            }),
+    };
+    let Some((owner, function_node)) = resolved else {
+        return Vec::new();
+    };
+    // Inherited functions run on the calling contract
+    let target = if owner == FREE_FUNCTIONS { FREE_FUNCTIONS } else { contract_name };
+
+    data.participants.insert(target.to_string());
+    record_call(data, contract_name, function_name, target, callee);
+    let mut lines = vec![format!(
+        "{}->>{}: {}({})",
+        contract_name,
+        target,
+        callee,
+        format_arguments(call, data)
+    )];
+
+    // Inline the callee's body unless it is already being walked
+    let id = definition_id(function_node);
+    if context.visited.contains(&id) {
+        lines.push(format!("Note over {}: {} (recursion)", target, callee));
+    } else if let Some(statements) = function_node["body"]["statements"].as_array() {
+        context.visited.insert(id);
+        lines.extend(process_function_body(target, callee, statements, data, context));
+        context.visited.remove(&id);
+    }
+
+    lines
+}
+
+/// Walk the body of `target.member` between a cross-contract call and its return
+///
+/// Keeps the callee's own calls inside its activation so returns close in LIFO order.
+/// Returns no lines when the callee's definition is not part of the AST.
+fn render_callee_body(
+    target: &str,
+    member: &str,
+    data: &mut DiagramData,
+    context: &mut BodyContext,
+) -> Vec<String> {
+    let Some(function_node) = context.functions.find(data, target, member) else {
+        return Vec::new();
+    };
+
+    let id = definition_id(function_node);
+    if context.visited.contains(&id) {
+        return vec![format!("Note over {}: {} (recursion)", target, member)];
+    }
+    let Some(statements) = function_node["body"]["statements"].as_array() else {
+        return Vec::new();
+    };
+    context.visited.insert(id);
+    let lines = process_function_body(target, member, statements, data, context);
+    context.visited.remove(&id);
+
+    lines
+}
+
+/// AST id of a function definition, the key of `BodyContext::visited`
+///
+/// Hand-built ASTs may leave out ids; their definitions fall back to the node's address,
+/// which is stable because definitions always come from the input AST.
+fn definition_id(function_node: &Value) -> i64 {
+    function_node["id"].as_i64().unwrap_or(function_node as *const Value as i64)
+}
+
+/// Split `{name: value, ...}` call options from the callee expression they wrap
+fn split_call_options(callee: &Value) -> (&Value, String) {
+    if callee["nodeType"].as_str() != Some("FunctionCallOptions") {
+        return (callee, String::new());
+    }
+
+    let pairs: Vec<String> = match (callee["names"].as_array(), callee["options"].as_array()) {
+        (Some(names), Some(values)) => names
+            .iter()
+            .zip(values)
+            .map(|(name, value)| {
+                format!("{}: {}", name.as_str().unwrap_or("?"), describe_expression(value))
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    (&callee["expression"], format!("{{{}}}", pairs.join(", ")))
+}
+
+/// Render a `new Contract(...)` deployment, returning no lines for any other expression
+fn render_deployment(
+    contract_name: &str,
+    function_name: &str,
+    call: &Value,
+    data: &mut DiagramData,
+) -> Vec<String> {
+    if call["nodeType"].as_str() != Some("FunctionCall") {
+        return Vec::new();
+    }
+
+    // `new Token{salt: s}(args)` wraps the NewExpression in call options
+    let (callee, options) = split_call_options(&call["expression"]);
+
+    // Only contract types are deployments; `new uint256[](n)` allocates memory
+    if callee["nodeType"].as_str() != Some("NewExpression")
+        || callee["typeName"]["nodeType"].as_str() != Some("UserDefinedTypeName")
+    {
+        return Vec::new();
+    }
+
+    let new_type = extract_type_name(&callee["typeName"]);
+    data.participants.insert(new_type.clone());
+    record_call(data, contract_name, function_name, &new_type, "constructor");
+
+    vec![
+        format!(
+            "{}->>+{}: deploy{}({})",
+            contract_name,
+            new_type,
+            options,
+            format_arguments(call, data)
+        ),
+        format!("{}-->>-{}: return (address)", new_type, contract_name),
+    ]
+}
+
+/// Render a low-level `call`, `delegatecall` or `staticcall`, returning no lines otherwise
+fn render_low_level_call(
+    contract_name: &str,
+    function_name: &str,
+    call: &Value,
+    data: &mut DiagramData,
+    config: &Config,
+) -> Vec<String> {
+    if call["nodeType"].as_str() != Some("FunctionCall") {
+        return Vec::new();
+    }
+
+    // `target.call{value: amount}(data)` wraps the member access in call options
+    let (callee, options) = split_call_options(&call["expression"]);
+
+    let member_name = callee["memberName"].as_str().unwrap_or("");
+    if callee["nodeType"].as_str() != Some("MemberAccess")
+        || !matches!(member_name, "call" | "delegatecall" | "staticcall")
+    {
+        return Vec::new();
+    }
+
+    // Resolve the callee address to a participant where possible
+    let base = &callee["expression"];
+    let target = match base["nodeType"].as_str() {
+        _ if config.show_msg_sender && is_msg_sender(base) => Some(CALLER.to_string()),
+        Some("Identifier") => base
+            .get("typeDescriptions")
+            .and_then(|td| td.get("typeString"))
+            .and_then(|ts| ts.as_str())
+            .filter(|ts| ts.starts_with("contract "))
+            .map(normalize_type_string)
+            .or_else(|| base["name"].as_str().map(|name| name.to_string())),
+        Some("FunctionCall") if base["kind"].as_str() == Some("typeConversion") => {
+            resolve_conversion_target(base, contract_name, data)
+                .filter(|t| data.contracts.contains_key(t))
+        }
+        _ => None,
+    }
+    .unwrap_or_else(|| config.recipient_participant.clone());
+    data.participants.insert(target.clone());
+    record_call(data, contract_name, function_name, &target, member_name);
+
+    let args: Vec<String> = call["arguments"]
+        .as_array()
+        .map(|args| args.iter().map(describe_expression).collect())
+        .unwrap_or_default();
+
+    let mut lines = Vec::new();
+    if member_name == "delegatecall" {
+        lines.push(format!(
+            "Note over {}: delegatecall (runs in {}'s context)",
+            contract_name, contract_name
+        ));
+    }
+    lines.push(format!(
+        "{}->>+{}: {}{}({})",
+        contract_name,
+        target,
+        member_name,
+        options,
+        args.join(", ")
+    ));
+    lines.push(format!("{}-->>-{}: return (success, data)", target, contract_name));
+    lines
+}
+
+/// Whether an address expression is `msg.sender`, possibly wrapped in `payable(...)`
+fn is_msg_sender(expression: &Value) -> bool {
+    match expression["nodeType"].as_str() {
+        Some("MemberAccess") => {
+            expression["memberName"].as_str() == Some("sender")
+                && expression["expression"]["name"].as_str() == Some("msg")
+        }
+        Some("FunctionCall") if expression["kind"].as_str() == Some("typeConversion") => {
+            expression["arguments"][0].is_object() && is_msg_sender(&expression["arguments"][0])
+        }
+        _ => false,
+    }
+}
+
+/// Whether `msg.value` is read anywhere inside an AST node
+fn uses_msg_value(node: &Value) -> bool {
+    match node {
+        Value::Object(map) => {
+            let is_msg_value = map.get("nodeType").and_then(Value::as_str) == Some("MemberAccess")
+                && node["memberName"].as_str() == Some("value")
+                && node["expression"]["name"].as_str() == Some("msg");
+            is_msg_value || map.values().any(uses_msg_value)
+        }
+        Value::Array(items) => items.iter().any(uses_msg_value),
+        _ => false,
+    }
+}
+
+/// Record a call site as a call-graph edge and, across contracts, a `calls` relationship
+fn record_call(
+    data: &mut DiagramData,
+    source: &str,
+    function_name: &str,
+    target: &str,
+    member_name: &str,
+) {
+    data.call_graph
+        .push((format!("{}.{}", source, function_name), format!("{}.{}", target, member_name)));
+    let known = data
+        .contract_relationships
+        .iter()
+        .any(|rel| rel.source == source && rel.target == target && rel.relation_type == "calls");
+    if source != target && !known {
+        data.contract_relationships.push(ContractRelationship {
+            source: source.to_string(),
+            target: target.to_string(),
+            relation_type: "calls".to_string(),
+            order: None,
+        });
+    }
+}
+
+/// Record the declared type of every `VariableDeclaration` below a node, keyed by node id
+fn index_declaration_types(node: &Value, types: &mut HashMap<i64, String>) {
+    match node {
+        Value::Object(object) => {
+            if node["nodeType"].as_str() == Some("VariableDeclaration") {
+                if let Some(id) = node["id"].as_i64() {
+                    let mut declared_type = extract_type_name(&node["typeName"]);
+                    if declared_type == "unknown" {
+                        if let Some(type_string) = node["typeDescriptions"]["typeString"].as_str() {
+                            declared_type = normalize_type_string(type_string);
+                        }
+                    }
+                    types.insert(id, declared_type);
+                }
+            }
+            object.values().for_each(|child| index_declaration_types(child, types));
+        }
+        Value::Array(children) => {
+            children.iter().for_each(|child| index_declaration_types(child, types))
+        }
+        _ => {}
+    }
+}
+
+/// Format the arguments of a call expression as `name: type` pairs
+fn format_arguments(call: &Value, data: &DiagramData) -> String {
+    let mut args = Vec::new();
+    let mut args_with_types = Vec::new();
+
+    if let Some(arguments) = call.get("arguments").and_then(|a| a.as_array()) {
+        for arg in arguments {
+            if arg["nodeType"].as_str() == Some("Identifier") {
+                if let Some(arg_name) = arg.get("name").and_then(|n| n.as_str()) {
+                    args.push(arg_name.to_string());
+                    // Prefer the declared type, falling back to naming heuristics
+                    let arg_type = arg["referencedDeclaration"]
+                        .as_i64()
+                        .and_then(|id| data.declaration_types.get(&id).cloned())
+                        .unwrap_or_else(|| guess_type_from_name(arg_name));
+                    args_with_types.push(format!("{}: {}", arg_name, arg_type));
+                }
+            } else if arg["nodeType"].as_str() == Some("Literal") {
+                if let Some(value) = arg.get("value").map(|v| v.to_string()) {
+                    args.push(value.clone());
+                    let literal_type = get_literal_type(arg);
+                    args_with_types.push(format!("{}: {}", value, literal_type));
+                }
+            }
+        }
+    }
+
+    if !args_with_types.is_empty() {
+        args_with_types.join(", ")
+    } else if !args.is_empty() {
+        args.join(", ")
+    } else {
+        String::new()
+    }
+}
+
+/// Resolve the contract type of a variable used as a call receiver
+///
+/// Uses the identifier's compiler type string, then the type declared on the variable
+/// it references; only contract types resolve.
+fn resolve_receiver_type(receiver: &Value, data: &DiagramData) -> Option<String> {
+    if let Some(type_string) = receiver["typeDescriptions"]["typeString"].as_str() {
+        if type_string.starts_with("contract ") {
+            return Some(normalize_type_string(type_string));
+        }
+    }
+
+    receiver["referencedDeclaration"]
+        .as_i64()
+        .and_then(|id| data.declaration_types.get(&id))
+        .filter(|declared_type| data.contracts.contains_key(*declared_type))
+        .cloned()
+}
+
+/// Check whether an expression is the `this` identifier
+fn is_this(expression: &Value) -> bool {
+    expression["nodeType"].as_str() == Some("Identifier")
+        && expression["name"].as_str() == Some("this")
+}
+
+/// Render `this.method(args)`, an external call back into the same contract
+///
+/// Unlike an internal call it sends a fresh message, so `msg.sender` becomes the contract.
+fn render_this_call(
+    contract_name: &str,
+    function_name: &str,
+    member_name: &str,
+    call: &Value,
+    data: &mut DiagramData,
+) -> Vec<String> {
+    record_call(data, contract_name, function_name, contract_name, member_name);
+    vec![
+        format!(
+            "Note over {}: external call to itself (msg.sender becomes {})",
+            contract_name, contract_name
+        ),
+        format!(
+            "{}->>{}: {}({}) [external self-call]",
+            contract_name,
+            contract_name,
+            member_name,
+            format_arguments(call, data)
+        ),
+    ]
+}
+
+/// Participant receiving a safe transfer's `onERC...Received` callback, from its `to` argument
+fn transfer_recipient(
+    to: &Value,
+    contract_name: &str,
+    data: &DiagramData,
+    config: &Config,
+) -> String {
+    let to = match to["nodeType"].as_str() {
+        Some("FunctionCall") if to["kind"].as_str() == Some("typeConversion") => {
+            &to["arguments"][0]
+        }
+        _ => to,
+    };
+
+    if is_this(to) {
+        contract_name.to_string()
+    } else if config.show_msg_sender && is_msg_sender(to) {
+        CALLER.to_string()
+    } else if let Some(contract) = resolve_receiver_type(to, data) {
+        contract
+    } else if to["nodeType"].as_str() == Some("Identifier") {
+        receiver_identifier(to)
+    } else {
+        config.recipient_participant.clone()
+    }
+}
+
+/// Name an untyped chained receiver with one participant id, e.g. `registry_getVault`
+///
+/// Calls and index accesses add no component, so `a.b().c()` becomes `a_b_c`.
+fn receiver_identifier(receiver: &Value) -> String {
+    let name = match receiver["nodeType"].as_str().unwrap_or("") {
+        "Identifier" => receiver["name"].as_str().unwrap_or("Unknown").to_string(),
+        "MemberAccess" => format!(
+            "{}_{}",
+            receiver_identifier(&receiver["expression"]),
+            receiver["memberName"].as_str().unwrap_or("Unknown")
+        ),
+        "FunctionCall" => receiver_identifier(&receiver["expression"]),
+        "IndexAccess" => receiver_identifier(&receiver["baseExpression"]),
+        _ => "Unknown".to_string(),
+    };
+
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect()
+}
+
+/// Resolve the participant behind a (possibly nested) type conversion
+///
+/// `IERC20(address(vault))` resolves to `IERC20`, while `payable(address(vault))` looks
+/// through both conversions and resolves to the declared type of `vault`.
+fn resolve_conversion_target(
+    conversion: &Value,
+    contract_name: &str,
+    data: &DiagramData,
+) -> Option<String> {
+    // Converting to a contract or interface type names the target directly
+    let callee = &conversion["expression"];
+    if callee["nodeType"].as_str() == Some("Identifier") {
+        return callee["name"].as_str().map(|name| name.to_string());
+    }
+
+    // Otherwise look through the converted value (e.g. `address(x)`, `payable(x)`)
+    let inner = conversion["arguments"].as_array()?.first()?;
+    match inner["nodeType"].as_str()? {
+        "FunctionCall" if inner["kind"].as_str() == Some("typeConversion") => {
+            resolve_conversion_target(inner, contract_name, data)
+        }
+        "Identifier" => {
+            let name = inner["name"].as_str()?;
+            if name == "this" {
+                return Some(contract_name.to_string());
+            }
+
+            // Prefer the declared contract type of the variable over its name
+            let declared_type = inner
+                .get("typeDescriptions")
+                .and_then(|td| td.get("typeString"))
+                .and_then(|ts| ts.as_str())
+                .filter(|ts| ts.starts_with("contract "))
+                .map(normalize_type_string)
+                .or_else(|| {
+                    data.contracts.get(contract_name).and_then(|info| {
+                        info.variables
+                            .iter()
+                            .find(|(var, _)| var == name)
+                            .map(|(_, var_type)| var_type.clone())
+                            .filter(|var_type| data.contracts.contains_key(var_type))
+                    })
+                });
+
+            Some(declared_type.unwrap_or_else(|| name.to_string()))
+        }
+        // Literal addresses such as `address(0x1234)` are not participants
+        _ => None,
+    }
+}
+
+/// Check whether an identifier names a contract or library type rather than a variable
+fn is_type_reference(identifier: &Value, contract_name: &str, data: &DiagramData) -> bool {
+    // The compiler annotates type names as `type(library Math)` / `type(contract Factory)`
+    if let Some(type_string) = identifier
+        .get("typeDescriptions")
+        .and_then(|td| td.get("typeString"))
+        .and_then(|ts| ts.as_str())
+    {
+        return type_string.starts_with("type(");
+    }
+
+    // Without type annotations, a known contract name that isn't shadowed by a state variable
+    let Some(name) = identifier["name"].as_str() else {
+        return false;
+    };
+    data.contracts.contains_key(name)
+        && !data
+            .contracts
+            .get(contract_name)
+            .is_some_and(|info| info.variables.iter().any(|(var, _)| var == name))
+}
+
+/// Check whether an identifier names a library, e.g. `SafeMath` in `SafeMath.add(a, b)`
+fn is_library_reference(identifier: &Value, data: &DiagramData) -> bool {
+    if let Some(type_string) = identifier["typeDescriptions"]["typeString"].as_str() {
+        return type_string.starts_with("type(library ");
+    }
+
+    identifier["name"]
+        .as_str()
+        .and_then(|name| data.contracts.get(name))
+        .is_some_and(|info| info.contract_type == "library")
+}
+
+/// Parse a `using L for T;` directive into a `(type, library)` binding
+///
+/// A missing type name (`using L for *;`) is recorded as the wildcard `*`.
+fn parse_using_for(directive: &Value) -> Option<(String, String)> {
+    let library = directive
+        .get("libraryName")
+        .and_then(|ln| ln.get("name").or_else(|| ln.get("namePath")))
+        .and_then(|n| n.as_str())?;
+
+    let bound_type = match directive.get("typeName") {
+        Some(type_name) if type_name.is_object() => extract_type_name(type_name),
+        _ => "*".to_string(),
+    };
+
+    Some((bound_type, library.to_string()))
+}
+
+/// Record a `(type, library)` using-for binding on a contract
+fn bind_library(
+    data: &mut DiagramData,
+    contract_info: &mut ContractInfo,
+    binding: (String, String),
+) {
+    data.participants.insert(binding.1.clone());
+    data.contract_relationships.push(ContractRelationship {
+        source: contract_info.name.clone(),
+        target: binding.1.clone(),
+        relation_type: "uses".to_string(),
+        order: None,
+    });
+    contract_info.using_for.push(binding);
+}
+
+/// Add the "uses" relationship for a library function called through a file-level binding
+fn record_library_use(data: &mut DiagramData, contract_name: &str, library: &str) {
+    let recorded = data.contract_relationships.iter().any(|rel| {
+        rel.source == contract_name && rel.target == library && rel.relation_type == "uses"
+    });
+    if !recorded && contract_name != library {
+        data.participants.insert(library.to_string());
+        data.contract_relationships.push(ContractRelationship {
+            source: contract_name.to_string(),
+            target: library.to_string(),
+            relation_type: "uses".to_string(),
+            order: None,
+        });
+    }
+}
+
+/// Find the library a member call should be attributed to through a using-for binding
+fn find_using_for_library(
+    contract_name: &str,
+    receiver: &Value,
+    member_name: &str,
+    data: &DiagramData,
+) -> Option<String> {
+    let contract_info = data.contracts.get(contract_name)?;
+    if contract_info.using_for.is_empty() {
+        return None;
+    }
+
+    // Prefer the compiler's type annotation, then fall back to the state variable declaration
+    let receiver_type = receiver
+        .get("typeDescriptions")
+        .and_then(|td| td.get("typeString"))
+        .and_then(|ts| ts.as_str())
+        .map(normalize_type_string)
+        .or_else(|| {
+            let id = receiver["referencedDeclaration"].as_i64()?;
+            data.declaration_types.get(&id).cloned()
+        })
+        .or_else(|| {
+            let name = receiver.get("name").and_then(|n| n.as_str())?;
+            contract_info
+                .variables
+                .iter()
+                .find(|(var, _)| var == name)
+                .map(|(_, var_type)| var_type.clone())
+        })?;
+
+    // Member functions of the receiver's own type take precedence over bound library functions
+    let declared_by = |name: &str| {
+        data.contracts.get(name).map(|info| info.functions.iter().any(|f| f == member_name))
+    };
+
+    contract_info
+        .using_for
+        .iter()
+        .filter(|(bound_type, _)| bound_type == "*" || *bound_type == receiver_type)
+        .find(|(_, library)| match declared_by(library) {
+            Some(declared) => declared,
+            None => declared_by(&receiver_type) != Some(true),
+        })
+        .map(|(_, library)| library.clone())
+}
+
+/// Cache file for a solc run, keyed by the compiler, its arguments and each source's contents
+///
+/// Files the sources import are not part of the key; they are checked against the
+/// `dependencies` recorded in the entry instead.
+fn solc_cache_file(
+    cache_dir: &Path,
+    solc: &Path,
+    file_paths: &[PathBuf],
+    config: &Config,
+) -> Result<PathBuf> {
+    let mut parts = vec![solc.to_string_lossy().into_owned().into_bytes()];
+    parts.extend(config.solc_extra_args.iter().map(|arg| arg.clone().into_bytes()));
+    parts.push(Vec::new());
+    parts.extend(config.remappings.iter().map(|remapping| remapping.clone().into_bytes()));
+    for file_path in file_paths {
+        parts.push(Vec::new());
+        parts.push(file_path.to_string_lossy().into_owned().into_bytes());
+        parts.push(
+            fs::read(file_path)
+                .with_context(|| format!("Failed to read source file: {}", file_path.display()))?,
+        );
+    }
+
+    Ok(cache_dir.join(format!("{}.json", content_hash(&parts))))
+}
+
+/// Content hashes of every source solc read for `output`, imports included
+///
+/// Sources come from solc's `sourceList`, or the `sources` keys when it is missing;
+/// a source that can't be read from the working directory is left out.
+fn source_hashes(output: &Value) -> serde_json::Map<String, Value> {
+    let listed = output["sourceList"]
+        .as_array()
+        .map(|list| list.iter().filter_map(Value::as_str).map(String::from).collect::<Vec<_>>());
+    let sources = listed.unwrap_or_else(|| {
+        output["sources"].as_object().into_iter().flatten().map(|(path, _)| path.clone()).collect()
+    });
+
+    sources
+        .into_iter()
+        .filter_map(|path| {
+            let contents = fs::read(&path).ok()?;
+            Some((path, Value::String(content_hash(&[contents]))))
+        })
+        .collect()
+}
+
+/// Output stored in a cache entry, if every source it recorded is unchanged
+fn cached_output(entry: Value) -> Option<Value> {
+    let dependencies = entry["dependencies"].as_object()?;
+    let unchanged = dependencies.iter().all(|(path, hash)| {
+        fs::read(path).is_ok_and(|contents| hash.as_str() == Some(&content_hash(&[contents])))
+    });
+
+    unchanged.then(|| entry["output"].clone())
+}
+
 /// Process a Solidity file and generate AST JSON
 ///
 /// # Arguments
 ///
 /// * `file_path` - Path to the Solidity file
+/// * `config` - Configuration naming the solc binary and extra arguments
 ///
 /// # Returns
 ///
 /// The AST JSON representation of the Solidity file
-pub fn process_solidity_file(file_path: &str) -> Result<Value> {
+pub fn process_solidity_file(file_path: &str, config: &Config) -> Result<Value> {
+    log::debug!("Compiling single file {}", file_path);
+    process_solidity_files(&[PathBuf::from(file_path)], config)
+}
+
+/// Compile several Solidity files in one solc invocation
+///
+/// A single run lets imports and types resolve across files; the result is one
+/// combined-json with an entry per source.
+pub fn process_solidity_files(file_paths: &[PathBuf], config: &Config) -> Result<Value> {
+    let solc = config.solc_path.clone().unwrap_or_else(|| PathBuf::from("solc"));
+
+    // Reuse the output of an identical earlier run
+    let cache_file = match &config.cache_dir {
+        Some(cache_dir) => Some(solc_cache_file(cache_dir, &solc, file_paths, config)?),
+        None => None,
+    };
+    let cached = cache_file.as_ref().and_then(|file| fs::read_to_string(file).ok());
+    if let Some(ast_json) =
+        cached.and_then(|content| serde_json::from_str(&content).ok()).and_then(cached_output)
+    {
+        log::debug!("Using cached solc output for {} files", file_paths.len());
+        return Ok(ast_json);
+    }
+
+    log::info!(
+        "Compiling {} with {}",
+        file_paths.iter().map(|path| path.display()).join(", "),
+        solc.display()
+    );
+
     // Run solc to generate AST
-    let output = Command::new("solc")
-        .args([
-            "--combined-json",
-            "ast",
-            file_path,
-        ])
-        .output()
-        .with_context(|| format!("Failed to execute solc on {}", file_path))?;
+    let output = Command::new(&solc)
+        .args(&config.solc_extra_args)
+        .args(&config.remappings)
+        .args(["--combined-json", "ast"])
+        .args(file_paths)
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Err(Sol2SeqError::SolcNotFound(solc).into());
+        }
+        Err(error) => {
+            let files = file_paths.iter().map(|path| path.display()).join(", ");
+            let context = format!("Failed to execute {} on {}", solc.display(), files);
+            return Err(anyhow::Error::new(error).context(context));
+        }
+    };
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow::anyhow!("solc failed: {}", stderr));
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        return Err(Sol2SeqError::SolcFailed { solc, stderr }.into());
     }
 
     // Parse the JSON output
     let ast_content = String::from_utf8_lossy(&output.stdout);
-    let ast_json: Value = serde_json::from_str(&ast_content)?;
+    let ast_json: Value = serde_json::from_str(&ast_content)
+        .with_context(|| format!("Failed to parse {} output", solc.display()))?;
+
+    // A cache that can't be written only costs a recompile next time
+    if let Some(cache_file) = &cache_file {
+        if let Some(cache_dir) = cache_file.parent() {
+            let _ = fs::create_dir_all(cache_dir);
+        }
+        let entry = serde_json::json!({
+            "dependencies": source_hashes(&ast_json),
+            "output": ast_json,
+        });
+        let _ = fs::write(cache_file, entry.to_string());
+    }
 
     // The AST is already in the correct format, just return it
     Ok(ast_json)
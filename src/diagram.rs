@@ -1,8 +1,14 @@
-use crate::{ast::extract_contract_info, types::*, utils::*};
-use anyhow::Result;
+use crate::{
+    ast::{extract_contract_info, CALLER},
+    error::Result,
+    types::*,
+    utils::*,
+};
+use anyhow::Context;
+use indexmap::IndexSet;
 use itertools::Itertools;
 use serde_json::Value;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 /// Generate a Mermaid sequence diagram from AST JSON
 pub fn generate_sequence_diagram(ast: &Value, light_colors: bool) -> Result<String> {
@@ -10,6 +16,7 @@ pub fn generate_sequence_diagram(ast: &Value, light_colors: bool) -> Result<Stri
         light_colors,
         output_file: None,
         show_storage_updates: true,
+        ..Default::default()
     };
     generate_sequence_diagram_with_config(ast, config)
 }
@@ -17,74 +24,150 @@ pub fn generate_sequence_diagram(ast: &Value, light_colors: bool) -> Result<Stri
 /// Generate a Mermaid sequence diagram from AST JSON with full configuration
 pub fn generate_sequence_diagram_with_config(ast: &Value, config: crate::Config) -> Result<String> {
     // Extract contract information
-    let data = extract_contract_info(ast, config.show_storage_updates)?;
+    let data = extract_contract_info(ast, &config)?;
 
-    // Generate diagram content
-    let mut diagram = Vec::new();
+    render_diagram(&data, &config)
+}
+
+/// Render extracted contract information in the configured format and diagram type
+pub(crate) fn render_diagram(data: &DiagramData, config: &crate::Config) -> Result<String> {
+    match config.output_format {
+        OutputFormat::Structurizr => return Ok(generate_structurizr(data)),
+        OutputFormat::PlantUml => return Ok(generate_plantuml(data, config)),
+        OutputFormat::Json => {
+            let json = serde_json::to_string_pretty(data).context("Failed to serialize data")?;
+            return Ok(json);
+        }
+        OutputFormat::Mermaid => {}
+    }
+
+    match config.diagram_type {
+        DiagramType::Class => return Ok(render_class_diagram(data, config.effective_theme())),
+        DiagramType::CallGraph => return Ok(render_call_graph(data, config.effective_theme())),
+        DiagramType::Sequence => {}
+    }
+
+    if config.only_public_api {
+        return Ok(generate_public_api_diagram(data, config.effective_theme()));
+    }
 
+    // Generate diagram content
     // Start diagram
-    diagram.push("```mermaid".to_string());
-    diagram.push("sequenceDiagram".to_string());
-    diagram.push("title Smart Contract Interaction Sequence Diagram".to_string());
-    diagram.push("autonumber".to_string());
-    diagram.push("".to_string());
+    let mut diagram = vec![
+        "```mermaid".to_string(),
+        "sequenceDiagram".to_string(),
+        format!("title {}", diagram_title(config)),
+        "autonumber".to_string(),
+        "".to_string(),
+    ];
 
     // Add visual styling with theme
-    add_theme_config(&mut diagram, config.light_colors);
+    let theme = config.effective_theme();
+    add_theme_config(&mut diagram, theme);
 
     // Format participants for the diagram - ensure User is first
-    let ordered_participants = order_participants(&data.participants);
+    let ordered_participants = order_participants(&data.participants, config.participant_order);
 
     // Create the participant declarations with descriptions
-    add_participants(&mut diagram, &ordered_participants, &data.contracts);
+    add_participants(&mut diagram, &ordered_participants, &data.contracts, config);
+
+    // Section notes hang off the outer lifelines, which are only `User` and `Events` when used
+    let first = ordered_participants.first().map_or("User", String::as_str);
+    let last = ordered_participants.last().map_or(first, String::as_str);
 
     // Add a blank line
     diagram.push("".to_string());
 
     // Add title and section separators
-    add_section_title(&mut diagram, "User Interactions", config.light_colors);
+    add_section_title(&mut diagram, first, "User Interactions", theme);
 
-    // Add user interactions
-    diagram.extend(data.user_interactions);
+    // Add user interactions, with entrypoint bodies spliced in when inlining
+    let (user_interactions, inlined) = entrypoint_flow(data, config);
+    let mut budget = config.max_interactions.unwrap_or(usize::MAX);
+    let mut omitted = 0;
+    diagram.extend(truncate_interactions(&user_interactions, &mut budget, &mut omitted));
 
     // Add contract interactions
-    if !data.contract_interactions.is_empty() {
+    let contract_interactions: Vec<(&String, &Vec<String>)> = data
+        .contract_interactions
+        .iter()
+        .filter(|(function_key, _)| !inlined.contains(function_key.as_str()))
+        .collect();
+    if !contract_interactions.is_empty() {
         diagram.push("".to_string());
-        add_section_title(&mut diagram, "Contract-to-Contract Interactions", config.light_colors);
+        add_section_title(&mut diagram, first, "Contract-to-Contract Interactions", theme);
 
         // Add contract interactions grouped by function
-        for (function_key, interactions_list) in data.contract_interactions.iter() {
+        for (function_key, interactions_list) in contract_interactions {
+            let interactions_list =
+                truncate_interactions(interactions_list, &mut budget, &mut omitted);
             if !interactions_list.is_empty() {
                 let parts: Vec<&str> = function_key.split('.').collect();
                 if parts.len() == 2 {
                     let (contract, function) = (parts[0], parts[1]);
                     diagram.push(format!("Note right of {}: Processing {}", contract, function));
-                    diagram.extend(interactions_list.clone());
+                    diagram.extend(interactions_list);
                     diagram.push("".to_string()); // Add spacing
                 }
             }
         }
     }
 
+    if omitted > 0 {
+        diagram.push(format!(
+            "Note over {}: diagram truncated ({} interactions omitted)",
+            first, omitted
+        ));
+    }
+
     // Add event notes
-    if !data.events.is_empty() {
+    if config.show_events_section && !data.events.is_empty() {
         diagram.push("".to_string());
-        add_section_title(&mut diagram, "Event Definitions", config.light_colors);
+        add_section_title(&mut diagram, first, "Event Definitions", theme);
 
         for (contract, event) in &data.events {
-            diagram.push(format!("Note over {},{}: Event: {}", contract, contract, event));
+            let signature = event_signature(data, contract, event);
+            diagram.push(format!("Note over {},{}: Event: {}", contract, contract, signature));
+        }
+    }
+
+    // Add custom error notes
+    if config.show_errors_section && !data.errors.is_empty() {
+        diagram.push("".to_string());
+        add_section_title(&mut diagram, first, "Error Definitions", theme);
+
+        for (contract, error) in &data.errors {
+            let signature = error_signature(data, contract, error);
+            diagram.push(format!("Note over {},{}: Error: {}", contract, contract, signature));
         }
     }
 
+    // Add file-level constants
+    if !data.constants.is_empty() {
+        diagram.push("".to_string());
+        let constants =
+            data.constants.iter().map(|(name, value)| format!("{} = {}", name, value)).join(", ");
+        diagram.push(format!("Note over {},{}: Constants: {}", first, last, constants));
+    }
+
     // Add contract overview/relationships
-    if !data.contracts.is_empty() {
+    if config.show_relationships_section && !data.contracts.is_empty() {
         diagram.push("".to_string());
-        add_section_title(&mut diagram, "Contract Relationships", config.light_colors);
+        add_section_title(&mut diagram, first, "Contract Relationships", theme);
+
+        // Emit contracts by name so the output is stable across runs
+        let contracts: Vec<(&String, &ContractInfo)> =
+            data.contracts.iter().sorted_by(|a, b| a.0.cmp(b.0)).collect();
 
         // Add function summaries
-        for (contract_name, info) in &data.contracts {
-            if !info.functions.is_empty() {
-                let functions_str = info.functions.join(", ");
+        for (contract_name, info) in &contracts {
+            let inherited = info
+                .inherited_functions
+                .iter()
+                .map(|(function, base)| format!("{} (inherited from {})", function, base));
+            let functions: Vec<String> = info.functions.iter().cloned().chain(inherited).collect();
+            if !functions.is_empty() {
+                let functions_str = functions.join(", ");
                 diagram.push(format!("Note over {}: Functions: {}", contract_name, functions_str));
             }
         }
@@ -92,9 +175,16 @@ pub fn generate_sequence_diagram_with_config(ast: &Value, config: crate::Config)
         diagram.push("".to_string());
 
         // Add inheritance relationships
-        for (contract_name, info) in &data.contracts {
+        for (contract_name, info) in &contracts {
             if !info.inherits_from.is_empty() {
-                let bases_str = info.inherits_from.join(", ");
+                let bases_str = info
+                    .inherits_from
+                    .iter()
+                    .map(|base| match info.base_arguments.get(base) {
+                        Some(arguments) => format!("{}({})", base, arguments.join(", ")),
+                        None => base.clone(),
+                    })
+                    .join(", ");
                 diagram
                     .push(format!("Note right of {}: Inherits from: {}", contract_name, bases_str));
             }
@@ -102,7 +192,7 @@ pub fn generate_sequence_diagram_with_config(ast: &Value, config: crate::Config)
 
         // Add contract type information
         diagram.push("".to_string());
-        for (contract_name, info) in &data.contracts {
+        for (contract_name, info) in &contracts {
             if info.contract_type != "contract" {
                 diagram
                     .push(format!("Note right of {}: Type: {}", contract_name, info.contract_type));
@@ -114,7 +204,11 @@ pub fn generate_sequence_diagram_with_config(ast: &Value, config: crate::Config)
             diagram.push("".to_string());
             let mut seen_relationships = HashSet::new();
 
-            for rel in &data.contract_relationships {
+            let relationships = data
+                .contract_relationships
+                .iter()
+                .sorted_by(|a, b| (&a.source, &a.target).cmp(&(&b.source, &b.target)));
+            for rel in relationships {
                 if rel.relation_type == "calls"
                     && data.participants.contains(&rel.source)
                     && data.participants.contains(&rel.target)
@@ -133,36 +227,559 @@ pub fn generate_sequence_diagram_with_config(ast: &Value, config: crate::Config)
     }
 
     // Add a legend at the end
-    add_legend(&mut diagram, config.light_colors);
+    if config.show_legend {
+        add_legend(&mut diagram, first, theme);
+    }
+
+    // Punctuation in types, literals and names must not end a line early
+    let diagram = diagram.iter().map(|line| escape_line(line)).collect();
+
+    // Mermaid rejects deactivating a participant that is not active
+    let mut diagram = balance_activations(diagram);
 
     // Close the diagram
     diagram.push("```".to_string());
 
+    // Add the call matrix after the diagram
+    if config.emit_call_matrix {
+        diagram.push("".to_string());
+        diagram.push(generate_call_matrix(data));
+    }
+
     Ok(diagram.join("\n"))
 }
 
+/// Generate a Mermaid class diagram of contracts, their members and relationships
+pub fn generate_class_diagram(ast: &Value, config: crate::Config) -> Result<String> {
+    let data = extract_contract_info(ast, &config)?;
+    Ok(render_class_diagram(&data, config.effective_theme()))
+}
+
+/// Render contracts as classes with `<|--` inheritance, `-->` references/calls and `..>` uses
+///
+/// Only relationships between contracts defined in the analyzed sources are drawn.
+fn render_class_diagram(data: &DiagramData, theme: Option<Theme>) -> String {
+    let mut diagram = vec!["```mermaid".to_string(), "classDiagram".to_string()];
+
+    add_theme_config(&mut diagram, theme);
+
+    for (contract_name, info) in data.contracts.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+        diagram.push(format!("class {} {{", contract_name));
+        if matches!(info.contract_type.as_str(), "interface" | "library" | "abstract") {
+            diagram.push(format!("    <<{}>>", info.contract_type));
+        }
+        for (name, var_type) in &info.variables {
+            diagram.push(format!("    {} {}", class_member_type(var_type), name));
+        }
+        for function in &info.function_details {
+            let marker = match function.visibility.as_str() {
+                "public" | "external" => "+",
+                "internal" => "#",
+                "private" => "-",
+                _ => "",
+            };
+            let params = function
+                .parameters
+                .iter()
+                .map(|p| {
+                    format!("{} {}", class_member_type(&p.param_type), p.name).trim().to_string()
+                })
+                .join(", ");
+            let returns =
+                function.returns.iter().map(|p| class_member_type(&p.param_type)).join(", ");
+            diagram.push(
+                format!("    {}{}({}) {}", marker, function.name, params, returns)
+                    .trim_end()
+                    .to_string(),
+            );
+        }
+        diagram.push("}".to_string());
+    }
+
+    diagram.push("".to_string());
+
+    let relationships = data
+        .contract_relationships
+        .iter()
+        .filter(|rel| {
+            data.contracts.contains_key(&rel.source) && data.contracts.contains_key(&rel.target)
+        })
+        .map(|rel| match rel.relation_type.as_str() {
+            "inherits" => format!("{} <|-- {}", rel.target, rel.source),
+            "uses" => format!("{} ..> {} : uses", rel.source, rel.target),
+            other => format!("{} --> {} : {}", rel.source, rel.target, other),
+        })
+        .unique();
+    diagram.extend(relationships);
+
+    diagram.push("```".to_string());
+    diagram.join("\n")
+}
+
+/// Write a type as a Mermaid class member type, e.g. `mapping~address,uint256~`
+///
+/// Mermaid reads any member containing parentheses as a method, so mappings become generics
+/// and other parentheses (tuples, function types) become `~` too.
+fn class_member_type(type_name: &str) -> String {
+    if let Some(inner) = type_name.strip_prefix("mapping(").and_then(|t| t.strip_suffix(')')) {
+        if let Some((key, value)) = inner.split_once("=>") {
+            return format!(
+                "mapping~{},{}~",
+                class_member_type(key.trim()),
+                class_member_type(value.trim())
+            );
+        }
+    }
+
+    type_name.replace(['(', ')'], "~")
+}
+
+/// Generate a Mermaid flowchart of `Contract.function` nodes and the calls between them
+pub fn generate_call_graph(ast: &Value, config: crate::Config) -> Result<String> {
+    let data = extract_contract_info(ast, &config)?;
+    Ok(render_call_graph(&data, config.effective_theme()))
+}
+
+/// Render `call_graph` edges as a left-to-right flowchart with one subgraph per contract
+fn render_call_graph(data: &DiagramData, theme: Option<Theme>) -> String {
+    let mut diagram = vec!["```mermaid".to_string(), "flowchart LR".to_string()];
+
+    add_theme_config(&mut diagram, theme);
+
+    // Group the functions appearing in the graph by contract
+    let nodes = data.call_graph.iter().flat_map(|(caller, callee)| [caller, callee]).unique();
+    let by_contract = nodes
+        .filter_map(|node| node.rsplit_once('.').map(|(contract, _)| (contract, node)))
+        .into_group_map();
+
+    // Ids are assigned by position so distinct names never share a node
+    let mut node_ids: HashMap<&str, String> = HashMap::new();
+    for (index, (contract, functions)) in
+        by_contract.iter().sorted_by(|a, b| a.0.cmp(b.0)).enumerate()
+    {
+        diagram.push(format!("    subgraph c{}[\"{}\"]", index, contract));
+        for function in functions.iter().sorted() {
+            let id = format!("f{}", node_ids.len());
+            diagram.push(format!("        {}[\"{}\"]", id, function));
+            node_ids.insert(function.as_str(), id);
+        }
+        diagram.push("    end".to_string());
+    }
+
+    diagram.push("".to_string());
+
+    for (caller, callee) in data.call_graph.iter().unique() {
+        let (from, to) = (&node_ids[caller.as_str()], &node_ids[callee.as_str()]);
+        diagram.push(format!("    {} --> {}", from, to));
+    }
+
+    diagram.push("```".to_string());
+    diagram.join("\n")
+}
+
+/// Generate a PlantUML sequence diagram from the same interactions as the Mermaid output
+///
+/// Interaction lines are recorded in Mermaid syntax and translated line by line;
+/// `loop`/`alt`/`else`/`end` blocks carry over unchanged.
+pub fn generate_plantuml(data: &DiagramData, config: &crate::Config) -> String {
+    let mut diagram = vec![
+        "@startuml".to_string(),
+        format!("title {}", diagram_title(config)),
+        "autonumber".to_string(),
+        "".to_string(),
+    ];
+
+    let ordered_participants = order_participants(&data.participants, config.participant_order);
+    let mut participants = Vec::new();
+    add_participants(&mut participants, &ordered_participants, &data.contracts, config);
+    diagram.extend(participants.iter().map(|line| match line.strip_prefix("box ") {
+        Some(file) => format!("box \"{}\"", file),
+        None if line == "end" => "end box".to_string(),
+        None => to_plantuml_participant(line),
+    }));
+    diagram.push("".to_string());
+
+    diagram.push("== User Interactions ==".to_string());
+    let (user_interactions, inlined) = entrypoint_flow(data, config);
+    let mut budget = config.max_interactions.unwrap_or(usize::MAX);
+    let mut omitted = 0;
+    let user_interactions = truncate_interactions(&user_interactions, &mut budget, &mut omitted);
+    diagram.extend(user_interactions.iter().map(|line| to_plantuml_line(line)));
+
+    let contract_interactions: Vec<(&String, &Vec<String>)> = data
+        .contract_interactions
+        .iter()
+        .filter(|(function_key, _)| !inlined.contains(function_key.as_str()))
+        .collect();
+    if !contract_interactions.is_empty() {
+        diagram.push("".to_string());
+        diagram.push("== Contract-to-Contract Interactions ==".to_string());
+
+        for (function_key, interactions_list) in contract_interactions {
+            let interactions_list =
+                truncate_interactions(interactions_list, &mut budget, &mut omitted);
+            if let Some((contract, function)) = function_key.split_once('.') {
+                if !interactions_list.is_empty() {
+                    diagram.push(format!("note right of {} : Processing {}", contract, function));
+                    diagram.extend(interactions_list.iter().map(|line| to_plantuml_line(line)));
+                    diagram.push("".to_string());
+                }
+            }
+        }
+    }
+
+    if omitted > 0 {
+        let first = ordered_participants.first().map_or("User", String::as_str);
+        diagram.push(format!(
+            "note over {} : diagram truncated ({} interactions omitted)",
+            first, omitted
+        ));
+    }
+
+    if config.show_events_section && !data.events.is_empty() {
+        diagram.push("".to_string());
+        diagram.push("== Event Definitions ==".to_string());
+
+        for (contract, event) in &data.events {
+            let signature = event_signature(data, contract, event);
+            diagram.push(format!("note over {} : Event: {}", contract, signature));
+        }
+    }
+
+    if config.show_errors_section && !data.errors.is_empty() {
+        diagram.push("".to_string());
+        diagram.push("== Error Definitions ==".to_string());
+
+        for (contract, error) in &data.errors {
+            let signature = error_signature(data, contract, error);
+            diagram.push(format!("note over {} : Error: {}", contract, signature));
+        }
+    }
+
+    diagram.push("@enduml".to_string());
+    diagram.join("\n")
+}
+
+/// User interaction lines, with each entrypoint's body after its call arrow if inlining
+///
+/// Also returns the `Contract.function` keys whose bodies were spliced in, which the
+/// Contract-to-Contract section then skips.
+fn entrypoint_flow<'a>(
+    data: &'a DiagramData,
+    config: &crate::Config,
+) -> (Vec<String>, HashSet<&'a str>) {
+    let mut inlined = HashSet::new();
+    if !config.inline_bodies {
+        return (data.user_interactions.clone(), inlined);
+    }
+
+    let mut lines = Vec::new();
+    for line in &data.user_interactions {
+        lines.push(line.clone());
+
+        let body = data
+            .entrypoint_keys
+            .get(line)
+            .and_then(|function_key| data.contract_interactions.get_key_value(function_key));
+        if let Some((key, body)) = body {
+            if inlined.insert(key.as_str()) {
+                lines.extend(body.iter().cloned());
+            }
+        }
+    }
+
+    (lines, inlined)
+}
+
+/// Format an event with its declared parameters, e.g. `Transfer(from: address, value: uint256)`
+fn event_signature(data: &DiagramData, contract: &str, event: &str) -> String {
+    let parameters = data.contracts.get(contract).and_then(|info| info.event_parameters.get(event));
+    definition_signature(event, parameters)
+}
+
+/// Format a custom error with its declared parameters, e.g. `Unauthorized(caller: address)`
+fn error_signature(data: &DiagramData, contract: &str, error: &str) -> String {
+    let parameters = data.contracts.get(contract).and_then(|info| info.error_parameters.get(error));
+    definition_signature(error, parameters)
+}
+
+/// Format `name(p: type, ...)` from an event or error definition's parameters
+fn definition_signature(name: &str, parameters: Option<&Vec<Parameter>>) -> String {
+    let parameters = parameters
+        .map(|parameters| {
+            parameters.iter().map(|p| format!("{}: {}", p.name, p.param_type)).join(", ")
+        })
+        .unwrap_or_default();
+    format!("{}({})", name, parameters)
+}
+
+/// Translate a Mermaid `participant X as "label"` declaration to PlantUML
+fn to_plantuml_participant(line: &str) -> String {
+    match line.strip_prefix("participant ").and_then(|rest| rest.split_once(" as ")) {
+        Some((name, label)) => {
+            format!("participant {} as {}", label.replace("<br/>", "\\n"), name)
+        }
+        None => line.to_string(),
+    }
+}
+
+/// Translate a single Mermaid interaction line to PlantUML
+fn to_plantuml_line(line: &str) -> String {
+    let line = line.trim();
+
+    if let Some(rest) = line.strip_prefix("Note ") {
+        if let Some((position, text)) = rest.split_once(": ") {
+            return format!("note {} : {}", position, text);
+        }
+    }
+
+    let Some(arrow_start) = line.find("->>") else {
+        return line.to_string();
+    };
+
+    // `-->>` is a dashed reply, `->>` a solid call
+    let (source, arrow) = match line[..arrow_start].strip_suffix('-') {
+        Some(source) => (source, "-->"),
+        None => (&line[..arrow_start], "->"),
+    };
+    let rest = &line[arrow_start + 3..];
+    let (target, message) = rest.split_once(':').unwrap_or((rest, ""));
+
+    // Mermaid `+`/`-` activation markers map to PlantUML `++`/`--`
+    let (target, activation) = match (target.strip_prefix('+'), target.strip_prefix('-')) {
+        (Some(target), _) => (target, " ++"),
+        (_, Some(target)) => (target, " --"),
+        _ => (target, ""),
+    };
+
+    format!("{} {} {}{} : {}", source, arrow, target, activation, message.trim())
+}
+
+/// Generate a Structurizr DSL workspace with each contract as a component
+///
+/// Relationships are taken from `contract_relationships` and labeled by their type;
+/// only relationships between known contracts are emitted.
+pub fn generate_structurizr(data: &DiagramData) -> String {
+    // Prefixed ids cannot clash with `system`/`onchain`; names that sanitize to the same id
+    // (e.g. `A$B` and `A_B`) get a numeric suffix
+    let contracts: Vec<(&String, &ContractInfo)> =
+        data.contracts.iter().sorted_by(|a, b| a.0.cmp(b.0)).collect();
+    let mut used = HashSet::new();
+    let mut ids: HashMap<&str, String> = HashMap::new();
+    for (contract_name, _) in &contracts {
+        let sanitized: String = contract_name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let base = format!("c_{}", sanitized);
+        let mut id = base.clone();
+        let mut suffix = 2;
+        while !used.insert(id.clone()) {
+            id = format!("{}_{}", base, suffix);
+            suffix += 1;
+        }
+        ids.insert(contract_name.as_str(), id);
+    }
+
+    let mut dsl = vec![
+        "workspace \"Smart Contracts\" {".to_string(),
+        "    model {".to_string(),
+        "        system = softwareSystem \"Smart Contracts\" {".to_string(),
+        "            onchain = container \"On-chain Contracts\" {".to_string(),
+    ];
+
+    for (contract_name, info) in &contracts {
+        dsl.push(format!(
+            "                {} = component \"{}\" \"{}\" \"{}\"",
+            ids[contract_name.as_str()],
+            contract_name,
+            info.source_file,
+            info.contract_type
+        ));
+    }
+
+    dsl.push("            }".to_string());
+    dsl.push("        }".to_string());
+    dsl.push("".to_string());
+
+    // Structurizr rejects duplicate relationships with the same description
+    let relationships = data
+        .contract_relationships
+        .iter()
+        .filter(|rel| {
+            data.contracts.contains_key(&rel.source) && data.contracts.contains_key(&rel.target)
+        })
+        .map(|rel| (&rel.source, &rel.target, &rel.relation_type))
+        .unique()
+        .sorted();
+
+    for (source, target, relation_type) in relationships {
+        dsl.push(format!(
+            "        {} -> {} \"{}\"",
+            ids[source.as_str()],
+            ids[target.as_str()],
+            relation_type
+        ));
+    }
+
+    dsl.push("    }".to_string());
+    dsl.push("".to_string());
+    dsl.push("    views {".to_string());
+    dsl.push("        component onchain {".to_string());
+    dsl.push("            include *".to_string());
+    dsl.push("            autolayout lr".to_string());
+    dsl.push("        }".to_string());
+    dsl.push("    }".to_string());
+    dsl.push("}".to_string());
+
+    dsl.join("\n")
+}
+
+/// Generate a Markdown matrix of caller contracts (rows) by callee contracts (columns)
+///
+/// Each cell counts the cross-contract call sites recorded in the call graph.
+pub fn generate_call_matrix(data: &DiagramData) -> String {
+    let calls: Vec<(&str, &str)> = data
+        .call_graph
+        .iter()
+        .filter_map(|(caller, callee)| Some((caller.split_once('.')?.0, callee.split_once('.')?.0)))
+        .filter(|(caller, callee)| caller != callee)
+        .collect();
+
+    let callers: Vec<&str> = calls.iter().map(|(caller, _)| *caller).unique().sorted().collect();
+    let callees: Vec<&str> = calls.iter().map(|(_, callee)| *callee).unique().sorted().collect();
+
+    let mut matrix = vec!["## Call Matrix".to_string(), "".to_string()];
+
+    let header = callees.iter().map(|callee| format!(" {} |", callee)).join("");
+    matrix.push(format!("| Caller \\ Callee |{}", header));
+    matrix.push(format!("| --- |{}", " --- |".repeat(callees.len())));
+
+    for caller in &callers {
+        let cells = callees
+            .iter()
+            .map(|callee| {
+                let count = calls.iter().filter(|call| call == &&(*caller, *callee)).count();
+                format!(" {} |", count)
+            })
+            .join("");
+        matrix.push(format!("| {} |{}", caller, cells));
+    }
+
+    matrix.join("\n")
+}
+
+/// Generate a compact diagram listing each contract's callable function signatures
+fn generate_public_api_diagram(data: &DiagramData, theme: Option<Theme>) -> String {
+    let mut diagram = vec![
+        "```mermaid".to_string(),
+        "sequenceDiagram".to_string(),
+        "title Smart Contract Public API".to_string(),
+        "".to_string(),
+    ];
+
+    add_theme_config(&mut diagram, theme);
+
+    // Only contracts exposing at least one callable function get a lifeline
+    let api_contracts: Vec<(&String, Vec<&FunctionInfo>)> = data
+        .contracts
+        .iter()
+        .sorted_by(|a, b| a.0.cmp(b.0))
+        .map(|(name, info)| {
+            let callable = info
+                .function_details
+                .iter()
+                .filter(|f| f.kind != "constructor")
+                .filter(|f| f.visibility == "public" || f.visibility == "external")
+                .collect::<Vec<_>>();
+            (name, callable)
+        })
+        .filter(|(_, callable)| !callable.is_empty())
+        .collect();
+
+    for (contract_name, _) in &api_contracts {
+        diagram.push(format!("participant {}", contract_name));
+    }
+    diagram.push("".to_string());
+
+    for (contract_name, callable) in &api_contracts {
+        for function in callable {
+            diagram.push(format!("Note over {}: {}", contract_name, format_signature(function)));
+        }
+    }
+
+    diagram.push("```".to_string());
+    diagram.join("\n")
+}
+
+/// Format a function as `name(type name, ...) visibility [mutability] [returns (...)]`
+fn format_signature(function: &FunctionInfo) -> String {
+    let format_params = |params: &[Parameter]| {
+        params
+            .iter()
+            .map(|p| {
+                if p.name.is_empty() {
+                    p.param_type.clone()
+                } else {
+                    format!("{} {}", p.param_type, p.name)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut signature =
+        format!("{}({}) {}", function.name, format_params(&function.parameters), function.visibility);
+
+    if function.state_mutability != "nonpayable" {
+        signature.push_str(&format!(" {}", function.state_mutability));
+    }
+
+    if !function.returns.is_empty() {
+        signature.push_str(&format!(" returns ({})", format_params(&function.returns)));
+    }
+
+    signature
+}
+
 /// Add theme configuration to the diagram
-fn add_theme_config(diagram: &mut Vec<String>, light_colors: bool) {
+fn add_theme_config(diagram: &mut Vec<String>, theme: Option<Theme>) {
+    let Some(theme) = theme else {
+        return;
+    };
+
     diagram.push("%%{init: {".to_string());
     diagram.push("  'theme': 'base',".to_string());
     diagram.push("  'themeVariables': {".to_string());
 
-    if light_colors {
-        // Lighter theme
-        diagram.push("    'primaryColor': '#fafbfc',".to_string());
-        diagram.push("    'primaryTextColor': '#444',".to_string());
-        diagram.push("    'primaryBorderColor': '#e1e4e8',".to_string());
-        diagram.push("    'lineColor': '#a0aec0',".to_string());
-        diagram.push("    'secondaryColor': '#f5fbff',".to_string());
-        diagram.push("    'tertiaryColor': '#fff8f8'".to_string());
-    } else {
-        // Default theme
-        diagram.push("    'primaryColor': '#f5f5f5',".to_string());
-        diagram.push("    'primaryTextColor': '#333',".to_string());
-        diagram.push("    'primaryBorderColor': '#999',".to_string());
-        diagram.push("    'lineColor': '#666',".to_string());
-        diagram.push("    'secondaryColor': '#f0f8ff',".to_string());
-        diagram.push("    'tertiaryColor': '#fff5f5'".to_string());
+    match theme {
+        Theme::Light => {
+            // Lighter theme
+            diagram.push("    'primaryColor': '#fafbfc',".to_string());
+            diagram.push("    'primaryTextColor': '#444',".to_string());
+            diagram.push("    'primaryBorderColor': '#e1e4e8',".to_string());
+            diagram.push("    'lineColor': '#a0aec0',".to_string());
+            diagram.push("    'secondaryColor': '#f5fbff',".to_string());
+            diagram.push("    'tertiaryColor': '#fff8f8'".to_string());
+        }
+        Theme::Dark => {
+            // Dark backgrounds with light text
+            diagram.push("    'primaryColor': '#1f2937',".to_string());
+            diagram.push("    'primaryTextColor': '#e5e7eb',".to_string());
+            diagram.push("    'primaryBorderColor': '#4b5563',".to_string());
+            diagram.push("    'lineColor': '#9ca3af',".to_string());
+            diagram.push("    'secondaryColor': '#111827',".to_string());
+            diagram.push("    'tertiaryColor': '#2d1f2f'".to_string());
+        }
+        Theme::Default => {
+            // Default theme
+            diagram.push("    'primaryColor': '#f5f5f5',".to_string());
+            diagram.push("    'primaryTextColor': '#333',".to_string());
+            diagram.push("    'primaryBorderColor': '#999',".to_string());
+            diagram.push("    'lineColor': '#666',".to_string());
+            diagram.push("    'secondaryColor': '#f0f8ff',".to_string());
+            diagram.push("    'tertiaryColor': '#fff5f5'".to_string());
+        }
     }
 
     diagram.push("  }".to_string());
@@ -170,8 +787,142 @@ fn add_theme_config(diagram: &mut Vec<String>, light_colors: bool) {
     diagram.push("".to_string());
 }
 
+/// Keep whole groups of lines while they fit in the interaction budget
+///
+/// A group ends after an interaction once every `alt`/`loop` block is closed and every
+/// activation returned, so truncating between groups keeps the Mermaid output valid.
+/// Once a group has been omitted, every later group is omitted as well.
+fn truncate_interactions(lines: &[String], budget: &mut usize, omitted: &mut usize) -> Vec<String> {
+    let mut kept = Vec::new();
+    let mut group = Vec::new();
+    let mut group_interactions = 0;
+    let mut blocks = 0usize;
+    let mut activations = 0usize;
+
+    let mut flush = |group: &mut Vec<String>, group_interactions: usize| {
+        if *omitted == 0 && group_interactions <= *budget {
+            *budget -= group_interactions;
+            kept.append(group);
+        } else {
+            *omitted += group_interactions;
+            group.clear();
+        }
+    };
+
+    for line in lines {
+        let trimmed = line.trim();
+        let keyword = trimmed.split_whitespace().next().unwrap_or("");
+        if matches!(keyword, "alt" | "loop" | "opt" | "par" | "critical" | "break" | "rect") {
+            blocks += 1;
+        } else if trimmed == "end" {
+            blocks = blocks.saturating_sub(1);
+        }
+        if trimmed.contains("->>") {
+            group_interactions += 1;
+            if trimmed.contains("->>+") {
+                activations += 1;
+            } else if trimmed.contains("-->>-") {
+                activations = activations.saturating_sub(1);
+            }
+        }
+        group.push(line.clone());
+
+        if blocks == 0 && activations == 0 && group_interactions > 0 {
+            flush(&mut group, group_interactions);
+            group_interactions = 0;
+        }
+    }
+    flush(&mut group, group_interactions);
+
+    kept
+}
+
+/// Escape the free text of a participant label, note or message line
+fn escape_line(line: &str) -> String {
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let trimmed = line.trim_start();
+
+    if let Some((name, label)) = trimmed
+        .strip_prefix("participant ")
+        .and_then(|rest| rest.split_once(" as \""))
+        .and_then(|(name, label)| Some((name, label.strip_suffix('"')?)))
+    {
+        return format!("{}participant {} as \"{}\"", indent, name, escape_mermaid(label));
+    }
+
+    if trimmed.starts_with("Note ") || parse_message(trimmed).is_some() {
+        if let Some((head, text)) = trimmed.split_once(':') {
+            return format!("{}{}:{}", indent, head, escape_mermaid(text));
+        }
+    }
+
+    line.to_string()
+}
+
+/// Split a Mermaid message line into its sender, arrow and receiver
+fn parse_message(line: &str) -> Option<(&str, &str, &str)> {
+    let head = line.trim().split_once(':')?.0;
+    for arrow in ["-->>-", "-->>+", "->>-", "->>+", "-->>", "->>"] {
+        if let Some((from, to)) = head.split_once(arrow) {
+            return Some((from.trim(), arrow, to.trim()));
+        }
+    }
+    None
+}
+
+/// Insert missing deactivations and drop stray ones so activations stay balanced
+///
+/// Activations are tracked per participant in line order, the way Mermaid does. Every
+/// branch of an `alt`/`loop` block is closed back to the activations open when the block
+/// started, and a `-` marker on an inactive sender is removed.
+fn balance_activations(lines: Vec<String>) -> Vec<String> {
+    let mut balanced = Vec::with_capacity(lines.len());
+    let mut active: HashMap<String, usize> = HashMap::new();
+    let mut blocks: Vec<HashMap<String, usize>> = Vec::new();
+
+    for line in lines {
+        let trimmed = line.trim();
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let keyword = trimmed.split_whitespace().next().unwrap_or("");
+
+        if matches!(keyword, "alt" | "loop" | "opt" | "par" | "critical" | "break" | "rect") {
+            blocks.push(active.clone());
+        } else if matches!(keyword, "else" | "and" | "end") {
+            if let Some(start) = blocks.last() {
+                for (participant, count) in active.iter_mut().sorted() {
+                    let open = start.get(participant).copied().unwrap_or(0);
+                    while *count > open {
+                        balanced.push(format!("{}    deactivate {}", indent, participant));
+                        *count -= 1;
+                    }
+                }
+            }
+            if keyword == "end" {
+                blocks.pop();
+            }
+        } else if let Some((from, arrow, to)) = parse_message(trimmed) {
+            if arrow.ends_with('-') {
+                match active.get_mut(from).filter(|count| **count > 0) {
+                    Some(count) => *count -= 1,
+                    None => {
+                        let fixed = line.replacen(arrow, arrow.trim_end_matches('-'), 1);
+                        balanced.push(fixed);
+                        continue;
+                    }
+                }
+            } else if arrow.ends_with('+') {
+                *active.entry(to.to_string()).or_insert(0) += 1;
+            }
+        }
+
+        balanced.push(line);
+    }
+
+    balanced
+}
+
 /// Order participants in a logical sequence
-fn order_participants(participants: &HashSet<String>) -> Vec<String> {
+fn order_participants(participants: &IndexSet<String>, order: ParticipantOrder) -> Vec<String> {
     let mut ordered = Vec::new();
 
     // User always first
@@ -179,8 +930,12 @@ fn order_participants(participants: &HashSet<String>) -> Vec<String> {
         ordered.push("User".to_string());
     }
 
-    // Then add other participants in sorted order (except Events which comes last)
-    for participant in participants.iter().sorted() {
+    // Then add other participants (except Events which comes last)
+    let others: Vec<&String> = match order {
+        ParticipantOrder::Alphabetical => participants.iter().sorted().collect(),
+        ParticipantOrder::FirstSeen => participants.iter().collect(),
+    };
+    for participant in others {
         if participant != "User" && participant != "Events" {
             ordered.push(participant.clone());
         }
@@ -195,108 +950,196 @@ fn order_participants(participants: &HashSet<String>) -> Vec<String> {
 }
 
 /// Add participants to the diagram
+///
+/// With `group_by_source_file`, contracts from the same file are declared together inside
+/// a `box <file>` block at the position of the first of them; synthetic participants and
+/// contracts of unknown origin stay outside any box.
 fn add_participants(
     diagram: &mut Vec<String>,
     ordered_participants: &[String],
     contracts: &std::collections::HashMap<String, ContractInfo>,
+    config: &crate::Config,
 ) {
+    let source_file = |participant: &String| {
+        contracts
+            .get(participant)
+            .map(|info| info.source_file.as_str())
+            .filter(|file| !file.is_empty() && *file != "unknown")
+            .filter(|_| config.group_by_source_file)
+    };
+
+    let mut boxed_files = HashSet::new();
     for participant in ordered_participants {
-        if participant == "User" {
-            diagram.push("participant User as \"External User\"".to_string());
-        } else if participant == "Events" {
-            diagram.push("participant Events as \"Blockchain Events\"".to_string());
-        } else if participant == "TokenContract" {
-            diagram.push("participant TokenContract as \"ERC20/ERC721 Tokens\"".to_string());
-        } else {
-            // Add contract description if available
-            if let Some(contract_info) = contracts.get(participant) {
-                // Extract key state variables for description
-                let key_vars: Vec<&(String, String)> = contract_info
-                    .variables
-                    .iter()
-                    .filter(|(name, _)| is_important_variable(name))
-                    .collect();
+        let Some(file) = source_file(participant) else {
+            diagram.push(participant_declaration(participant, contracts, config));
+            continue;
+        };
+        if boxed_files.insert(file) {
+            diagram.push(format!("box {}", file));
+            for member in ordered_participants.iter().filter(|p| source_file(p) == Some(file)) {
+                diagram.push(participant_declaration(member, contracts, config));
+            }
+            diagram.push("end".to_string());
+        }
+    }
+}
 
-                let mut description_parts = Vec::new();
+/// Declare a participant, describing contracts by type, notice, key variables and file
+fn participant_declaration(
+    participant: &String,
+    contracts: &std::collections::HashMap<String, ContractInfo>,
+    config: &crate::Config,
+) -> String {
+    if participant == "User" {
+        return "participant User as \"External User\"".to_string();
+    } else if participant == "Events" {
+        return "participant Events as \"Blockchain Events\"".to_string();
+    } else if participant == CALLER && !contracts.contains_key(participant) {
+        return format!("participant {} as \"Caller (msg.sender)\"", CALLER);
+    } else if *participant == config.token_participant && !contracts.contains_key(participant) {
+        return format!("participant {} as \"{}\"", participant, config.token_participant_label);
+    } else if *participant == config.recipient_participant && !contracts.contains_key(participant)
+    {
+        return format!(
+            "participant {} as \"{}\"",
+            participant, config.recipient_participant_label
+        );
+    }
 
-                // Add contract name (always)
-                description_parts.push(participant.clone());
+    // Add contract description if available
+    let Some(contract_info) = contracts.get(participant) else {
+        return format!("participant {}", participant);
+    };
 
-                // Add contract type if it's not a standard contract
-                if contract_info.contract_type != "contract" {
-                    description_parts[0] =
-                        format!("{} ({})", participant, contract_info.contract_type);
-                }
+    // Extract key state variables for description
+    let prefixes = &config.important_variable_prefixes;
+    let key_vars: Vec<&(String, String)> = contract_info
+        .variables
+        .iter()
+        .filter(|(name, _)| is_important_variable(name, prefixes))
+        .collect();
 
-                // Add key variables if available
-                if !key_vars.is_empty() {
-                    let var_list: Vec<String> = key_vars
-                        .iter()
-                        .take(2)
-                        .map(|(name, typ)| format!("{}: {}", name, typ))
-                        .collect();
-                    description_parts.push(format!("({})", var_list.join(", ")));
-                }
+    let mut description_parts = Vec::new();
 
-                // Add source file if available
-                if !contract_info.source_file.is_empty() {
-                    description_parts.push(format!("from {}", contract_info.source_file));
-                }
+    // Add contract name (always)
+    description_parts.push(participant.clone());
 
-                // Combine the parts with line breaks
-                let title = description_parts.join("<br/>");
-                diagram.push(format!("participant {} as \"{}\"", participant, title));
-            } else {
-                diagram.push(format!("participant {}", participant));
-            }
+    // Mark interfaces and abstract contracts, and note other non-standard types
+    match contract_info.contract_type.as_str() {
+        "contract" => {}
+        "interface" | "abstract" => {
+            description_parts[0] = format!("«{}» {}", contract_info.contract_type, participant);
+        }
+        contract_type => {
+            description_parts[0] = format!("{} ({})", participant, contract_type);
         }
     }
+
+    // Add the contract's NatSpec notice if available
+    if let Some(notice) = &contract_info.notice {
+        description_parts.push(notice.replace('"', "'"));
+    }
+
+    // Add key variables if available
+    if !key_vars.is_empty() {
+        let var_list: Vec<String> = key_vars
+            .iter()
+            .take(2)
+            .map(|(name, typ)| format!("{}: {}", name, typ))
+            .collect();
+        description_parts.push(format!("({})", var_list.join(", ")));
+    }
+
+    // Add source file if available
+    if !contract_info.source_file.is_empty() {
+        description_parts.push(format!("from {}", contract_info.source_file));
+    }
+
+    // Combine the parts with line breaks
+    let title = description_parts.join("<br/>");
+    format!("participant {} as \"{}\"", participant, title)
 }
 
-/// Add a section title to the diagram
-fn add_section_title(diagram: &mut Vec<String>, title: &str, light_colors: bool) {
-    let color = if light_colors {
-        match title {
+/// Title of the diagram, with characters that end a Mermaid `title` line replaced
+///
+/// The title runs to the first `;`, `#` or newline, so those become `,`, `No.` and a space.
+/// Colons are kept, but a leading one would turn into the `title:` form and is dropped.
+fn diagram_title(config: &crate::Config) -> String {
+    let title = match &config.title {
+        Some(title) => title.as_str(),
+        None => "Smart Contract Interaction Sequence Diagram",
+    };
+    let title = title.replace(';', ",").replace('#', "No.").split_whitespace().join(" ");
+    title.trim_start_matches(':').trim_start().to_string()
+}
+
+/// Add a section title to the diagram, in a colored `rect` unless the output is plain
+fn add_section_title(diagram: &mut Vec<String>, anchor: &str, title: &str, theme: Option<Theme>) {
+    let Some(theme) = theme else {
+        diagram.push(format!("Note over {}: {}", anchor, title));
+        diagram.push("".to_string());
+        return;
+    };
+
+    let color = match theme {
+        Theme::Light => match title {
             "User Interactions" => "rgb(252, 252, 255)",
             "Contract-to-Contract Interactions" => "rgb(248, 252, 255)",
             "Event Definitions" => "rgb(255, 252, 252)",
+            "Error Definitions" => "rgb(255, 250, 240)",
             "Contract Relationships" => "rgb(252, 255, 252)",
+            "Diagram Legend" => "rgb(248, 252, 255)",
             _ => "rgb(250, 250, 250)",
-        }
-    } else {
-        match title {
+        },
+        Theme::Dark => match title {
+            "User Interactions" => "rgb(31, 41, 55)",
+            "Contract-to-Contract Interactions" => "rgb(23, 37, 58)",
+            "Event Definitions" => "rgb(55, 31, 41)",
+            "Error Definitions" => "rgb(58, 45, 23)",
+            "Contract Relationships" => "rgb(26, 50, 38)",
+            "Diagram Legend" => "rgb(30, 30, 50)",
+            _ => "rgb(38, 38, 38)",
+        },
+        Theme::Default => match title {
             "User Interactions" => "rgb(245, 245, 245)",
             "Contract-to-Contract Interactions" => "rgb(240, 248, 255)",
             "Event Definitions" => "rgb(255, 245, 245)",
+            "Error Definitions" => "rgb(255, 248, 235)",
             "Contract Relationships" => "rgb(245, 255, 245)",
+            "Diagram Legend" => "rgb(240, 240, 255)",
             _ => "rgb(240, 240, 240)",
-        }
+        },
     };
 
     diagram.push(format!("rect {}", color));
-    diagram.push(format!("Note over User: {}", title));
+    diagram.push(format!("Note over {}: {}", anchor, title));
     diagram.push("end".to_string());
     diagram.push("".to_string());
 }
 
 /// Add a legend to the diagram
-fn add_legend(diagram: &mut Vec<String>, light_colors: bool) {
-    diagram.push("".to_string());
-    diagram.push("%%{init: { 'sequence': { 'showSequenceNumbers': true } }}%%".to_string());
+fn add_legend(diagram: &mut Vec<String>, anchor: &str, theme: Option<Theme>) {
     diagram.push("".to_string());
+    if theme.is_some() {
+        diagram.push("%%{init: { 'sequence': { 'showSequenceNumbers': true } }}%%".to_string());
+        diagram.push("".to_string());
+    }
 
-    let legend_color = if light_colors { "rgb(248, 252, 255)" } else { "rgb(240, 240, 255)" };
+    add_section_title(diagram, anchor, "Diagram Legend", theme);
 
-    diagram.push(format!("rect {}", legend_color));
-    diagram.push("Note over User: Diagram Legend".to_string());
-    diagram.push("end".to_string());
-    diagram.push("".to_string());
-
-    diagram.push("Note left of User: User→Contract: Public/External function calls".to_string());
-    diagram.push("Note left of User: User←Contract: Function returns".to_string());
-    diagram.push("Note left of User: Contract→Contract: Internal interactions".to_string());
-    diagram.push("Note left of User: Contract→Events: Emitted events".to_string());
-    diagram.push(
-        "Note left of User: Colored sections indicate different interaction types".to_string(),
-    );
+    let legend = [
+        "User→Contract: Public/External function calls",
+        "User←Contract: Function returns",
+        "Contract→Contract: Internal interactions",
+        "Contract→Events: Emitted events",
+    ];
+    for entry in legend {
+        diagram.push(format!("Note left of {}: {}", anchor, entry));
+    }
+    if theme.is_some() {
+        diagram.push(format!(
+            "Note left of {}: Colored sections indicate different interaction types",
+            anchor
+        ));
+    }
 }
@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+
+/// Result type returned by the public API
+pub type Result<T> = std::result::Result<T, Sol2SeqError>;
+
+/// Errors returned by the public API
+#[derive(Debug, thiserror::Error)]
+pub enum Sol2SeqError {
+    /// Reading or writing a file failed
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A file or solc output was not valid JSON
+    #[error("{context}")]
+    JsonParse {
+        context: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    /// The solc binary could not be found
+    #[error("solc not found: {}", .0.display())]
+    SolcNotFound(PathBuf),
+
+    /// solc ran but exited with an error
+    #[error("{} failed: {stderr}", .solc.display())]
+    SolcFailed { solc: PathBuf, stderr: String },
+
+    /// None of the given paths contained Solidity files
+    #[error("No Solidity files found in the provided paths")]
+    NoSolidityFiles,
+
+    /// The AST JSON is in none of the supported layouts
+    #[error("Unsupported AST format: {0}")]
+    UnsupportedAstFormat(String),
+
+    /// Any other failure, such as an unknown contract in the filter
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for Sol2SeqError {
+    /// Recover the typed error carried by an internal `anyhow` error
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<Sol2SeqError>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+
+        let context = error.to_string();
+        let error = match error.downcast::<std::io::Error>() {
+            Ok(source) => return Self::Io { context, source },
+            Err(error) => error,
+        };
+        match error.downcast::<serde_json::Error>() {
+            Ok(source) => Self::JsonParse { context, source },
+            Err(error) => Self::Other(error),
+        }
+    }
+}
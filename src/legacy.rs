@@ -0,0 +1,255 @@
+//! Translation of the legacy solc AST into the compact AST the rest of the crate reads
+//!
+//! solc before 0.8 could emit (and before 0.6 `--ast-json` always emitted) a legacy layout
+//! where every node is `{ "name": <node type>, "attributes": {...}, "children": [...] }`.
+//! The compact layout instead uses `nodeType` and named fields such as `body`, `arguments`
+//! or `leftExpression`, so legacy nodes are rewritten field by field before extraction.
+
+use serde_json::{Map, Value};
+
+/// Whether an AST, or any source in a combined/standard-json output, uses the legacy layout
+pub(crate) fn is_legacy_ast(ast: &Value) -> bool {
+    if is_legacy_node(ast) {
+        return true;
+    }
+
+    ast["sources"].as_object().is_some_and(|sources| {
+        sources
+            .values()
+            .any(|source| is_legacy_node(&source["AST"]) || is_legacy_node(&source["ast"]))
+    })
+}
+
+/// A legacy node names its type in `name` and has `attributes` or `children` instead of fields
+fn is_legacy_node(node: &Value) -> bool {
+    node.get("nodeType").is_none()
+        && node["name"].is_string()
+        && (node.get("attributes").is_some() || node.get("children").is_some())
+}
+
+/// Rewrite every legacy node below `value` in the compact layout, leaving other JSON as is
+pub(crate) fn to_compact(value: &Value) -> Value {
+    match value {
+        Value::Object(object) if is_legacy_node(value) => translate_node(object),
+        Value::Object(object) => Value::Object(
+            object.iter().map(|(key, value)| (key.clone(), to_compact(value))).collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(to_compact).collect()),
+        _ => value.clone(),
+    }
+}
+
+/// Translate one legacy node and its children
+fn translate_node(node: &Map<String, Value>) -> Value {
+    let node_type = node["name"].as_str().unwrap_or_default();
+    let children: Vec<Value> = node
+        .get("children")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(to_compact)
+        .collect();
+
+    let mut compact = Map::new();
+    compact.insert("nodeType".to_string(), Value::String(node_type.to_string()));
+    for key in ["id", "src"] {
+        if let Some(value) = node.get(key) {
+            compact.insert(key.to_string(), value.clone());
+        }
+    }
+
+    // Attributes keep their names, except the few the compact layout renamed
+    for (key, value) in node.get("attributes").and_then(Value::as_object).into_iter().flatten() {
+        let value = match value {
+            // Empty lists are written as `[null]`
+            Value::Array(items) if items.iter().all(Value::is_null) => Value::Array(Vec::new()),
+            value => value.clone(),
+        };
+        match key.as_str() {
+            "type" => {
+                compact.insert(
+                    "typeDescriptions".to_string(),
+                    serde_json::json!({ "typeString": value }),
+                );
+            }
+            "member_name" => {
+                compact.insert("memberName".to_string(), value);
+            }
+            "token" if node_type == "Literal" => {
+                compact.insert("kind".to_string(), value);
+            }
+            "hexvalue" => {
+                compact.insert("hexValue".to_string(), value);
+            }
+            "type_conversion" => {
+                let kind =
+                    if value.as_bool() == Some(true) { "typeConversion" } else { "functionCall" };
+                compact.insert("kind".to_string(), Value::String(kind.to_string()));
+            }
+            // Identifiers carry their name in `value`
+            "value" if node_type == "Identifier" => {
+                compact.insert("name".to_string(), value);
+            }
+            "value" if node_type == "ElementaryTypeNameExpression" => {
+                compact.insert(
+                    "typeName".to_string(),
+                    serde_json::json!({ "nodeType": "ElementaryTypeName", "name": value }),
+                );
+            }
+            _ => {
+                compact.insert(key.clone(), value);
+            }
+        }
+    }
+
+    let mut set = |key: &str, value: Value| {
+        compact.insert(key.to_string(), value);
+    };
+    let child = |index: usize| children.get(index).cloned().unwrap_or(Value::Null);
+    let rest = |from: usize| Value::Array(children.iter().skip(from).cloned().collect());
+    let of_type = |node_type: &str| -> Vec<Value> {
+        children
+            .iter()
+            .filter(|child| child["nodeType"].as_str() == Some(node_type))
+            .cloned()
+            .collect()
+    };
+
+    match node_type {
+        "SourceUnit" | "Block" => {
+            let key = if node_type == "Block" { "statements" } else { "nodes" };
+            set(key, Value::Array(children.clone()));
+        }
+        "ContractDefinition" => {
+            set("baseContracts", Value::Array(of_type("InheritanceSpecifier")));
+            let members = children
+                .iter()
+                .filter(|child| child["nodeType"].as_str() != Some("InheritanceSpecifier"))
+                .cloned()
+                .collect();
+            set("nodes", Value::Array(members));
+        }
+        "InheritanceSpecifier" | "ModifierInvocation" => {
+            let key = if node_type == "ModifierInvocation" { "modifierName" } else { "baseName" };
+            set(key, child(0));
+            set("arguments", rest(1));
+        }
+        "FunctionDefinition" => {
+            let parameter_lists = of_type("ParameterList");
+            set("parameters", parameter_lists.first().cloned().unwrap_or(Value::Null));
+            set("returnParameters", parameter_lists.get(1).cloned().unwrap_or(Value::Null));
+            set("modifiers", Value::Array(of_type("ModifierInvocation")));
+            set("body", of_type("Block").into_iter().next().unwrap_or(Value::Null));
+
+            // Constructors were flagged, and fallbacks unnamed, before `kind` existed
+            let kind = if node["attributes"]["isConstructor"].as_bool() == Some(true) {
+                "constructor"
+            } else if node["attributes"]["name"].as_str() == Some("") {
+                "fallback"
+            } else {
+                "function"
+            };
+            set("kind", Value::String(kind.to_string()));
+        }
+        "ModifierDefinition" => {
+            set("parameters", child(0));
+            set("body", child(1));
+        }
+        "EventDefinition" => set("parameters", child(0)),
+        "ParameterList" => set("parameters", Value::Array(children.clone())),
+        "StructDefinition" | "EnumDefinition" => set("members", Value::Array(children.clone())),
+        "VariableDeclaration" => {
+            set("typeName", child(0));
+            if children.len() > 1 {
+                set("value", child(1));
+            }
+        }
+        "Mapping" => {
+            set("keyType", child(0));
+            set("valueType", child(1));
+        }
+        "ArrayTypeName" => {
+            set("baseType", child(0));
+            set("length", child(1));
+        }
+        "UsingForDirective" => {
+            set("libraryName", child(0));
+            set("typeName", child(1));
+        }
+        "ExpressionStatement" | "Return" => set("expression", child(0)),
+        "EmitStatement" => set("eventCall", child(0)),
+        "VariableDeclarationStatement" => {
+            set("declarations", Value::Array(of_type("VariableDeclaration")));
+            let initial_value = children
+                .iter()
+                .find(|child| child["nodeType"].as_str() != Some("VariableDeclaration"))
+                .cloned();
+            set("initialValue", initial_value.unwrap_or(Value::Null));
+        }
+        "IfStatement" => {
+            set("condition", child(0));
+            set("trueBody", child(1));
+            set("falseBody", child(2));
+        }
+        "WhileStatement" | "DoWhileStatement" => {
+            // The body is the only statement among the two children
+            let (body, condition) = if is_statement(&child(0)) { (0, 1) } else { (1, 0) };
+            set("condition", child(condition));
+            set("body", child(body));
+        }
+        "ForStatement" => {
+            // Omitted header parts leave no child: the body comes last, the condition is the
+            // only expression, and a statement before it (or one of two) is the initializer
+            let header = &children[..children.len().saturating_sub(1)];
+            let statements = header.iter().filter(|part| is_statement(part)).count();
+            let mut header = header.iter().peekable();
+            if let Some(init) = header.next_if(|part| {
+                part["nodeType"].as_str() == Some("VariableDeclarationStatement")
+                    || (is_statement(part) && statements == 2)
+            }) {
+                set("initializationExpression", init.clone());
+            }
+            if let Some(condition) = header.next_if(|part| !is_statement(part)) {
+                set("condition", condition.clone());
+            }
+            if let Some(loop_expression) = header.next() {
+                set("loopExpression", loop_expression.clone());
+            }
+            set("body", children.last().cloned().unwrap_or(Value::Null));
+        }
+        "FunctionCall" => {
+            set("expression", child(0));
+            set("arguments", rest(1));
+        }
+        "MemberAccess" => set("expression", child(0)),
+        "IndexAccess" => {
+            set("baseExpression", child(0));
+            set("indexExpression", child(1));
+        }
+        "BinaryOperation" => {
+            set("leftExpression", child(0));
+            set("rightExpression", child(1));
+        }
+        "UnaryOperation" => set("subExpression", child(0)),
+        "Assignment" => {
+            set("leftHandSide", child(0));
+            set("rightHandSide", child(1));
+        }
+        "Conditional" => {
+            set("condition", child(0));
+            set("trueExpression", child(1));
+            set("falseExpression", child(2));
+        }
+        "TupleExpression" => set("components", Value::Array(children.clone())),
+        "NewExpression" => set("typeName", child(0)),
+        _ => {}
+    }
+
+    Value::Object(compact)
+}
+
+/// Whether a translated node is a statement rather than an expression
+fn is_statement(node: &Value) -> bool {
+    let node_type = node["nodeType"].as_str().unwrap_or_default();
+    node_type == "Block" || node_type.ends_with("Statement") || node_type == "Return"
+}
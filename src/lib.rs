@@ -9,7 +9,7 @@ This crate can be used as:
 
 ## Library Usage
 
-```rust
+```no_run
 use anyhow::Result;
 use sol2seq::{generate_diagram_from_file, Config};
 
@@ -18,6 +18,7 @@ fn main() -> Result<()> {
     let config = Config {
         light_colors: false,
         output_file: Some("diagram.md".into()),
+        ..Default::default()
     };
 
     // Generate diagram from AST file
@@ -32,73 +33,790 @@ fn main() -> Result<()> {
 
 ```bash
 # Generate a diagram from an AST JSON file
-sol2seq path/to/ast.json output.md
+sol2seq ast path/to/ast.json -o output.md
+
+# Print the diagram to stdout instead of writing a file
+sol2seq ast path/to/ast.json --stdout
 
 # Use lighter colors
-sol2seq --light-colors path/to/ast.json output.md
+sol2seq ast --light-colors path/to/ast.json -o output.md
 ```
 */
 
 mod ast;
 mod diagram;
+mod error;
+mod legacy;
 mod types;
 mod utils;
 
-use anyhow::{Context, Result};
-use std::{fs, path::{Path, PathBuf}};
+use anyhow::Context;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Recursively find all files with the given extension in a directory
+fn find_files_with_extension(dir_path: &Path, extension: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
 
-/// Recursively find all Solidity files in a directory
-fn find_solidity_files(dir_path: &Path) -> Result<Vec<PathBuf>> {
-    let mut sol_files = Vec::new();
-    
     if dir_path.is_dir() {
         for entry in fs::read_dir(dir_path)
             .with_context(|| format!("Failed to read directory: {}", dir_path.display()))?
         {
             let entry = entry?;
             let path = entry.path();
-            
+
             if path.is_dir() {
                 // Recursively search subdirectories
-                let mut sub_files = find_solidity_files(&path)?;
-                sol_files.append(&mut sub_files);
-            } else if let Some(ext) = path.extension() {
-                // Check if file has .sol extension
-                if ext == "sol" {
-                    sol_files.push(path);
-                }
+                let mut sub_files = find_files_with_extension(&path, extension)?;
+                files.append(&mut sub_files);
+            } else if path.extension().is_some_and(|ext| ext == extension) {
+                files.push(path);
             }
         }
     }
-    
-    Ok(sol_files)
+
+    Ok(files)
 }
 
 /// Configuration for diagram generation
-#[derive(Debug, Clone)]
+///
+/// Also deserializable from a `sol2seq.toml` file with [`read_config_file`]; keys are the
+/// field names and omitted keys keep their defaults.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
-    /// Use lighter colors for the diagram
+    /// Use lighter colors for the diagram (shorthand for `Theme::Light`; any other `theme`
+    /// than the default wins)
     pub light_colors: bool,
 
+    /// Color theme of the diagram
+    pub theme: Theme,
+
     /// Output file path (None for stdout)
+    #[serde(skip)]
     pub output_file: Option<PathBuf>,
     
     /// Include storage updates in the diagram
     pub show_storage_updates: bool,
+
+    /// Render only each contract's callable signatures instead of the interaction flow
+    pub only_public_api: bool,
+
+    /// Append a caller/callee matrix counting cross-contract call sites
+    pub emit_call_matrix: bool,
+
+    /// Output format of the generated diagram
+    pub output_format: OutputFormat,
+
+    /// Kind of Mermaid diagram to generate (sequence, class or call graph)
+    pub diagram_type: DiagramType,
+
+    /// Name of the synthetic participant used for token transfers
+    pub token_participant: String,
+
+    /// Description shown for the token participant
+    pub token_participant_label: String,
+
+    /// Name of the synthetic participant receiving ETH sends
+    pub recipient_participant: String,
+
+    /// Description shown for the recipient participant
+    pub recipient_participant_label: String,
+
+    /// Map of function names (or `*` patterns) to the actor calling them; unmapped functions use `User`
+    pub actor_map: HashMap<String, String>,
+
+    /// Descriptions of domain-specific function names, consulted before the built-in ones
+    pub function_purposes: HashMap<String, String>,
+
+    /// Maximum nesting depth when walking function bodies and inlining internal calls
+    pub max_body_depth: usize,
+
+    /// solc binary used to compile sources (None for `solc` on PATH)
+    pub solc_path: Option<PathBuf>,
+
+    /// Extra arguments passed to solc, e.g. `--base-path .`
+    pub solc_extra_args: Vec<String>,
+
+    /// Import remappings (`prefix=target`) forwarded to solc
+    pub remappings: Vec<String>,
+
+    /// Directory caching solc output by source contents and arguments (None disables it)
+    pub cache_dir: Option<PathBuf>,
+
+    /// Only diagram these contracts (and their direct call targets); empty means all
+    pub contracts: Vec<String>,
+
+    /// Function visibilities rendered as user entrypoints
+    pub include_visibilities: Vec<String>,
+
+    /// Maximum number of interactions rendered before the diagram is truncated
+    pub max_interactions: Option<usize>,
+
+    /// Collapse consecutive identical interaction lines into one with an `(xN)` multiplier
+    pub collapse_duplicates: bool,
+
+    /// Order of participant lifelines between `User` and `Events`
+    pub participant_order: ParticipantOrder,
+
+    /// Render calls and ETH sends to `msg.sender` against a dedicated `Caller` participant
+    pub show_msg_sender: bool,
+
+    /// Append each entrypoint's 4-byte function selector to its arrow
+    pub show_selectors: bool,
+
+    /// Fold view/pure returns into a single non-activating call arrow
+    pub compact_view_returns: bool,
+
+    /// Append the legend explaining arrow kinds
+    pub show_legend: bool,
+
+    /// Include the Event Definitions section
+    pub show_events_section: bool,
+
+    /// Include the Error Definitions section
+    pub show_errors_section: bool,
+
+    /// Include the Contract Relationships section
+    pub show_relationships_section: bool,
+
+    /// Diagram title (defaults to "Smart Contract Interaction Sequence Diagram")
+    pub title: Option<String>,
+
+    /// Name prefixes marking a state variable as key in participant descriptions
+    pub important_variable_prefixes: Vec<String>,
+
+    /// Flag the first state write after an external call in each entrypoint
+    pub flag_reentrancy: bool,
+
+    /// Wrap contracts declared in the same source file in a Mermaid `box`
+    pub group_by_source_file: bool,
+
+    /// Render each entrypoint's body between its call arrow and return, in one section
+    pub inline_bodies: bool,
+
+    /// Omit the `%%{init}%%` theme blocks and colored `rect` sections for portable Mermaid
+    pub plain: bool,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self { 
-            light_colors: false, 
+            light_colors: false,
+            theme: Theme::Default,
             output_file: None,
             show_storage_updates: true,
+            only_public_api: false,
+            emit_call_matrix: false,
+            output_format: OutputFormat::Mermaid,
+            diagram_type: DiagramType::Sequence,
+            token_participant: "TokenContract".to_string(),
+            token_participant_label: "ERC20/ERC721 Tokens".to_string(),
+            recipient_participant: "Recipient".to_string(),
+            recipient_participant_label: "ETH Recipient".to_string(),
+            actor_map: HashMap::new(),
+            function_purposes: HashMap::new(),
+            max_body_depth: 16,
+            solc_path: None,
+            solc_extra_args: Vec::new(),
+            remappings: Vec::new(),
+            cache_dir: None,
+            contracts: Vec::new(),
+            include_visibilities: vec!["public".to_string(), "external".to_string()],
+            max_interactions: None,
+            collapse_duplicates: false,
+            participant_order: ParticipantOrder::Alphabetical,
+            show_msg_sender: false,
+            show_selectors: false,
+            compact_view_returns: false,
+            show_legend: true,
+            show_events_section: true,
+            show_errors_section: true,
+            show_relationships_section: true,
+            title: None,
+            important_variable_prefixes: [
+                "owner",
+                "admin",
+                "token",
+                "deployer",
+                "implementation",
+                "registry",
+                "factory",
+            ]
+            .map(String::from)
+            .to_vec(),
+            flag_reentrancy: false,
+            group_by_source_file: false,
+            inline_bodies: false,
+            plain: false,
         }
     }
 }
 
+impl Config {
+    /// Start building a configuration from the defaults
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Theme to render with, honoring the `light_colors` shorthand; `None` for plain output
+    ///
+    /// An explicitly chosen theme wins over `light_colors`.
+    pub(crate) fn effective_theme(&self) -> Option<Theme> {
+        if self.plain {
+            None
+        } else if self.light_colors && self.theme == Theme::Default {
+            Some(Theme::Light)
+        } else {
+            Some(self.theme)
+        }
+    }
+}
+
+/// Chained builder for [`Config`]
+///
+/// ```
+/// use sol2seq::Config;
+///
+/// let config = Config::builder().light_colors(true).show_storage_updates(false).build();
+/// assert!(config.light_colors && !config.show_storage_updates);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Use lighter colors for the diagram
+    pub fn light_colors(mut self, light_colors: bool) -> Self {
+        self.config.light_colors = light_colors;
+        self
+    }
+
+    /// Color theme of the diagram
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.config.theme = theme;
+        self
+    }
+
+    /// Write the diagram to this file
+    pub fn output_file(mut self, output_file: impl Into<PathBuf>) -> Self {
+        self.config.output_file = Some(output_file.into());
+        self
+    }
+
+    /// Include storage updates in the diagram
+    pub fn show_storage_updates(mut self, show_storage_updates: bool) -> Self {
+        self.config.show_storage_updates = show_storage_updates;
+        self
+    }
+
+    /// Render only each contract's callable signatures
+    pub fn only_public_api(mut self, only_public_api: bool) -> Self {
+        self.config.only_public_api = only_public_api;
+        self
+    }
+
+    /// Append a caller/callee matrix of cross-contract call sites
+    pub fn emit_call_matrix(mut self, emit_call_matrix: bool) -> Self {
+        self.config.emit_call_matrix = emit_call_matrix;
+        self
+    }
+
+    /// Output format of the generated diagram
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.config.output_format = output_format;
+        self
+    }
+
+    /// Kind of Mermaid diagram to generate
+    pub fn diagram_type(mut self, diagram_type: DiagramType) -> Self {
+        self.config.diagram_type = diagram_type;
+        self
+    }
+
+    /// Name and description of the synthetic token participant
+    pub fn token_participant(mut self, name: impl Into<String>, label: impl Into<String>) -> Self {
+        self.config.token_participant = name.into();
+        self.config.token_participant_label = label.into();
+        self
+    }
+
+    /// Name and description of the synthetic ETH recipient participant
+    pub fn recipient_participant(
+        mut self,
+        name: impl Into<String>,
+        label: impl Into<String>,
+    ) -> Self {
+        self.config.recipient_participant = name.into();
+        self.config.recipient_participant_label = label.into();
+        self
+    }
+
+    /// Route calls to a function (or `*` pattern) from a named actor
+    pub fn actor(mut self, function: impl Into<String>, actor: impl Into<String>) -> Self {
+        self.config.actor_map.insert(function.into(), actor.into());
+        self
+    }
+
+    /// Describe what a function name does, overriding any built-in description
+    pub fn function_purpose(
+        mut self,
+        function: impl Into<String>,
+        purpose: impl Into<String>,
+    ) -> Self {
+        self.config.function_purposes.insert(function.into(), purpose.into());
+        self
+    }
+
+    /// Maximum nesting depth when walking function bodies
+    pub fn max_body_depth(mut self, max_body_depth: usize) -> Self {
+        self.config.max_body_depth = max_body_depth;
+        self
+    }
+
+    /// solc binary used to compile sources
+    pub fn solc_path(mut self, solc_path: impl Into<PathBuf>) -> Self {
+        self.config.solc_path = Some(solc_path.into());
+        self
+    }
+
+    /// Append an extra argument passed to solc
+    pub fn solc_arg(mut self, arg: impl Into<String>) -> Self {
+        self.config.solc_extra_args.push(arg.into());
+        self
+    }
+
+    /// Append an import remapping (`prefix=target`) forwarded to solc
+    pub fn remapping(mut self, remapping: impl Into<String>) -> Self {
+        self.config.remappings.push(remapping.into());
+        self
+    }
+
+    /// Cache solc output in this directory
+    pub fn cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.config.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    /// Restrict the diagram to a contract (repeatable)
+    pub fn contract(mut self, name: impl Into<String>) -> Self {
+        self.config.contracts.push(name.into());
+        self
+    }
+
+    /// Replace the function visibilities rendered as user entrypoints
+    pub fn include_visibilities<I, S>(mut self, visibilities: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.include_visibilities = visibilities.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Cap the number of interactions rendered in the diagram
+    pub fn max_interactions(mut self, max_interactions: usize) -> Self {
+        self.config.max_interactions = Some(max_interactions);
+        self
+    }
+
+    /// Collapse consecutive identical interaction lines
+    pub fn collapse_duplicates(mut self, collapse_duplicates: bool) -> Self {
+        self.config.collapse_duplicates = collapse_duplicates;
+        self
+    }
+
+    /// Order of participant lifelines
+    pub fn participant_order(mut self, participant_order: ParticipantOrder) -> Self {
+        self.config.participant_order = participant_order;
+        self
+    }
+
+    /// Render calls and ETH sends to `msg.sender` against a `Caller` participant
+    pub fn show_msg_sender(mut self, show_msg_sender: bool) -> Self {
+        self.config.show_msg_sender = show_msg_sender;
+        self
+    }
+
+    /// Append each entrypoint's 4-byte function selector to its arrow
+    pub fn show_selectors(mut self, show_selectors: bool) -> Self {
+        self.config.show_selectors = show_selectors;
+        self
+    }
+
+    /// Fold view/pure returns into a single non-activating call arrow
+    pub fn compact_view_returns(mut self, compact_view_returns: bool) -> Self {
+        self.config.compact_view_returns = compact_view_returns;
+        self
+    }
+
+    /// Append the legend explaining arrow kinds
+    pub fn show_legend(mut self, show_legend: bool) -> Self {
+        self.config.show_legend = show_legend;
+        self
+    }
+
+    /// Include the Event Definitions section
+    pub fn show_events_section(mut self, show_events_section: bool) -> Self {
+        self.config.show_events_section = show_events_section;
+        self
+    }
+
+    /// Include the Error Definitions section
+    pub fn show_errors_section(mut self, show_errors_section: bool) -> Self {
+        self.config.show_errors_section = show_errors_section;
+        self
+    }
+
+    /// Include the Contract Relationships section
+    pub fn show_relationships_section(mut self, show_relationships_section: bool) -> Self {
+        self.config.show_relationships_section = show_relationships_section;
+        self
+    }
+
+    /// Set the diagram title
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.config.title = Some(title.into());
+        self
+    }
+
+    /// Add a name prefix marking state variables as key, e.g. `vault` or `oracle` (repeatable)
+    pub fn important_variable_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.config.important_variable_prefixes.push(prefix.into());
+        self
+    }
+
+    /// Flag the first state write after an external call in each entrypoint
+    pub fn flag_reentrancy(mut self, flag_reentrancy: bool) -> Self {
+        self.config.flag_reentrancy = flag_reentrancy;
+        self
+    }
+
+    /// Wrap contracts declared in the same source file in a Mermaid `box`
+    pub fn group_by_source_file(mut self, group_by_source_file: bool) -> Self {
+        self.config.group_by_source_file = group_by_source_file;
+        self
+    }
+
+    /// Render each entrypoint's body between its call arrow and return
+    pub fn inline_bodies(mut self, inline_bodies: bool) -> Self {
+        self.config.inline_bodies = inline_bodies;
+        self
+    }
+
+    /// Emit bare Mermaid without theme blocks or colored sections
+    pub fn plain(mut self, plain: bool) -> Self {
+        self.config.plain = plain;
+        self
+    }
+
+    /// Finish building the configuration
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
+
+/// Read `prefix=target` import remappings from a file such as Foundry's `remappings.txt`
+///
+/// Blank lines and `#` comments are skipped.
+pub fn read_remappings_file<P: AsRef<Path>>(path: P) -> Result<Vec<String>> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read remappings file: {}", path.display()))?;
+
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// Read a [`Config`] from a TOML file such as `sol2seq.toml`
+///
+/// ```toml
+/// theme = "dark"
+/// solc_path = "/usr/local/bin/solc-0.8.24"
+/// remappings = ["@openzeppelin/=lib/openzeppelin-contracts/"]
+/// contracts = ["Vault"]
+///
+/// [function_purposes]
+/// liquidate = "Seize undercollateralized position"
+/// ```
+#[cfg(feature = "config-file")]
+pub fn read_config_file<P: AsRef<Path>>(path: P) -> Result<Config> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+
+    Ok(toml::from_str(&content)
+        .with_context(|| format!("Failed to parse config file: {}", path.display()))?)
+}
+
+/// Remove a solc output cache directory, if it exists
+pub fn clean_cache<P: AsRef<Path>>(cache_dir: P) -> Result<()> {
+    let cache_dir = cache_dir.as_ref();
+    if cache_dir.exists() {
+        fs::remove_dir_all(cache_dir)
+            .with_context(|| format!("Failed to remove cache: {}", cache_dir.display()))?;
+    }
+    Ok(())
+}
+
+/// Read function descriptions from a JSON object of `"functionName": "description"` pairs
+pub fn read_purposes_file<P: AsRef<Path>>(path: P) -> Result<HashMap<String, String>> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read purposes file: {}", path.display()))?;
+
+    Ok(serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse purposes file: {}", path.display()))?)
+}
+
+/// Generate the diagram for an AST and save it to the configured output file, if any
+fn render_and_write(ast: &serde_json::Value, config: Config) -> anyhow::Result<String> {
+    // Generate sequence diagram
+    let diagram = diagram::generate_sequence_diagram_with_config(ast, config.clone())?;
+
+    // Save to file if specified
+    if let Some(output_path) = config.output_file {
+        fs::write(&output_path, &diagram)
+            .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+    }
+
+    Ok(diagram)
+}
+
+/// Render one diagram per contract, each restricted to that contract and its call targets
+///
+/// The AST is parsed once; only each contract's function bodies are walked separately.
+fn render_per_contract(
+    ast: &serde_json::Value,
+    config: &Config,
+) -> anyhow::Result<HashMap<String, String>> {
+    ast::extract_contract_info_per_contract(ast, config)?
+        .into_iter()
+        .map(|(contract_name, data)| {
+            let contract_config = Config {
+                contracts: vec![contract_name.clone()],
+                output_file: None,
+                ..config.clone()
+            };
+            let diagram = diagram::render_diagram(&data, &contract_config)?;
+            Ok((contract_name, diagram))
+        })
+        .collect()
+}
+
+/// Collect the ASTs of Foundry artifacts (`forge build` output) into combined-json shape
+///
+/// Every contract artifact of a source file carries that file's full AST, so each
+/// source is kept once; build-info files and artifacts without an AST are skipped.
+fn load_foundry_artifacts(out_dir: &Path) -> anyhow::Result<serde_json::Value> {
+    let mut combined_ast = serde_json::json!({ "sources": {} });
+
+    let artifacts = find_files_with_extension(out_dir, "json")?;
+    for artifact_path in artifacts.iter().filter(|path| {
+        !path.components().any(|component| component.as_os_str() == "build-info")
+    }) {
+        let artifact = load_ast_file(artifact_path)?;
+        let ast = &artifact["ast"];
+        if ast["nodeType"].as_str() != Some("SourceUnit") {
+            continue;
+        }
+
+        let source_path = ast["absolutePath"].as_str().unwrap_or_default().to_string();
+        let source = serde_json::json!({ "sources": { source_path: { "AST": ast } } });
+        utils::merge_ast_json(&mut combined_ast, &source)?;
+    }
+
+    if combined_ast["sources"].as_object().is_some_and(|sources| sources.is_empty()) {
+        return Err(anyhow::anyhow!("No artifact ASTs found in {}", out_dir.display()));
+    }
+
+    Ok(combined_ast)
+}
+
+/// Collect the ASTs of Hardhat build-info files into combined-json shape
+///
+/// `build_info_path` is a single build-info JSON file or a directory of them; each
+/// file's `output.sources[file].ast` becomes one source.
+fn load_hardhat_build_info(build_info_path: &Path) -> anyhow::Result<serde_json::Value> {
+    let mut combined_ast = serde_json::json!({ "sources": {} });
+
+    let build_info_files = if build_info_path.is_dir() {
+        find_files_with_extension(build_info_path, "json")?
+    } else {
+        vec![build_info_path.to_path_buf()]
+    };
+
+    for build_info_file in &build_info_files {
+        let build_info = load_ast_file(build_info_file)?;
+        let Some(sources) = build_info["output"]["sources"].as_object() else {
+            continue;
+        };
+
+        // Each build-info file is a separate compilation with its own node ids
+        let mut compilation = serde_json::json!({ "sources": {} });
+        for (source_path, source) in sources {
+            if source["ast"].is_object() {
+                compilation["sources"][source_path] = serde_json::json!({ "AST": source["ast"] });
+            }
+        }
+        utils::merge_compilation(&mut combined_ast, &compilation)?;
+    }
+
+    if combined_ast["sources"].as_object().is_some_and(|sources| sources.is_empty()) {
+        return Err(anyhow::anyhow!(
+            "No source ASTs found in build info: {}",
+            build_info_path.display()
+        ));
+    }
+
+    Ok(combined_ast)
+}
+
+/// Read and parse an AST JSON file
+///
+/// The file is parsed straight from a buffered reader, so the raw text of an AST tens of
+/// megabytes large is never held in memory next to the parsed `Value`.
+/// `cargo bench --bench ast_loading` compares its speed with parsing from a string.
+fn load_ast_file(ast_file: &Path) -> anyhow::Result<serde_json::Value> {
+    let file = fs::File::open(ast_file)
+        .with_context(|| format!("Failed to read AST file: {}", ast_file.display()))?;
+
+    serde_json::from_reader(std::io::BufReader::new(file))
+        .with_context(|| "Failed to parse AST JSON")
+}
+
+/// Compile Solidity source files or directories and merge their ASTs
+fn load_sources<P: AsRef<Path>>(
+    source_paths: &[P],
+    config: &Config,
+) -> anyhow::Result<serde_json::Value> {
+    // Process each Solidity file and combine ASTs
+    let mut combined_ast = serde_json::Value::Object(serde_json::Map::new());
+    // Keyed by canonical path so a file passed both directly and via its directory is
+    // compiled once; the path as given is kept so diagrams show the user's relative paths
+    let mut all_source_files = BTreeMap::new();
+    let mut config = config.clone();
+
+    // First, collect all Solidity files from provided paths (could be files or directories)
+    for path in source_paths {
+        let path = path.as_ref();
+
+        // Pick up import remappings declared next to the sources
+        let source_dir = if path.is_dir() { Some(path) } else { path.parent() };
+        if let Some(remappings_file) = source_dir.map(|dir| dir.join("remappings.txt")) {
+            if remappings_file.is_file() {
+                for remapping in read_remappings_file(&remappings_file)? {
+                    if !config.remappings.contains(&remapping) {
+                        config.remappings.push(remapping);
+                    }
+                }
+            }
+        }
+
+        let files = if path.is_dir() {
+            // If it's a directory, find all Solidity files inside it
+            find_files_with_extension(path, "sol")?
+        } else {
+            // If it's a file, add it directly (assuming it's a Solidity file)
+            vec![path.to_path_buf()]
+        };
+        for file in files {
+            let canonical = fs::canonicalize(&file).unwrap_or_else(|_| file.clone());
+            all_source_files.entry(canonical).or_insert(file);
+        }
+    }
+
+    if all_source_files.is_empty() {
+        return Err(Sol2SeqError::NoSolidityFiles.into());
+    }
+    let all_source_files: Vec<PathBuf> = all_source_files.into_values().collect();
+
+    // Compile everything in one solc run so imports resolve across files
+    match ast::process_solidity_files(&all_source_files, &config) {
+        Ok(ast) => return Ok(ast),
+        Err(error) => log::warn!("Combined solc run failed, compiling files one by one: {:#}", error),
+    }
+
+    // Fall back to one solc run per file and combine the ASTs
+    for file_path in &all_source_files {
+        let file_str = file_path.to_str().ok_or_else(|| {
+            anyhow::anyhow!("Failed to convert path to string: {}", file_path.display())
+        })?;
+
+        let ast = ast::process_solidity_file(file_str, &config)?;
+
+        // Merge with combined AST; each run numbers its AST nodes from scratch
+        utils::merge_compilation(&mut combined_ast, &ast)?;
+    }
+
+    Ok(combined_ast)
+}
+
+/// Extract the structured diagram model from AST JSON without rendering it
+///
+/// # Example
+///
+/// ```no_run
+/// use sol2seq::{analyze_ast, Config};
+///
+/// let ast = serde_json::json!({ "nodeType": "SourceUnit", "nodes": [] });
+/// let data = analyze_ast(&ast, &Config::default()).unwrap();
+/// for (name, contract) in &data.contracts {
+///     println!("{}: {} functions", name, contract.functions.len());
+/// }
+/// ```
+pub fn analyze_ast(ast: &serde_json::Value, config: &Config) -> Result<DiagramData> {
+    Ok(ast::extract_contract_info(ast, config)?)
+}
+
+/// Extract the structured diagram model from an AST JSON file
+pub fn analyze_ast_file<P: AsRef<Path>>(ast_file: P, config: &Config) -> Result<DiagramData> {
+    analyze_ast(&load_ast_file(ast_file.as_ref())?, config)
+}
+
+/// Extract the structured diagram model from Solidity source files or directories
+pub fn analyze_sources<P: AsRef<Path>>(source_paths: &[P], config: &Config) -> Result<DiagramData> {
+    analyze_ast(&load_sources(source_paths, config)?, config)
+}
+
+/// Check that AST JSON can be processed, without rendering a diagram
+///
+/// The report counts the contracts, functions and interactions found and lists contracts
+/// with no resolvable function bodies and any expected AST fields that were missing.
+/// Counts follow `config`'s filters, so they match what would be rendered with it.
+///
+/// # Example
+///
+/// ```
+/// use sol2seq::{validate_ast, Config};
+///
+/// let ast = serde_json::json!({ "nodeType": "SourceUnit", "nodes": [] });
+/// let report = validate_ast(&ast, &Config::default()).unwrap();
+/// assert_eq!(report.contracts, 0);
+/// assert!(!report.is_valid());
+/// ```
+pub fn validate_ast(ast: &serde_json::Value, config: &Config) -> Result<ValidationReport> {
+    Ok(ast::validate_ast(ast, config)?)
+}
+
+/// Check that an AST JSON file can be processed, without rendering a diagram
+pub fn validate_ast_file<P: AsRef<Path>>(ast_file: P, config: &Config) -> Result<ValidationReport> {
+    validate_ast(&load_ast_file(ast_file.as_ref())?, config)
+}
+
 /// Generate a sequence diagram from an AST JSON file
 ///
+/// The file is streamed into the parser instead of being read into a string first, which
+/// keeps peak memory close to the size of the parsed AST for very large inputs.
+///
 /// # Arguments
 ///
 /// * `ast_file` - Path to the AST JSON file
@@ -123,24 +841,9 @@ pub fn generate_diagram_from_file<P: AsRef<std::path::Path>>(
     ast_file: P,
     config: Config,
 ) -> Result<String> {
-    // Load AST file
-    let ast_content = fs::read_to_string(&ast_file)
-        .with_context(|| format!("Failed to read AST file: {}", ast_file.as_ref().display()))?;
-
-    // Parse JSON
-    let ast_json: serde_json::Value =
-        serde_json::from_str(&ast_content).with_context(|| "Failed to parse AST JSON")?;
-
-    // Generate sequence diagram
-    let diagram = diagram::generate_sequence_diagram_with_config(&ast_json, config.clone())?;
-
-    // Save to file if specified
-    if let Some(output_path) = config.output_file {
-        fs::write(&output_path, &diagram)
-            .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
-    }
+    let ast_json = load_ast_file(ast_file.as_ref())?;
 
-    Ok(diagram)
+    Ok(render_and_write(&ast_json, config)?)
 }
 
 /// Generate a sequence diagram from Solidity source files
@@ -170,54 +873,170 @@ pub fn generate_diagram_from_sources<P: AsRef<std::path::Path>>(
     source_paths: &[P],
     config: Config,
 ) -> Result<String> {
-    // Process each Solidity file and combine ASTs
-    let mut combined_ast = serde_json::Value::Object(serde_json::Map::new());
-    let mut all_source_files = Vec::new();
+    let combined_ast = load_sources(source_paths, &config)?;
 
-    // First, collect all Solidity files from provided paths (could be files or directories)
-    for path in source_paths {
-        let path = path.as_ref();
-        if path.is_dir() {
-            // If it's a directory, find all Solidity files inside it
-            let mut sol_files = find_solidity_files(path)?;
-            all_source_files.append(&mut sol_files);
-        } else {
-            // If it's a file, add it directly (assuming it's a Solidity file)
-            all_source_files.push(path.to_path_buf());
-        }
-    }
+    Ok(render_and_write(&combined_ast, config)?)
+}
 
-    if all_source_files.is_empty() {
-        return Err(anyhow::anyhow!("No Solidity files found in the provided paths"));
-    }
+/// Generate a self-contained sequence diagram for each contract in the sources
+///
+/// Each diagram only shows that contract's entrypoints and the participants it calls.
+///
+/// # Arguments
+///
+/// * `source_paths` - Paths to Solidity source files or directories
+/// * `config` - Configuration for diagram generation; `output_file` is ignored
+///
+/// # Returns
+///
+/// A map of contract name to its diagram
+///
+/// # Example
+///
+/// ```no_run
+/// use sol2seq::{Config, generate_diagrams_per_contract};
+///
+/// let diagrams = generate_diagrams_per_contract(&["src"], Config::default()).unwrap();
+/// for (contract, diagram) in &diagrams {
+///     std::fs::write(format!("docs/{}.md", contract), diagram).unwrap();
+/// }
+/// ```
+pub fn generate_diagrams_per_contract<P: AsRef<Path>>(
+    source_paths: &[P],
+    config: Config,
+) -> Result<HashMap<String, String>> {
+    let combined_ast = load_sources(source_paths, &config)?;
 
-    // Process each Solidity file and combine ASTs
-    for file_path in &all_source_files {
-        let file_str = file_path.to_str().ok_or_else(|| {
-            anyhow::anyhow!("Failed to convert path to string: {}", file_path.display())
-        })?;
-        
-        let ast = ast::process_solidity_file(file_str)?;
+    Ok(render_per_contract(&combined_ast, &config)?)
+}
 
-        // Merge with combined AST
-        utils::merge_ast_json(&mut combined_ast, &ast)?;
-    }
+/// Generate a self-contained sequence diagram for each contract in an AST JSON file
+///
+/// See [`generate_diagrams_per_contract`]; `config.output_file` is ignored.
+pub fn generate_diagrams_per_contract_from_file<P: AsRef<Path>>(
+    ast_file: P,
+    config: Config,
+) -> Result<HashMap<String, String>> {
+    let ast_json = load_ast_file(ast_file.as_ref())?;
 
-    // Generate sequence diagram
-    let diagram = diagram::generate_sequence_diagram_with_config(&combined_ast, config.clone())?;
+    Ok(render_per_contract(&ast_json, &config)?)
+}
 
-    // Save to file if specified
-    if let Some(output_path) = config.output_file {
-        fs::write(&output_path, &diagram)
-            .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
-    }
+/// Generate a self-contained sequence diagram for each contract in Foundry build artifacts
+///
+/// See [`generate_diagrams_per_contract`]; `config.output_file` is ignored.
+pub fn generate_diagrams_per_contract_from_foundry<P: AsRef<Path>>(
+    out_dir: P,
+    config: Config,
+) -> Result<HashMap<String, String>> {
+    let combined_ast = load_foundry_artifacts(out_dir.as_ref())?;
 
-    Ok(diagram)
+    Ok(render_per_contract(&combined_ast, &config)?)
+}
+
+/// Generate a self-contained sequence diagram for each contract in Hardhat build-info files
+///
+/// See [`generate_diagrams_per_contract`]; `config.output_file` is ignored.
+pub fn generate_diagrams_per_contract_from_hardhat<P: AsRef<Path>>(
+    build_info_path: P,
+    config: Config,
+) -> Result<HashMap<String, String>> {
+    let combined_ast = load_hardhat_build_info(build_info_path.as_ref())?;
+
+    Ok(render_per_contract(&combined_ast, &config)?)
+}
+
+/// Generate a sequence diagram from Foundry build artifacts
+///
+/// # Arguments
+///
+/// * `out_dir` - Foundry output directory (`out/`) produced by `forge build`
+/// * `config` - Configuration for diagram generation
+///
+/// # Returns
+///
+/// The generated diagram as a string
+///
+/// # Example
+///
+/// ```no_run
+/// use sol2seq::{Config, generate_diagram_from_foundry};
+///
+/// let diagram = generate_diagram_from_foundry("out", Config::default()).unwrap();
+/// println!("{}", diagram);
+/// ```
+pub fn generate_diagram_from_foundry<P: AsRef<Path>>(out_dir: P, config: Config) -> Result<String> {
+    let combined_ast = load_foundry_artifacts(out_dir.as_ref())?;
+
+    Ok(render_and_write(&combined_ast, config)?)
+}
+
+/// Generate a sequence diagram from Hardhat build-info files
+///
+/// # Arguments
+///
+/// * `build_info_path` - A build-info JSON file or directory (`artifacts/build-info`)
+/// * `config` - Configuration for diagram generation
+///
+/// # Returns
+///
+/// The generated diagram as a string
+///
+/// # Example
+///
+/// ```no_run
+/// use sol2seq::{Config, generate_diagram_from_hardhat};
+///
+/// let diagram = generate_diagram_from_hardhat("artifacts/build-info", Config::default()).unwrap();
+/// println!("{}", diagram);
+/// ```
+pub fn generate_diagram_from_hardhat<P: AsRef<Path>>(
+    build_info_path: P,
+    config: Config,
+) -> Result<String> {
+    let combined_ast = load_hardhat_build_info(build_info_path.as_ref())?;
+
+    Ok(render_and_write(&combined_ast, config)?)
+}
+
+/// Generate a sequence diagram from AST JSON text
+///
+/// Nothing is read from or written to disk and solc is never run, so this is the entry
+/// point for `wasm32-unknown-unknown` builds; `config.output_file` is ignored.
+///
+/// # Arguments
+///
+/// * `ast_json` - AST JSON in any of the layouts accepted by `generate_diagram_from_file`
+/// * `config` - Configuration for diagram generation
+///
+/// # Returns
+///
+/// The generated diagram as a string
+///
+/// # Example
+///
+/// ```
+/// use sol2seq::{Config, generate_diagram_from_str};
+///
+/// let ast = r#"{ "nodeType": "SourceUnit", "nodes": [] }"#;
+/// let diagram = generate_diagram_from_str(ast, Config::default()).unwrap();
+/// assert!(diagram.starts_with("```mermaid"));
+/// ```
+pub fn generate_diagram_from_str(ast_json: &str, config: Config) -> Result<String> {
+    let ast: serde_json::Value =
+        serde_json::from_str(ast_json).context("Failed to parse AST JSON")?;
+
+    diagram::generate_sequence_diagram_with_config(&ast, config)
 }
 
 // Re-export types for public API
-pub use diagram::generate_sequence_diagram;
+pub use diagram::{
+    generate_call_graph, generate_class_diagram, generate_sequence_diagram,
+    generate_sequence_diagram_with_config,
+};
+pub use error::{Result, Sol2SeqError};
 pub use types::{
-    ContractInfo, ContractRelationship, DiagramData, Interaction, InteractionType, Parameter,
-    StateVariable,
+    ContractInfo, ContractRelationship, DiagramData, DiagramType, FunctionInfo, Interaction,
+    InteractionType, OutputFormat, Parameter, ParticipantOrder, StateVariable, Theme,
+    ValidationReport,
 };
@@ -1,7 +1,10 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use sol2seq::Config;
-use std::path::PathBuf;
+use sol2seq::{Config, DiagramType, OutputFormat, ParticipantOrder, Theme};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 /// Solidity Sequence Diagram Generator
 ///
@@ -17,13 +20,156 @@ struct Args {
     #[clap(subcommand)]
     command: Commands,
 
+    /// Config file to read options from (defaults to `sol2seq.toml` if present)
+    #[clap(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
     /// Use lighter colors for diagram
-    #[clap(long, short, action)]
-    light_colors: bool,
-    
+    #[clap(
+        long,
+        short,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        conflicts_with = "theme"
+    )]
+    light_colors: Option<bool>,
+
+    /// Color theme (default, light, dark) [default: default]
+    #[clap(long)]
+    theme: Option<Theme>,
+
     /// Disable storage update notes in the diagram
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    no_storage_updates: Option<bool>,
+
+    /// Only list each contract's public/external function signatures
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    only_public_api: Option<bool>,
+
+    /// Append a Markdown matrix of cross-contract call sites
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    call_matrix: Option<bool>,
+
+    /// Output format (mermaid, structurizr, plantuml, json) [default: mermaid]
+    #[clap(long)]
+    format: Option<OutputFormat>,
+
+    /// Mermaid diagram type (sequence, class, callgraph) [default: sequence]
+    #[clap(long)]
+    diagram_type: Option<DiagramType>,
+
+    /// Map a function name or pattern to the actor calling it (e.g. `harvest=Keeper`)
+    #[clap(long = "actor", value_name = "FUNCTION=ACTOR", value_parser = parse_key_value)]
+    actors: Vec<(String, String)>,
+
+    /// solc binary used to compile sources (defaults to `solc` on PATH)
+    #[clap(long = "solc", value_name = "PATH")]
+    solc_path: Option<PathBuf>,
+
+    /// Extra argument passed to solc (repeatable, e.g. `--solc-arg=--base-path=.`)
+    #[clap(long = "solc-arg", value_name = "ARG", allow_hyphen_values = true)]
+    solc_args: Vec<String>,
+
+    /// File of `prefix=target` import remappings to forward to solc
+    #[clap(long, value_name = "PATH")]
+    remappings_file: Option<PathBuf>,
+
+    /// JSON object of `"functionName": "description"` notes for entrypoints and calls
+    #[clap(long, value_name = "PATH")]
+    purposes_file: Option<PathBuf>,
+
+    /// Reuse solc output cached in `.sol2seq-cache/` when the sources are unchanged
     #[clap(long, action)]
-    no_storage_updates: bool,
+    cache: bool,
+
+    /// Always run solc, even when the config file enables the cache
+    #[clap(long, action, conflicts_with = "cache")]
+    no_cache: bool,
+
+    /// Only diagram this contract and its direct call targets (repeatable)
+    #[clap(long = "contract", value_name = "NAME")]
+    contracts: Vec<String>,
+
+    /// Function visibility rendered as a user entrypoint (repeatable) [default: public, external]
+    #[clap(long = "visibility", value_name = "VISIBILITY")]
+    visibilities: Vec<String>,
+
+    /// Truncate the diagram after this many interactions
+    #[clap(long, value_name = "N")]
+    max_interactions: Option<usize>,
+
+    /// Collapse consecutive identical interaction lines into one with an `(xN)` multiplier
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    collapse_duplicates: Option<bool>,
+
+    /// Participant order (alphabetical, first-seen) [default: alphabetical]
+    #[clap(long)]
+    participant_order: Option<ParticipantOrder>,
+
+    /// Show calls and ETH sends to `msg.sender` against a dedicated `Caller` participant
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    show_msg_sender: Option<bool>,
+
+    /// Append the 4-byte function selector to each entrypoint arrow
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    show_selectors: Option<bool>,
+
+    /// Fold view/pure returns into the call arrow instead of a separate return line
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    compact_view_returns: Option<bool>,
+
+    /// Omit the legend section
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    no_legend: Option<bool>,
+
+    /// Omit the Event Definitions section
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    no_events: Option<bool>,
+
+    /// Omit the Error Definitions section
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    no_errors: Option<bool>,
+
+    /// Omit the Contract Relationships section
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    no_relationships: Option<bool>,
+
+    /// Diagram title (defaults to "Smart Contract Interaction Sequence Diagram")
+    #[clap(long)]
+    title: Option<String>,
+
+    /// Mark state changes that follow an external call (a reentrancy review aid)
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    flag_reentrancy: Option<bool>,
+
+    /// Group contracts from the same source file in a box
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    group_by_file: Option<bool>,
+
+    /// Show each entrypoint's calls between its arrow and return instead of a later section
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    inline_bodies: Option<bool>,
+
+    /// Emit bare Mermaid without theme blocks or colored sections
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    plain: Option<bool>,
+}
+
+/// Where a generating subcommand writes its diagram
+#[derive(clap::Args, Debug)]
+struct OutputArgs {
+    /// Output file path (optional, will print to stdout if not provided)
+    #[clap(short, long = "output", value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Print the diagram to stdout, ignoring any positional output path
+    #[clap(long, action, conflicts_with_all = ["output", "per_contract"])]
+    stdout: bool,
+
+    /// Write one `<Contract>.md` diagram per contract into the output directory
+    #[clap(long, action, requires = "output")]
+    per_contract: bool,
 }
 
 #[derive(Subcommand, Debug)]
@@ -32,7 +178,9 @@ enum Commands {
     Ast {
         /// AST JSON file path
         ast_file: PathBuf,
-        /// Output file path (optional, will print to stdout if not provided)
+        #[clap(flatten)]
+        output: OutputArgs,
+        /// Deprecated: use `-o/--output` instead
         output_file: Option<PathBuf>,
     },
     /// Generate diagram from Solidity source files or directories
@@ -40,30 +188,193 @@ enum Commands {
         /// Solidity source files or directories to process (directories will be recursively searched for .sol files)
         #[clap(required = true)]
         source_paths: Vec<PathBuf>,
-        /// Output file path (optional, will print to stdout if not provided)
+        #[clap(flatten)]
+        output: OutputArgs,
+        /// Deprecated: use `-o/--output` instead (only accepted after `--`)
         #[clap(last = true)]
         output_file: Option<PathBuf>,
     },
+    /// Generate diagram from Foundry build artifacts (`forge build` output directory)
+    Foundry {
+        /// Foundry output directory
+        #[clap(default_value = "out")]
+        out_dir: PathBuf,
+        #[clap(flatten)]
+        output: OutputArgs,
+        /// Deprecated: use `-o/--output` instead
+        output_file: Option<PathBuf>,
+    },
+    /// Generate diagram from Hardhat build-info files
+    Hardhat {
+        /// Build-info JSON file or directory
+        #[clap(default_value = "artifacts/build-info")]
+        build_info: PathBuf,
+        #[clap(flatten)]
+        output: OutputArgs,
+        /// Deprecated: use `-o/--output` instead
+        output_file: Option<PathBuf>,
+    },
+    /// Check that an AST JSON file can be processed, without rendering a diagram
+    Check {
+        /// AST JSON file path
+        ast_file: PathBuf,
+    },
+    /// Remove the solc output cache (`.sol2seq-cache/`)
+    CleanCache,
+}
+
+impl Commands {
+    /// Whether `--per-contract` was given
+    fn per_contract(&self) -> bool {
+        match self {
+            Commands::Ast { output, .. }
+            | Commands::Source { output, .. }
+            | Commands::Foundry { output, .. }
+            | Commands::Hardhat { output, .. } => output.per_contract,
+            Commands::Check { .. } | Commands::CleanCache => false,
+        }
+    }
+
+    /// Output file requested with `-o/--output` or, failing that, the deprecated positional path
+    ///
+    /// `None` means the diagram goes to stdout, as it does for `--stdout`.
+    fn output_file(&self) -> Option<PathBuf> {
+        let (output, positional) = match self {
+            Commands::Ast { output, output_file, .. }
+            | Commands::Source { output, output_file, .. }
+            | Commands::Foundry { output, output_file, .. }
+            | Commands::Hardhat { output, output_file, .. } => (output, output_file),
+            Commands::Check { .. } | Commands::CleanCache => return None,
+        };
+        if output.stdout {
+            return None;
+        }
+        if output.output.is_none() && positional.is_some() {
+            eprintln!("warning: the positional output path is deprecated, use `-o/--output`");
+        }
+        output.output.clone().or_else(|| positional.clone())
+    }
+}
+
+/// Directory caching solc output between runs
+const CACHE_DIR: &str = ".sol2seq-cache";
+
+/// Config file read from the current directory when `--config` is not given
+const CONFIG_FILE: &str = "sol2seq.toml";
+
+/// Parse a `key=value` command-line argument
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{}`", s))
 }
 
 fn main() -> Result<()> {
     env_logger::init();
 
     let args = Args::parse();
+
+    let output_file = args.command.output_file();
+
+    // `--per-contract` or a directory as the output gets one `<Contract>.md` per contract
+    let per_contract_dir = match &output_file {
+        Some(path) if args.command.per_contract() || path.is_dir() => Some(path.clone()),
+        _ => None,
+    };
     let has_output_file = match &args.command {
-        Commands::Ast { output_file, .. } => output_file.is_some(),
-        Commands::Source { output_file, .. } => output_file.is_some(),
+        Commands::Check { .. } | Commands::CleanCache => true,
+        _ => output_file.is_some(),
     };
 
-    // Create configuration
-    let config = Config {
-        light_colors: args.light_colors,
-        output_file: match &args.command {
-            Commands::Ast { output_file, .. } => output_file.clone(),
-            Commands::Source { output_file, .. } => output_file.clone(),
-        },
-        show_storage_updates: !args.no_storage_updates,
+    // Options come from the command line, then the config file, then the defaults
+    let mut config = match &args.config {
+        Some(path) => sol2seq::read_config_file(path)?,
+        None if Path::new(CONFIG_FILE).is_file() => sol2seq::read_config_file(CONFIG_FILE)?,
+        None => Config::default(),
     };
+    // `--theme` and `--light-colors` conflict; either replaces the file's color settings
+    if let Some(theme) = args.theme {
+        config.theme = theme;
+        config.light_colors = false;
+    }
+    if let Some(light_colors) = args.light_colors {
+        config.light_colors = light_colors;
+        if light_colors {
+            config.theme = Theme::Default;
+        }
+    }
+    config.output_file = if per_contract_dir.is_some() { None } else { output_file };
+    config.show_storage_updates =
+        args.no_storage_updates.map_or(config.show_storage_updates, |off| !off);
+    config.only_public_api = args.only_public_api.unwrap_or(config.only_public_api);
+    config.emit_call_matrix = args.call_matrix.unwrap_or(config.emit_call_matrix);
+    config.output_format = args.format.unwrap_or(config.output_format);
+    config.diagram_type = args.diagram_type.unwrap_or(config.diagram_type);
+    config.actor_map.extend(args.actors);
+    if let Some(path) = &args.purposes_file {
+        config.function_purposes.extend(sol2seq::read_purposes_file(path)?);
+    }
+    config.solc_path = args.solc_path.or(config.solc_path);
+    if !args.solc_args.is_empty() {
+        config.solc_extra_args = args.solc_args;
+    }
+    if let Some(path) = &args.remappings_file {
+        config.remappings = sol2seq::read_remappings_file(path)?;
+    }
+    if args.cache {
+        config.cache_dir = Some(PathBuf::from(CACHE_DIR));
+    } else if args.no_cache {
+        config.cache_dir = None;
+    }
+    if !args.contracts.is_empty() {
+        config.contracts = args.contracts;
+    }
+    if !args.visibilities.is_empty() {
+        config.include_visibilities = args.visibilities;
+    }
+    config.max_interactions = args.max_interactions.or(config.max_interactions);
+    config.collapse_duplicates = args.collapse_duplicates.unwrap_or(config.collapse_duplicates);
+    config.participant_order = args.participant_order.unwrap_or(config.participant_order);
+    config.show_msg_sender = args.show_msg_sender.unwrap_or(config.show_msg_sender);
+    config.show_selectors = args.show_selectors.unwrap_or(config.show_selectors);
+    config.compact_view_returns = args.compact_view_returns.unwrap_or(config.compact_view_returns);
+    config.show_legend = args.no_legend.map_or(config.show_legend, |off| !off);
+    config.show_events_section = args.no_events.map_or(config.show_events_section, |off| !off);
+    config.show_errors_section = args.no_errors.map_or(config.show_errors_section, |off| !off);
+    config.show_relationships_section =
+        args.no_relationships.map_or(config.show_relationships_section, |off| !off);
+    config.title = args.title.or(config.title);
+    config.flag_reentrancy = args.flag_reentrancy.unwrap_or(config.flag_reentrancy);
+    config.group_by_source_file = args.group_by_file.unwrap_or(config.group_by_source_file);
+    config.inline_bodies = args.inline_bodies.unwrap_or(config.inline_bodies);
+    config.plain = args.plain.unwrap_or(config.plain);
+
+    if let Some(dir) = &per_contract_dir {
+        let diagrams = match &args.command {
+            Commands::Ast { ast_file, .. } => {
+                sol2seq::generate_diagrams_per_contract_from_file(ast_file, config)?
+            }
+            Commands::Source { source_paths, .. } => {
+                sol2seq::generate_diagrams_per_contract(source_paths, config)?
+            }
+            Commands::Foundry { out_dir, .. } => {
+                sol2seq::generate_diagrams_per_contract_from_foundry(out_dir, config)?
+            }
+            Commands::Hardhat { build_info, .. } => {
+                sol2seq::generate_diagrams_per_contract_from_hardhat(build_info, config)?
+            }
+            Commands::Check { .. } | Commands::CleanCache => unreachable!("no output path"),
+        };
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create output directory: {}", dir.display()))?;
+        for (contract_name, diagram) in &diagrams {
+            let path = dir.join(format!("{}.md", contract_name));
+            fs::write(&path, diagram)
+                .with_context(|| format!("Failed to write output file: {}", path.display()))?;
+        }
+        println!("Generated {} diagrams in {}", diagrams.len(), dir.display());
+        return Ok(());
+    }
 
     // Generate the diagram
     let diagram = match args.command {
@@ -73,6 +384,40 @@ fn main() -> Result<()> {
         Commands::Source { source_paths, .. } => {
             sol2seq::generate_diagram_from_sources(&source_paths, config)?
         }
+        Commands::Foundry { out_dir, .. } => {
+            sol2seq::generate_diagram_from_foundry(out_dir, config)?
+        }
+        Commands::Hardhat { build_info, .. } => {
+            sol2seq::generate_diagram_from_hardhat(build_info, config)?
+        }
+        Commands::Check { ast_file } => {
+            let report = sol2seq::validate_ast_file(&ast_file, &config)?;
+            println!("Contracts: {}", report.contracts);
+            println!("Functions: {}", report.functions);
+            println!("Interactions: {}", report.interactions);
+            if !report.contracts_without_bodies.is_empty() {
+                println!(
+                    "Contracts without function bodies: {}",
+                    report.contracts_without_bodies.join(", ")
+                );
+            }
+            if !report.missing_fields.is_empty() {
+                println!("Missing fields: {}", report.missing_fields.join(", "));
+            }
+            if report.contracts == 0 {
+                anyhow::bail!("No contracts found in {}", ast_file.display());
+            }
+            if !report.is_valid() {
+                anyhow::bail!("{} is missing expected AST fields", ast_file.display());
+            }
+            return Ok(());
+        }
+        Commands::CleanCache => {
+            let cache_dir = config.cache_dir.unwrap_or_else(|| PathBuf::from(CACHE_DIR));
+            sol2seq::clean_cache(&cache_dir)?;
+            println!("Removed {}", cache_dir.display());
+            return Ok(());
+        }
     };
 
     // If no output file specified, print to stdout
@@ -1,8 +1,117 @@
-use indexmap::IndexMap;
-use std::collections::{HashMap, HashSet};
+use indexmap::{IndexMap, IndexSet};
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+/// Output format for the generated diagram
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Mermaid sequence diagram
+    #[default]
+    Mermaid,
+    /// Structurizr DSL workspace describing contracts as C4 components
+    Structurizr,
+    /// PlantUML sequence diagram
+    PlantUml,
+    /// JSON dump of the extracted `DiagramData` model
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mermaid" => Ok(Self::Mermaid),
+            "structurizr" => Ok(Self::Structurizr),
+            "plantuml" => Ok(Self::PlantUml),
+            "json" => Ok(Self::Json),
+            other => Err(format!("Unknown output format: {}", other)),
+        }
+    }
+}
+
+/// Kind of Mermaid diagram to generate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagramType {
+    /// Sequence diagram of entrypoints and the calls they make
+    #[default]
+    Sequence,
+    /// Class diagram of contracts, their members and relationships
+    Class,
+    /// Flowchart of which functions call which, across contracts
+    CallGraph,
+}
+
+impl FromStr for DiagramType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sequence" => Ok(Self::Sequence),
+            "class" => Ok(Self::Class),
+            "callgraph" => Ok(Self::CallGraph),
+            other => Err(format!("Unknown diagram type: {}", other)),
+        }
+    }
+}
+
+/// Left-to-right order of participant lifelines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParticipantOrder {
+    /// Alphabetical, between `User` and `Events`
+    #[default]
+    Alphabetical,
+    /// In the order participants are first encountered in the AST and its calls
+    #[serde(alias = "firstseen")]
+    FirstSeen,
+}
+
+impl FromStr for ParticipantOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "alphabetical" => Ok(Self::Alphabetical),
+            "first-seen" | "firstseen" => Ok(Self::FirstSeen),
+            other => Err(format!("Unknown participant order: {}", other)),
+        }
+    }
+}
+
+/// Color theme of the generated Mermaid diagram
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    /// Neutral greys on a light background
+    #[default]
+    Default,
+    /// Lighter pastel colors
+    Light,
+    /// Dark backgrounds with light text, for dark-mode documentation sites
+    Dark,
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "default" => Ok(Self::Default),
+            "light" => Ok(Self::Light),
+            "dark" => Ok(Self::Dark),
+            other => Err(format!("Unknown theme: {}", other)),
+        }
+    }
+}
 
 /// Represents a contract's state variable
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct StateVariable {
     pub name: String,
     pub var_type: String,
@@ -10,14 +119,26 @@ pub struct StateVariable {
 }
 
 /// Represents a function parameter or return value
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Parameter {
     pub name: String,
     pub param_type: String,
+    pub indexed: bool, // only set on event parameters
+}
+
+/// Represents a function's callable signature
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub kind: String,
+    pub visibility: String,
+    pub state_mutability: String,
+    pub parameters: Vec<Parameter>,
+    pub returns: Vec<Parameter>,
 }
 
 /// Represents a sequence diagram interaction
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum InteractionType {
     Call,
     Return,
@@ -25,7 +146,7 @@ pub enum InteractionType {
 }
 
 /// Represents a diagram interaction between participants
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Interaction {
     pub interaction_type: InteractionType,
     pub from: String,
@@ -36,32 +157,84 @@ pub struct Interaction {
 }
 
 /// Represents contract information
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct ContractInfo {
     pub name: String,
     pub events: Vec<String>,
+    #[serde(serialize_with = "sorted_map")]
+    pub event_parameters: HashMap<String, Vec<Parameter>>,
+    pub errors: Vec<String>, // Custom `error` definitions
+    #[serde(serialize_with = "sorted_map")]
+    pub error_parameters: HashMap<String, Vec<Parameter>>,
     pub functions: Vec<String>,
+    pub function_details: Vec<FunctionInfo>,
     pub variables: Vec<(String, String)>,
     pub inherits_from: Vec<String>,
+    pub linearized_bases: Vec<String>, // `linearizedBaseContracts` without the contract itself
+    #[serde(serialize_with = "sorted_map")]
+    pub base_arguments: HashMap<String, Vec<String>>, // Arguments in `is Base(args)` by base
     pub contract_type: String,
     pub source_file: String,
+    pub using_for: Vec<(String, String)>, // (type, library) bindings
+    pub inherited_functions: Vec<(String, String)>, // (function, base contract) pairs
+    pub notice: Option<String>, // NatSpec `@notice` of the contract
 }
 
 /// Relationship between contracts
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ContractRelationship {
     pub source: String,
     pub target: String,
     pub relation_type: String,
+    pub order: Option<usize>, // Position of an `inherits` base in the `is` list
 }
 
 /// Container for all extracted contract information
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct DiagramData {
-    pub participants: HashSet<String>,
+    #[serde(serialize_with = "sorted_set")]
+    pub participants: IndexSet<String>, // In first-seen order
+    #[serde(serialize_with = "sorted_map")]
     pub contracts: HashMap<String, ContractInfo>,
     pub user_interactions: Vec<String>,
+    #[serde(skip)]
+    pub entrypoint_keys: HashMap<String, String>, // `Contract.function` by entrypoint arrow line
     pub contract_interactions: IndexMap<String, Vec<String>>, // Grouped by function
     pub events: Vec<(String, String)>,
+    pub errors: Vec<(String, String)>, // Custom errors as (contract, error)
     pub contract_relationships: Vec<ContractRelationship>,
+    #[serde(serialize_with = "sorted_map")]
+    pub declaration_types: HashMap<i64, String>, // Declared type by AST node id
+    pub call_graph: Vec<(String, String)>, // (caller, callee) as `Contract.function`
+    pub constants: Vec<(String, String)>, // File-level constants as (name, value)
+}
+
+/// Serialize a participant set as a sorted array so the JSON export is stable
+fn sorted_set<S: Serializer>(set: &IndexSet<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    set.iter().collect::<std::collections::BTreeSet<_>>().serialize(serializer)
+}
+
+/// Serialize a hash map with its keys sorted so the JSON export is stable
+fn sorted_map<K: Ord + Serialize, V: Serialize, S: Serializer>(
+    map: &HashMap<K, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+/// What `validate_ast` extracted from an AST, for checking inputs before rendering
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ValidationReport {
+    pub contracts: usize,
+    pub functions: usize,
+    pub interactions: usize,
+    pub contracts_without_bodies: Vec<String>, // Non-interface contracts with no function body
+    pub missing_fields: Vec<String>, // Absent AST fields, e.g. `Token.transfer.visibility`
+}
+
+impl ValidationReport {
+    /// Whether at least one contract was extracted and no expected field was missing
+    pub fn is_valid(&self) -> bool {
+        self.contracts > 0 && self.missing_fields.is_empty()
+    }
 }
@@ -1,5 +1,9 @@
+use crate::types::{ContractInfo, Parameter};
 use anyhow::Result;
 use serde_json::Value;
+use itertools::Itertools;
+use std::collections::HashMap;
+use tiny_keccak::{Hasher, Keccak};
 
 /// Extract a type name from an AST type node
 pub fn extract_type_name(type_node: &Value) -> String {
@@ -12,8 +16,19 @@ pub fn extract_type_name(type_node: &Value) -> String {
     match node_type {
         "ElementaryTypeName" => type_node["name"].as_str().unwrap_or("unknown").to_string(),
         "UserDefinedTypeName" => {
-            // Check if it has a path representation for contract types
-            if type_node.get("pathNode").is_some() {
+            // Prefer the short name of enums, structs and contracts over qualified paths
+            if let Some(short_name) =
+                type_node["typeDescriptions"]["typeString"].as_str().and_then(short_type_name)
+            {
+                short_name
+            } else if let Some(alias) = type_node["typeDescriptions"]["typeString"]
+                .as_str()
+                .filter(|type_string| !type_string.contains(' '))
+            {
+                // User-defined value types carry their bare, possibly qualified, alias
+                alias.rsplit('.').next().unwrap_or(alias).to_string()
+            } else if type_node.get("pathNode").is_some() {
+                // Check if it has a path representation for contract types
                 type_node["pathNode"]["name"]
                     .as_str()
                     .or_else(|| type_node["name"].as_str())
@@ -75,6 +90,150 @@ pub fn extract_type_name(type_node: &Value) -> String {
     }
 }
 
+/// Normalize a compiler `typeString` into a short type name
+///
+/// Strips the declaration kind prefix (`contract IERC20` → `IERC20`) and any data
+/// location suffix (`struct S storage ref` → `S`).
+pub fn normalize_type_string(type_string: &str) -> String {
+    let mut type_name = type_string;
+
+    for prefix in ["contract ", "struct ", "enum ", "library "] {
+        if let Some(stripped) = type_name.strip_prefix(prefix) {
+            type_name = stripped;
+            break;
+        }
+    }
+
+    for suffix in [" storage ref", " storage pointer", " memory", " calldata"] {
+        if let Some(stripped) = type_name.strip_suffix(suffix) {
+            type_name = stripped;
+            break;
+        }
+    }
+
+    type_name.to_string()
+}
+
+/// Canonical ABI form of a parameter type, as used in function signatures
+///
+/// Expands aliases (`uint` → `uint256`), maps contract types to `address` and enums to
+/// `uint8`, and keeps array suffixes. Returns `None` for structs and other user-defined
+/// types, whose ABI form cannot be told from the name alone.
+pub fn canonical_abi_type(
+    type_name: &str,
+    contracts: &HashMap<String, ContractInfo>,
+) -> Option<String> {
+    let mut type_name = type_name.trim();
+    for suffix in [" storage ref", " storage pointer", " memory", " calldata"] {
+        if let Some(stripped) = type_name.strip_suffix(suffix) {
+            type_name = stripped;
+            break;
+        }
+    }
+
+    let (base, arrays) = type_name.find('[').map_or((type_name, ""), |i| type_name.split_at(i));
+    let base = match base {
+        "uint" => "uint256".to_string(),
+        "int" => "int256".to_string(),
+        "byte" => "bytes1".to_string(),
+        "fixed" => "fixed128x18".to_string(),
+        "ufixed" => "ufixed128x18".to_string(),
+        "address payable" => "address".to_string(),
+        _ if base.starts_with("contract ") || contracts.contains_key(base) => "address".to_string(),
+        _ if base.starts_with("enum ") => "uint8".to_string(),
+        _ if is_elementary_type(base) => base.to_string(),
+        _ => return None,
+    };
+
+    Some(format!("{}{}", base, arrays))
+}
+
+/// Check whether a type name is a built-in value type such as `bool`, `bytes32` or `uint64`
+fn is_elementary_type(type_name: &str) -> bool {
+    let sized = |prefix: &str, extra: &[char]| {
+        type_name
+            .strip_prefix(prefix)
+            .is_some_and(|size| size.chars().all(|c| c.is_ascii_digit() || extra.contains(&c)))
+    };
+
+    matches!(type_name, "address" | "bool" | "string" | "function")
+        || sized("uint", &[])
+        || sized("int", &[])
+        || sized("bytes", &[])
+        || sized("fixed", &['x'])
+        || sized("ufixed", &['x'])
+}
+
+/// 4-byte selector of a canonical function signature such as `transfer(address,uint256)`
+pub fn function_selector(signature: &str) -> String {
+    let mut hasher = Keccak::v256();
+    let mut hash = [0u8; 32];
+    hasher.update(signature.as_bytes());
+    hasher.finalize(&mut hash);
+
+    format!("0x{}", hash[..4].iter().map(|byte| format!("{:02x}", byte)).join(""))
+}
+
+/// Keccak-256 of a sequence of byte strings as lowercase hex, stable across runs and releases
+///
+/// Each part is length-prefixed so that `["ab", "c"]` and `["a", "bc"]` hash differently.
+pub fn content_hash<P: AsRef<[u8]>>(parts: &[P]) -> String {
+    let mut hasher = Keccak::v256();
+    let mut hash = [0u8; 32];
+    for part in parts {
+        let part = part.as_ref();
+        hasher.update(&(part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize(&mut hash);
+
+    hash.iter().map(|byte| format!("{:02x}", byte)).join("")
+}
+
+/// Shorten an `enum`, `struct` or `contract` type string to its unqualified name
+fn short_type_name(type_string: &str) -> Option<String> {
+    if !["enum ", "struct ", "contract "].iter().any(|prefix| type_string.starts_with(prefix)) {
+        return None;
+    }
+
+    let type_name = normalize_type_string(type_string);
+    Some(type_name.rsplit('.').next().unwrap_or(&type_name).to_string())
+}
+
+/// Extract the parameters declared in a `ParameterList` node
+pub fn extract_parameters(parameter_list: &Value) -> Vec<Parameter> {
+    let Some(parameters) = parameter_list.get("parameters").and_then(|p| p.as_array()) else {
+        return Vec::new();
+    };
+
+    parameters
+        .iter()
+        .map(|param| {
+            let mut param_type = "unknown".to_string();
+            if param.get("typeName").is_some() {
+                param_type = extract_type_name(&param["typeName"]);
+            }
+
+            // Fall back to the compiler's type description
+            if param_type == "unknown" {
+                if let Some(type_str) = param
+                    .get("typeDescriptions")
+                    .and_then(|td| td.get("typeString"))
+                    .and_then(|ts| ts.as_str())
+                {
+                    param_type = type_str.to_string();
+                }
+            }
+
+            Parameter {
+                name: param["name"].as_str().unwrap_or("").to_string(),
+                param_type,
+                indexed: param["indexed"].as_bool().unwrap_or(false),
+            }
+        })
+        .collect()
+}
+
 /// Extract return type information from a function definition
 pub fn extract_return_type(function_node: &Value) -> Option<String> {
     if let Some(return_parameters) = function_node.get("returnParameters") {
@@ -135,8 +294,50 @@ pub fn extract_return_type(function_node: &Value) -> Option<String> {
     None
 }
 
+/// Extract the `@notice` text of a NatSpec `documentation` node
+///
+/// Accepts both the `StructuredDocumentation` object and the legacy plain string. Text
+/// without any tag counts as `@notice`, as in solc.
+pub fn natspec_notice(documentation: &Value) -> Option<String> {
+    let text = documentation.get("text").unwrap_or(documentation).as_str()?;
+    let lines = text
+        .lines()
+        .map(|line| line.trim().trim_start_matches('/').trim_start_matches('*').trim());
+
+    let mut notice: Vec<&str> = Vec::new();
+    let mut in_notice = !text.contains('@');
+    for line in lines {
+        if let Some(tagged) = line.strip_prefix('@') {
+            let (tag, rest) = tagged.split_once(char::is_whitespace).unwrap_or((tagged, ""));
+            in_notice = tag == "notice";
+            if in_notice {
+                notice.push(rest.trim());
+            }
+        } else if in_notice && !line.is_empty() {
+            notice.push(line);
+        }
+    }
+
+    let notice = notice.into_iter().filter(|line| !line.is_empty()).join(" ");
+    (!notice.is_empty()).then_some(notice)
+}
+
 /// Get a description of a function based on its name
-pub fn get_function_purpose(function_name: &str) -> Option<String> {
+///
+/// Custom purposes win over the built-in ones. Within each, the exact name wins, otherwise
+/// the longest key the name starts with, so `mintTo` is minting but `sunburn` is not burning.
+pub fn get_function_purpose(
+    function_name: &str,
+    custom_purposes: &HashMap<String, String>,
+) -> Option<String> {
+    if let Some(purpose) = custom_purposes.get(function_name) {
+        return Some(purpose.clone());
+    }
+    let custom = custom_purposes.iter().map(|(key, purpose)| (key.as_str(), purpose.as_str()));
+    if let Some(purpose) = match_purpose(function_name, custom) {
+        return Some(purpose.to_string());
+    }
+
     let common_functions = [
         ("constructor", "Contract initialization"),
         ("transfer", "Transfer tokens or ETH"),
@@ -157,27 +358,107 @@ pub fn get_function_purpose(function_name: &str) -> Option<String> {
         ("airdropToKeyIds", "Send ETH to wallets identified by public keys"),
     ];
 
-    for (key, description) in common_functions.iter() {
-        if function_name.to_lowercase().contains(&key.to_lowercase()) {
-            return Some(description.to_string());
-        }
+    match_purpose(function_name, common_functions.iter().copied()).map(str::to_string)
+}
+
+/// Find the purpose of the longest key the function name starts with (case-insensitively)
+///
+/// A key equal to the whole name is the longest possible prefix, so exact names win.
+fn match_purpose<'a>(
+    function_name: &str,
+    entries: impl Iterator<Item = (&'a str, &'a str)>,
+) -> Option<&'a str> {
+    let lowercase_name = function_name.to_lowercase();
+    entries
+        .filter(|(key, _)| lowercase_name.starts_with(&key.to_lowercase()))
+        .max_by(|a, b| a.0.len().cmp(&b.0.len()).then_with(|| b.0.cmp(a.0)))
+        .map(|(_, purpose)| purpose)
+}
+
+/// Get a short description of what a well-known modifier enforces
+pub fn get_modifier_purpose(modifier_name: &str) -> Option<String> {
+    let description = match modifier_name {
+        "onlyOwner" => "caller must be the owner",
+        "onlyRole" => "caller must hold the role",
+        "nonReentrant" => "blocks reentrant calls",
+        "whenNotPaused" => "contract must not be paused",
+        "whenPaused" => "contract must be paused",
+        "initializer" => "runs only once during initialization",
+        _ => return None,
+    };
+
+    Some(description.to_string())
+}
+
+/// Check whether a name matches a pattern with optional leading/trailing `*` wildcards
+pub fn matches_pattern(name: &str, pattern: &str) -> bool {
+    match (pattern.strip_prefix('*'), pattern.strip_suffix('*')) {
+        (Some(rest), _) if rest.ends_with('*') => name.contains(rest.trim_end_matches('*')),
+        (Some(suffix), _) => name.ends_with(suffix),
+        (_, Some(prefix)) => name.starts_with(prefix),
+        _ => name == pattern,
     }
+}
 
-    None
+/// Resolve the actor calling a function from a function-name → actor map
+///
+/// Exact names take precedence over wildcard patterns; unmapped functions default to `User`.
+pub fn resolve_actor(function_name: &str, actor_map: &HashMap<String, String>) -> String {
+    if let Some(actor) = actor_map.get(function_name) {
+        return actor.clone();
+    }
+
+    actor_map
+        .iter()
+        .filter(|(pattern, _)| pattern.contains('*'))
+        .sorted_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(b.0)))
+        .find(|(pattern, _)| matches_pattern(function_name, pattern))
+        .map(|(_, actor)| actor.clone())
+        .unwrap_or_else(|| "User".to_string())
 }
 
 /// Determine if a variable is important enough to include in the contract description
-pub fn is_important_variable(var_name: &str) -> bool {
-    let important_prefixes =
-        ["owner", "admin", "token", "deployer", "implementation", "registry", "factory"];
+///
+/// A prefix only matches at a camelCase or snake_case word boundary, so `stakingToken` and
+/// `ADMIN_ROLE` are important but `brokenFlag` and `tokenomicsDisabled` are not.
+pub fn is_important_variable(var_name: &str, important_prefixes: &[String]) -> bool {
+    let words = name_words(var_name);
+    important_prefixes.iter().any(|prefix| {
+        let prefix_words = name_words(prefix);
+        !prefix_words.is_empty()
+            && words.windows(prefix_words.len()).any(|window| {
+                let (last, rest) = window.split_last().unwrap();
+                let (prefix_last, prefix_rest) = prefix_words.split_last().unwrap();
+                rest == prefix_rest
+                    && (last == prefix_last || *last == format!("{}s", prefix_last))
+            })
+    })
+}
 
-    for prefix in important_prefixes.iter() {
-        if var_name.to_lowercase().contains(prefix) {
-            return true;
+/// Split an identifier into its lowercase camelCase / snake_case words
+fn name_words(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            words.push(std::mem::take(&mut word));
+            continue;
+        }
+        // `fooBar` and the `T` of `USDCToken` start a new word
+        let starts_word = c.is_uppercase()
+            && i > 0
+            && (chars[i - 1].is_lowercase()
+                || chars[i - 1].is_uppercase()
+                    && chars.get(i + 1).is_some_and(|next| next.is_lowercase()));
+        if starts_word {
+            words.push(std::mem::take(&mut word));
         }
+        word.extend(c.to_lowercase());
     }
-
-    false
+    words.push(word);
+    words.retain(|word| !word.is_empty());
+    words
 }
 
 /// Guess the type of a variable based on its name
@@ -214,9 +495,361 @@ pub fn get_literal_type(literal: &Value) -> String {
     }
 }
 
+/// Describe an expression as readable Solidity-like source text
+pub fn describe_expression(expression: &Value) -> String {
+    match expression["nodeType"].as_str().unwrap_or("") {
+        "Identifier" => expression["name"].as_str().unwrap_or("?").to_string(),
+        "Literal" => {
+            let value = expression["value"].as_str().unwrap_or("?");
+            if expression["kind"].as_str() == Some("string") {
+                format!("\"{}\"", value)
+            } else {
+                value.to_string()
+            }
+        }
+        "MemberAccess" => format!(
+            "{}.{}",
+            describe_expression(&expression["expression"]),
+            expression["memberName"].as_str().unwrap_or("?")
+        ),
+        "IndexAccess" => format!(
+            "{}[{}]",
+            describe_expression(&expression["baseExpression"]),
+            describe_expression(&expression["indexExpression"])
+        ),
+        "BinaryOperation" => format!(
+            "{} {} {}",
+            describe_expression(&expression["leftExpression"]),
+            expression["operator"].as_str().unwrap_or("?"),
+            describe_expression(&expression["rightExpression"])
+        ),
+        "UnaryOperation" => {
+            let operator = expression["operator"].as_str().unwrap_or("?");
+            let operand = describe_expression(&expression["subExpression"]);
+            if expression["prefix"].as_bool() == Some(false) {
+                format!("{}{}", operand, operator)
+            } else if operator == "delete" {
+                format!("delete {}", operand)
+            } else {
+                format!("{}{}", operator, operand)
+            }
+        }
+        "FunctionCall" => {
+            let arguments = expression["arguments"]
+                .as_array()
+                .map(|args| args.iter().map(describe_expression).join(", "))
+                .unwrap_or_default();
+            format!("{}({})", describe_expression(&expression["expression"]), arguments)
+        }
+        "Conditional" => format!(
+            "{} ? {} : {}",
+            describe_expression(&expression["condition"]),
+            describe_expression(&expression["trueExpression"]),
+            describe_expression(&expression["falseExpression"])
+        ),
+        "TupleExpression" => {
+            let components = expression["components"]
+                .as_array()
+                .map(|components| components.iter().map(describe_expression).join(", "))
+                .unwrap_or_default();
+            format!("({})", components)
+        }
+        "ElementaryTypeNameExpression" => expression["typeName"]["name"]
+            .as_str()
+            .or_else(|| expression["typeName"].as_str())
+            .unwrap_or("?")
+            .to_string(),
+        _ => "...".to_string(),
+    }
+}
+
+/// Global functions built into Solidity
+const BUILTIN_FUNCTIONS: [&str; 14] = [
+    "keccak256",
+    "sha256",
+    "ripemd160",
+    "ecrecover",
+    "addmod",
+    "mulmod",
+    "blockhash",
+    "blobhash",
+    "gasleft",
+    "selfdestruct",
+    "require",
+    "assert",
+    "revert",
+    "type",
+];
+
+/// Global namespaces whose members are built in, e.g. `abi.encode` or `string.concat`
+const BUILTIN_NAMESPACES: [&str; 6] = ["abi", "block", "msg", "tx", "bytes", "string"];
+
+/// Check whether a call targets a Solidity built-in rather than a contract
+pub fn is_builtin_call(call: &Value) -> bool {
+    let callee = &call["expression"];
+    match callee["nodeType"].as_str() {
+        Some("Identifier") => {
+            callee["name"].as_str().is_some_and(|name| BUILTIN_FUNCTIONS.contains(&name))
+        }
+        Some("MemberAccess") => {
+            let base = &callee["expression"];
+            base["nodeType"].as_str() == Some("Identifier")
+                && base["name"].as_str().is_some_and(|name| BUILTIN_NAMESPACES.contains(&name))
+        }
+        _ => false,
+    }
+}
+
+/// Check whether a built-in call hashes, recovers a signer or ABI-encodes, which is worth a note
+pub fn is_notable_builtin_call(call: &Value) -> bool {
+    let callee = &call["expression"];
+    match callee["nodeType"].as_str() {
+        Some("Identifier") => matches!(
+            callee["name"].as_str(),
+            Some("keccak256" | "sha256" | "ripemd160" | "ecrecover")
+        ),
+        Some("MemberAccess") => callee["expression"]["name"].as_str() == Some("abi"),
+        _ => false,
+    }
+}
+
+/// Collapse runs of identical diagram lines into one line with an `(xN)` multiplier
+///
+/// An activated call is kept together with everything up to its matching return, so a
+/// repeated call collapses as a whole and activations stay balanced. Block keywords
+/// (`alt`, `loop`, `else`, `end`, ...) are kept as-is so the diagram structure is unchanged.
+pub fn collapse_duplicate_lines(lines: &[String]) -> Vec<String> {
+    let mut units: Vec<&[String]> = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let mut end = start + 1;
+        if lines[start].contains("->>+") {
+            let mut depth = 1;
+            while end < lines.len() && depth > 0 {
+                if lines[end].contains("->>+") {
+                    depth += 1;
+                } else if lines[end].contains("-->>-") {
+                    depth -= 1;
+                }
+                end += 1;
+            }
+            // A call without its return is left on its own
+            if depth > 0 {
+                end = start + 1;
+            }
+        }
+        units.push(&lines[start..end]);
+        start = end;
+    }
+
+    let mut collapsed = Vec::with_capacity(lines.len());
+    for (run, unit) in units.into_iter().dedup_with_count() {
+        let keyword = unit[0].split_whitespace().next().unwrap_or("");
+        let is_block = matches!(
+            keyword,
+            "alt" | "else" | "loop" | "opt" | "par" | "and" | "critical" | "break" | "rect" | "end"
+        );
+        if run > 1 && !is_block {
+            collapsed.push(format!("{} (x{})", unit[0], run));
+            collapsed.extend(unit[1..].iter().cloned());
+        } else {
+            for _ in 0..run {
+                collapsed.extend(unit.iter().cloned());
+            }
+        }
+    }
+
+    collapsed
+}
+
+/// Escape text so Mermaid renders it verbatim inside a message, note or participant label
+///
+/// `#` and `;` would start an entity code or end the statement, and an unbalanced bracket can
+/// be read as a shape delimiter, so each is replaced by its `#<code>;` entity. Brackets inside
+/// a `"string literal"` are always escaped so they cannot pair with the ones around it.
+pub fn escape_mermaid(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut unbalanced = vec![false; chars.len()];
+    let mut open: Vec<usize> = Vec::new();
+    let mut in_string = false;
+    for (i, &c) in chars.iter().enumerate() {
+        match c {
+            '"' => in_string = !in_string,
+            '(' | '[' | '{' | ')' | ']' | '}' if in_string => unbalanced[i] = true,
+            '(' | '[' | '{' => open.push(i),
+            ')' | ']' | '}' => {
+                let opener = match c {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                match open.last() {
+                    Some(&j) if chars[j] == opener => {
+                        open.pop();
+                    }
+                    _ => unbalanced[i] = true,
+                }
+            }
+            _ => {}
+        }
+    }
+    for i in open {
+        unbalanced[i] = true;
+    }
+
+    chars
+        .iter()
+        .zip(unbalanced)
+        .map(|(&c, unbalanced)| match c {
+            '#' | ';' => format!("#{};", c as u32),
+            _ if unbalanced => format!("#{};", c as u32),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+/// AST keys whose values are node ids (or arrays / maps of them) within one compilation
+const NODE_ID_KEYS: &[&str] = &[
+    "id",
+    "referencedDeclaration",
+    "scope",
+    "sourceUnit",
+    "linearizedBaseContracts",
+    "baseFunctions",
+    "baseModifiers",
+    "contractDependencies",
+    "usedErrors",
+    "usedEvents",
+    "overloadedDeclarations",
+    "assignments",
+    "superFunction",
+    "exportedSymbols",
+];
+
+/// Merge the output of a separate solc run into `target`, renumbering its AST node ids
+///
+/// Node ids are only unique within one compilation. Files already in `target` (shared
+/// imports) keep the ids they have there, so references into them still resolve; every
+/// other id is moved past the largest id in `target`.
+pub fn merge_compilation(target: &mut Value, output: &Value) -> Result<()> {
+    let offset = source_asts(target).into_iter().map(|(_, ast)| max_node_id(ast) + 1).max();
+    let Some(offset) = offset else {
+        return merge_ast_json(target, output);
+    };
+
+    let mut shared_ids = HashMap::new();
+    for (path, ast) in source_asts(output) {
+        if let Some((_, existing)) = source_asts(target).into_iter().find(|(p, _)| *p == path) {
+            map_shared_ids(existing, ast, &mut shared_ids);
+        }
+    }
+
+    let mut output = output.clone();
+    if let Some(sources) = output.get_mut("sources").and_then(Value::as_object_mut) {
+        for source in sources.values_mut() {
+            for key in ["AST", "ast"] {
+                if let Some(ast) = source.get_mut(key) {
+                    renumber_node_ids(ast, &shared_ids, offset);
+                }
+            }
+        }
+    }
+
+    merge_ast_json(target, &output)
+}
+
+/// Source unit ASTs of combined-json or standard-json output, by file path
+fn source_asts(output: &Value) -> Vec<(&str, &Value)> {
+    output["sources"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(path, source)| {
+            let ast = source.get("AST").or_else(|| source.get("ast"))?;
+            Some((path.as_str(), ast))
+        })
+        .collect()
+}
+
+/// Largest node id in an AST, or -1 when it has none
+fn max_node_id(node: &Value) -> i64 {
+    match node {
+        Value::Object(object) => {
+            let id = object.get("id").and_then(Value::as_i64).unwrap_or(-1);
+            object.values().map(max_node_id).fold(id, i64::max)
+        }
+        Value::Array(children) => children.iter().map(max_node_id).fold(-1, i64::max),
+        _ => -1,
+    }
+}
+
+/// Pair the node ids of two compilations of the same file by walking both ASTs together
+fn map_shared_ids(existing: &Value, incoming: &Value, ids: &mut HashMap<i64, i64>) {
+    match (existing, incoming) {
+        (Value::Object(existing_object), Value::Object(incoming_object)) => {
+            if existing["nodeType"] != incoming["nodeType"] {
+                return;
+            }
+            if let (Some(old), Some(new)) = (existing["id"].as_i64(), incoming["id"].as_i64()) {
+                ids.insert(new, old);
+            }
+            for (key, child) in incoming_object {
+                if let Some(existing_child) = existing_object.get(key) {
+                    map_shared_ids(existing_child, child, ids);
+                }
+            }
+        }
+        (Value::Array(existing_children), Value::Array(incoming_children)) => {
+            for (existing_child, child) in existing_children.iter().zip(incoming_children) {
+                map_shared_ids(existing_child, child, ids);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Rewrite the node ids of an AST: shared ids take their mapped value, others are offset
+fn renumber_node_ids(node: &mut Value, shared_ids: &HashMap<i64, i64>, offset: i64) {
+    fn renumber(value: &mut Value, shared_ids: &HashMap<i64, i64>, offset: i64) {
+        match value {
+            Value::Number(number) => {
+                if let Some(id) = number.as_i64().filter(|id| *id >= 0) {
+                    *value = shared_ids.get(&id).copied().unwrap_or(id + offset).into();
+                }
+            }
+            Value::Array(items) => {
+                items.iter_mut().for_each(|item| renumber(item, shared_ids, offset))
+            }
+            Value::Object(map) => {
+                map.values_mut().for_each(|item| renumber(item, shared_ids, offset))
+            }
+            _ => {}
+        }
+    }
+
+    match node {
+        Value::Object(object) => {
+            for (key, child) in object.iter_mut() {
+                if NODE_ID_KEYS.contains(&key.as_str()) {
+                    renumber(child, shared_ids, offset);
+                } else {
+                    renumber_node_ids(child, shared_ids, offset);
+                }
+            }
+        }
+        Value::Array(children) => {
+            children.iter_mut().for_each(|child| renumber_node_ids(child, shared_ids, offset))
+        }
+        _ => {}
+    }
+}
+
 /// Merge two AST JSON objects
 ///
-/// This function combines two AST JSON objects into one, merging arrays and objects.
+/// This function combines two AST JSON objects into one. Entries of the `sources` map are
+/// keyed by file path, so a file compiled in several solc runs (such as a shared import) is
+/// kept once; contracts in `nodes` arrays are deduplicated by name and source file. Other
+/// arrays gain the items they do not contain yet and nested objects are merged recursively.
 ///
 /// # Arguments
 ///
@@ -227,42 +860,55 @@ pub fn get_literal_type(literal: &Value) -> String {
 ///
 /// Result indicating success or failure
 pub fn merge_ast_json(target: &mut Value, source: &Value) -> Result<()> {
-    if let (Value::Object(target_obj), Value::Object(source_obj)) = (target, source) {
-        for (key, value) in source_obj {
-            if !target_obj.contains_key(key) {
+    let (Value::Object(target_obj), Value::Object(source_obj)) = (target, source) else {
+        return Ok(());
+    };
+    let same_file = target_obj.get("absolutePath") == source_obj.get("absolutePath");
+
+    for (key, value) in source_obj {
+        match (target_obj.get_mut(key), value) {
+            (None, _) => {
                 // If the key doesn't exist in target, simply insert the value
                 target_obj.insert(key.clone(), value.clone());
-            } else {
-                match (target_obj.get_mut(key).unwrap(), value) {
-                    (Value::Array(target_arr), Value::Array(source_arr)) => {
-                        // If both are arrays, append source array items to target
-                        target_arr.extend_from_slice(source_arr);
-                    }
-                    (Value::Object(target_inner), Value::Object(source_inner)) => {
-                        // If both are objects, recursively merge
-                        for (inner_key, inner_value) in source_inner {
-                            if !target_inner.contains_key(inner_key) {
-                                target_inner.insert(inner_key.clone(), inner_value.clone());
-                            } else {
-                                let mut temp_value = target_inner.get(inner_key).unwrap().clone();
-                                if let (Value::Array(temp_arr), Value::Array(source_inner_arr)) =
-                                    (&mut temp_value, inner_value)
-                                {
-                                    temp_arr.extend_from_slice(source_inner_arr);
-                                    target_inner.insert(inner_key.clone(), temp_value);
-                                } else {
-                                    // For conflicts, prefer the source value
-                                    target_inner.insert(inner_key.clone(), inner_value.clone());
-                                }
-                            }
-                        }
+            }
+            (Some(Value::Object(target_sources)), Value::Object(source_sources))
+                if key == "sources" =>
+            {
+                // Later output for the same file path replaces the earlier one
+                for (path, source_entry) in source_sources {
+                    target_sources.insert(path.clone(), source_entry.clone());
+                }
+            }
+            (Some(Value::Array(target_nodes)), Value::Array(source_nodes)) if key == "nodes" => {
+                for node in source_nodes {
+                    let is_contract = node["nodeType"].as_str() == Some("ContractDefinition");
+                    let duplicate = is_contract
+                        && same_file
+                        && target_nodes.iter().any(|existing| {
+                            existing["nodeType"].as_str() == Some("ContractDefinition")
+                                && existing["name"] == node["name"]
+                        });
+                    if !duplicate {
+                        target_nodes.push(node.clone());
                     }
-                    (_, _) => {
-                        // For other types, prefer the source value
-                        target_obj.insert(key.clone(), value.clone());
+                }
+            }
+            (Some(Value::Array(target_arr)), Value::Array(source_arr)) => {
+                // Append source items the target does not contain yet, e.g. `sourceList`
+                for item in source_arr {
+                    if !target_arr.contains(item) {
+                        target_arr.push(item.clone());
                     }
                 }
             }
+            (Some(target_inner @ Value::Object(_)), Value::Object(_)) => {
+                // If both are objects, recursively merge
+                merge_ast_json(target_inner, value)?;
+            }
+            (Some(target_value), _) => {
+                // For other types, prefer the source value
+                *target_value = value.clone();
+            }
         }
     }
 
@@ -0,0 +1,4291 @@
+use serde_json::{json, Value};
+use sol2seq::{
+    analyze_ast, generate_class_diagram, generate_sequence_diagram,
+    generate_sequence_diagram_with_config, validate_ast, Config, DiagramType, OutputFormat,
+    ParticipantOrder, Sol2SeqError, Theme,
+};
+
+/// Wrap top-level nodes in a source unit
+fn source_unit(nodes: Vec<Value>) -> Value {
+    json!({ "nodeType": "SourceUnit", "absolutePath": "Test.sol", "nodes": nodes })
+}
+
+/// Build a contract definition of the given kind
+fn contract(name: &str, kind: &str, nodes: Vec<Value>) -> Value {
+    json!({
+        "nodeType": "ContractDefinition",
+        "name": name,
+        "contractKind": kind,
+        "baseContracts": [],
+        "nodes": nodes,
+    })
+}
+
+/// Build a function definition with the given parameters and body statements
+fn function(name: &str, visibility: &str, params: Vec<Value>, statements: Vec<Value>) -> Value {
+    json!({
+        "nodeType": "FunctionDefinition",
+        "name": name,
+        "kind": "function",
+        "visibility": visibility,
+        "stateMutability": "nonpayable",
+        "parameters": { "parameters": params },
+        "returnParameters": { "parameters": [] },
+        "body": { "nodeType": "Block", "statements": statements },
+    })
+}
+
+/// Build a parameter or variable declaration with an elementary type
+fn param(name: &str, type_name: &str) -> Value {
+    json!({
+        "nodeType": "VariableDeclaration",
+        "name": name,
+        "typeName": { "nodeType": "ElementaryTypeName", "name": type_name },
+    })
+}
+
+/// Build a parameter or variable declaration with a user-defined type
+fn typed_param(name: &str, type_name: &str) -> Value {
+    json!({
+        "nodeType": "VariableDeclaration",
+        "name": name,
+        "typeName": { "nodeType": "UserDefinedTypeName", "name": type_name },
+    })
+}
+
+/// Build an identifier, optionally annotated with its compiler type string
+fn identifier(name: &str, type_string: Option<&str>) -> Value {
+    match type_string {
+        Some(ts) => json!({
+            "nodeType": "Identifier",
+            "name": name,
+            "typeDescriptions": { "typeString": ts },
+        }),
+        None => json!({ "nodeType": "Identifier", "name": name }),
+    }
+}
+
+/// Build an expression statement calling `base.member(args)`
+fn member_call(base: Value, member: &str, args: Vec<Value>) -> Value {
+    json!({
+        "nodeType": "ExpressionStatement",
+        "expression": {
+            "nodeType": "FunctionCall",
+            "expression": { "nodeType": "MemberAccess", "memberName": member, "expression": base },
+            "arguments": args,
+        },
+    })
+}
+
+/// Build an expression statement calling a free identifier such as `require(...)`
+fn identifier_call(name: &str, args: Vec<Value>) -> Value {
+    json!({
+        "nodeType": "ExpressionStatement",
+        "expression": {
+            "nodeType": "FunctionCall",
+            "expression": identifier(name, None),
+            "arguments": args,
+        },
+    })
+}
+
+/// Build a `uint256` local declaration initialized from `value`, e.g. `uint m = Math.max(a, b)`
+fn declaration(names: &[&str], value: Value) -> Value {
+    json!({
+        "nodeType": "VariableDeclarationStatement",
+        "declarations": names.iter().map(|name| param(name, "uint256")).collect::<Vec<_>>(),
+        "initialValue": value,
+    })
+}
+
+/// Build an expression statement assigning `rhs` to `lhs` with the given operator
+fn assignment(lhs: Value, operator: &str, rhs: Value) -> Value {
+    json!({
+        "nodeType": "ExpressionStatement",
+        "expression": {
+            "nodeType": "Assignment",
+            "operator": operator,
+            "leftHandSide": lhs,
+            "rightHandSide": rhs,
+        },
+    })
+}
+
+/// Build a type conversion call such as `address(x)` or `IERC20(x)`
+fn conversion(callee: Value, argument: Value) -> Value {
+    json!({
+        "nodeType": "FunctionCall",
+        "kind": "typeConversion",
+        "expression": callee,
+        "arguments": [argument],
+    })
+}
+
+fn render(ast: &Value) -> String {
+    generate_sequence_diagram(ast, false).expect("diagram generation failed")
+}
+
+/// Write a stand-in solc that prints `output` as its combined-json
+///
+/// `script` replaces the default `cat "$out"` body; `$out` is the file holding `output`.
+#[cfg(unix)]
+fn fake_solc(dir: &std::path::Path, output: &Value, script: Option<&str>) -> std::path::PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let output_file = dir.join("solc-output.json");
+    std::fs::write(&output_file, output.to_string()).unwrap();
+    let solc = dir.join("solc");
+    let script = format!(
+        "#!/bin/sh\nout={}\n{}\n",
+        output_file.display(),
+        script.unwrap_or("cat \"$out\"")
+    );
+    std::fs::write(&solc, script).unwrap();
+    std::fs::set_permissions(&solc, std::fs::Permissions::from_mode(0o755)).unwrap();
+    solc
+}
+
+#[test]
+fn test_global_using_for_attributes_member_calls_to_library() {
+    let ast = source_unit(vec![
+        json!({ "nodeType": "UserDefinedValueTypeDefinition", "name": "Fixed" }),
+        contract("FixedMath", "library", vec![function("mul", "internal", vec![], vec![])]),
+        json!({
+            "nodeType": "UsingForDirective",
+            "libraryName": { "nodeType": "IdentifierPath", "name": "FixedMath" },
+            "typeName": { "nodeType": "UserDefinedTypeName", "name": "Fixed" },
+            "global": true,
+        }),
+        contract(
+            "Pool",
+            "contract",
+            vec![function(
+                "scale",
+                "external",
+                vec![typed_param("price", "Fixed")],
+                vec![member_call(
+                    identifier("price", Some("Fixed")),
+                    "mul",
+                    vec![identifier("factor", None)],
+                )],
+            )],
+        ),
+        contract("Idle", "contract", vec![]),
+    ]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Pool->>FixedMath: mul(price, factor: any)"));
+    assert!(!diagram.contains("Pool->>+price"));
+
+    // Only contracts calling a bound function use the library, and never the library itself
+    let data = analyze_ast(&ast, &Config::default()).unwrap();
+    let uses: Vec<(&str, &str)> = data
+        .contract_relationships
+        .iter()
+        .filter(|rel| rel.relation_type == "uses")
+        .map(|rel| (rel.source.as_str(), rel.target.as_str()))
+        .collect();
+    assert_eq!(uses, vec![("Pool", "FixedMath")]);
+}
+
+#[test]
+fn test_file_level_constants_and_using_for() {
+    let ast = source_unit(vec![
+        json!({
+            "nodeType": "VariableDeclaration",
+            "name": "FEE",
+            "constant": true,
+            "typeName": { "nodeType": "ElementaryTypeName", "name": "uint256" },
+            "value": { "nodeType": "Literal", "kind": "number", "value": "3" },
+        }),
+        json!({
+            "nodeType": "UsingForDirective",
+            "libraryName": { "nodeType": "IdentifierPath", "name": "SafeMath" },
+            "typeName": { "nodeType": "ElementaryTypeName", "name": "uint256" },
+        }),
+        contract(
+            "SafeMath",
+            "library",
+            vec![function("add", "internal", vec![], vec![])],
+        ),
+        contract(
+            "Pool",
+            "contract",
+            vec![function(
+                "accrue",
+                "external",
+                vec![typed_param("amount", "uint256")],
+                vec![member_call(
+                    identifier("amount", Some("uint256")),
+                    "add",
+                    vec![identifier("FEE", None)],
+                )],
+            )],
+        ),
+    ]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Note over User,SafeMath: Constants: FEE = 3"));
+    assert!(diagram.contains("Pool->>SafeMath: add(amount, FEE: any)"));
+}
+
+#[test]
+fn test_tuple_destructuring_renders_all_returned_names() {
+    let reserves = member_call(
+        identifier("pair", Some("contract IPair")),
+        "getReserves",
+        vec![],
+    );
+    let declaration = json!({
+        "nodeType": "VariableDeclarationStatement",
+        "declarations": [param("reserve0", "uint112"), null, param("reserve1", "uint112")],
+        "initialValue": reserves["expression"],
+    });
+    let assignment = json!({
+        "nodeType": "ExpressionStatement",
+        "expression": {
+            "nodeType": "Assignment",
+            "operator": "=",
+            "leftHandSide": {
+                "nodeType": "TupleExpression",
+                "components": [identifier("x", None), identifier("y", None)],
+            },
+            "rightHandSide": reserves["expression"],
+        },
+    });
+    let ast = source_unit(vec![contract(
+        "Router",
+        "contract",
+        vec![function(
+            "quote",
+            "external",
+            vec![],
+            vec![declaration, assignment],
+        )],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("IPair-->>-Router: return → (reserve0, _, reserve1)"));
+    assert!(diagram.contains("IPair-->>-Router: return → (x, y)"));
+    assert_eq!(diagram.matches("Router->>+IPair: getReserves()").count(), 2);
+}
+
+#[test]
+fn test_inline_assembly_is_flagged_with_notable_opcodes() {
+    let yul_call = |name: &str, arguments: Vec<Value>| {
+        json!({
+            "nodeType": "YulFunctionCall",
+            "functionName": { "nodeType": "YulIdentifier", "name": name },
+            "arguments": arguments,
+        })
+    };
+    let modern = json!({
+        "nodeType": "InlineAssembly",
+        "AST": {
+            "nodeType": "YulBlock",
+            "statements": [
+                { "nodeType": "YulExpressionStatement", "expression": yul_call("sstore", vec![
+                    yul_call("add", vec![]),
+                    yul_call("delegatecall", vec![yul_call("gas", vec![])]),
+                ]) },
+            ],
+        },
+    });
+    let legacy = json!({
+        "nodeType": "InlineAssembly",
+        "operations": "{ let ok := call(gas(), to, 0, 0, 0, 0, 0) }",
+    });
+    let plain = json!({
+        "nodeType": "InlineAssembly",
+        "operations": "{ let x := add(1, 2) }",
+    });
+    let ast = source_unit(vec![contract(
+        "Proxy",
+        "contract",
+        vec![function(
+            "run",
+            "external",
+            vec![],
+            vec![modern, legacy, plain],
+        )],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Note over Proxy: inline assembly (sstore, delegatecall)"));
+    assert!(diagram.contains("Note over Proxy: inline assembly (call)"));
+    assert!(diagram.contains("Note over Proxy: inline assembly\n"));
+}
+
+#[test]
+fn test_unchecked_block_is_marked_and_keeps_inner_calls() {
+    let unchecked = json!({
+        "nodeType": "UncheckedBlock",
+        "statements": [member_call(identifier("oracle", None), "poke", vec![])],
+    });
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function("sync", "external", vec![], vec![unchecked])],
+    )]);
+
+    let diagram = render(&ast);
+    let marker = diagram
+        .find("Note over Vault: unchecked arithmetic")
+        .unwrap();
+    let call = diagram.find("Vault->>+oracle: poke()").unwrap();
+    assert!(marker < call);
+    assert_balanced_activations(&diagram);
+}
+
+#[test]
+fn test_conditions_describe_calls_member_access_and_negation() {
+    let balance_check = json!({
+        "nodeType": "BinaryOperation",
+        "operator": ">",
+        "leftExpression": member_call(identifier("token", None), "balanceOf", vec![identifier("user", None)])["expression"],
+        "rightExpression": { "nodeType": "Literal", "kind": "number", "value": "0" },
+    });
+    let not_paused = json!({
+        "nodeType": "UnaryOperation",
+        "operator": "!",
+        "prefix": true,
+        "subExpression": {
+            "nodeType": "MemberAccess",
+            "memberName": "paused",
+            "expression": identifier("config", None),
+        },
+    });
+    let statements = vec![
+        json!({
+            "nodeType": "IfStatement",
+            "condition": balance_check,
+            "trueBody": { "nodeType": "Block", "statements": [] },
+        }),
+        json!({
+            "nodeType": "WhileStatement",
+            "condition": not_paused,
+            "body": { "nodeType": "Block", "statements": [
+                member_call(identifier("oracle", None), "poke", vec![]),
+            ] },
+        }),
+    ];
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function("sync", "external", vec![], statements)],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("alt if token.balanceOf(user) > 0"));
+    assert!(diagram.contains("loop while !config.paused"));
+    assert!(diagram.contains("    Vault->>+oracle: poke()"));
+    assert_balanced_activations(&diagram);
+}
+
+#[test]
+fn test_for_loops_render_statically_known_bounds() {
+    let for_loop = |var: &str, operator: &str, bound: Value| {
+        json!({
+            "nodeType": "ForStatement",
+            "initializationExpression": {
+                "nodeType": "VariableDeclarationStatement",
+                "declarations": [param(var, "uint256")],
+                "initialValue": { "nodeType": "Literal", "kind": "number", "value": "0" },
+            },
+            "condition": {
+                "nodeType": "BinaryOperation",
+                "operator": operator,
+                "leftExpression": identifier(var, None),
+                "rightExpression": bound,
+            },
+            "body": { "nodeType": "Block", "statements": [] },
+        })
+    };
+    let length = |name: &str| {
+        let array = identifier(name, None);
+        json!({ "nodeType": "MemberAccess", "memberName": "length", "expression": array })
+    };
+    let statements = vec![
+        for_loop(
+            "i",
+            "<",
+            json!({ "nodeType": "Literal", "kind": "number", "value": "10" }),
+        ),
+        for_loop("j", "<=", identifier("maxRounds", None)),
+        for_loop("k", "<", length("users")),
+        for_loop("n", "<", length("recipients")),
+    ];
+    let ast = source_unit(vec![contract(
+        "Airdrop",
+        "contract",
+        vec![
+            param("users", "address[]"),
+            param("maxRounds", "uint256"),
+            function(
+                "run",
+                "external",
+                vec![param("recipients", "address[]")],
+                statements,
+            ),
+        ],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("loop for each i (0..10)"), "{}", diagram);
+    assert!(diagram.contains("loop for each j (0..=maxRounds)"));
+    assert!(diagram.contains("loop for each k in users"));
+    assert!(diagram.contains("loop For each n: uint256"));
+}
+
+#[test]
+fn test_else_if_chain_stays_in_one_alt_block() {
+    let compare = |operator: &str, value: &str| {
+        json!({
+            "nodeType": "BinaryOperation",
+            "operator": operator,
+            "leftExpression": identifier("amount", None),
+            "rightExpression": { "nodeType": "Literal", "kind": "number", "value": value },
+        })
+    };
+    let block = |statements: Vec<Value>| json!({ "nodeType": "Block", "statements": statements });
+    let chain = json!({
+        "nodeType": "IfStatement",
+        "condition": compare(">", "100"),
+        "trueBody": block(vec![member_call(identifier("large", None), "route", vec![])]),
+        "falseBody": {
+            "nodeType": "IfStatement",
+            "condition": compare(">", "10"),
+            "trueBody": block(vec![member_call(identifier("medium", None), "route", vec![])]),
+            "falseBody": block(vec![member_call(identifier("small", None), "route", vec![])]),
+        },
+    });
+    let ast = source_unit(vec![contract(
+        "Router",
+        "contract",
+        vec![function("swap", "external", vec![], vec![chain])],
+    )]);
+
+    let diagram = render(&ast);
+    let lines: Vec<&str> = diagram.lines().map(str::trim).collect();
+    let alt = lines
+        .iter()
+        .position(|line| *line == "alt if amount > 100")
+        .unwrap();
+    assert_eq!(
+        lines[alt..alt + 10],
+        [
+            "alt if amount > 100",
+            "Router->>+large: route()",
+            "large-->>-Router: return",
+            "else if amount > 10",
+            "Router->>+medium: route()",
+            "medium-->>-Router: return",
+            "else",
+            "Router->>+small: route()",
+            "small-->>-Router: return",
+            "end",
+        ]
+    );
+    assert_eq!(
+        lines.iter().filter(|line| line.starts_with("alt ")).count(),
+        1
+    );
+    assert_balanced_activations(&diagram);
+}
+
+#[test]
+fn test_only_public_api_lists_full_signatures() {
+    let mut deposit = function(
+        "deposit",
+        "external",
+        vec![param("amount", "uint256")],
+        vec![],
+    );
+    deposit["stateMutability"] = json!("payable");
+
+    let mut get_balance = function(
+        "getBalance",
+        "external",
+        vec![param("", "address")],
+        vec![],
+    );
+    get_balance["stateMutability"] = json!("view");
+    get_balance["returnParameters"] = json!({ "parameters": [param("", "uint256")] });
+
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![deposit, get_balance, function("_sweep", "internal", vec![], vec![])],
+    )]);
+
+    let config = Config { only_public_api: true, ..Default::default() };
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+
+    assert!(diagram.contains("Note over Vault: deposit(uint256 amount) external payable"));
+    assert!(diagram.contains("Note over Vault: getBalance(address) external view returns (uint256)"));
+    assert!(!diagram.contains("_sweep"));
+    assert!(!diagram.contains("User->>"));
+}
+
+#[test]
+fn test_nested_type_conversion_targets_are_resolved() {
+    let address = json!({ "nodeType": "ElementaryTypeNameExpression", "typeName": "address" });
+    let payable = json!({ "nodeType": "ElementaryTypeNameExpression", "typeName": "payable" });
+    let vault = identifier("vault", Some("contract Vault"));
+    let treasury = json!({ "nodeType": "Literal", "kind": "number", "value": "0x1234" });
+
+    let ast = source_unit(vec![
+        contract("Vault", "contract", vec![]),
+        contract(
+            "Router",
+            "contract",
+            vec![function(
+                "sweep",
+                "external",
+                vec![],
+                vec![
+                    member_call(
+                        conversion(
+                            identifier("IERC20", None),
+                            conversion(address.clone(), vault.clone()),
+                        ),
+                        "transfer",
+                        vec![identifier("amount", None)],
+                    ),
+                    member_call(
+                        conversion(payable.clone(), conversion(address.clone(), vault)),
+                        "transfer",
+                        vec![identifier("amount", None)],
+                    ),
+                    member_call(
+                        conversion(payable, conversion(address.clone(), treasury)),
+                        "transfer",
+                        vec![identifier("amount", None)],
+                    ),
+                    // `uint b = IERC20(address(v)).balanceOf(a);`
+                    declaration(
+                        &["b"],
+                        member_call(
+                            conversion(
+                                identifier("IERC20", None),
+                                conversion(address, identifier("v", Some("contract Vault"))),
+                            ),
+                            "balanceOf",
+                            vec![identifier("a", None)],
+                        )["expression"]
+                            .clone(),
+                    ),
+                ],
+            )],
+        ),
+    ]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Router->>+IERC20: transfer(amount: uint256)"));
+    assert!(diagram.contains("IERC20-->>-Router: return"));
+    assert!(diagram.contains("Router->>+IERC20: balanceOf(a: any)\nIERC20-->>-Router: return → b"));
+    assert!(diagram.contains("participant IERC20"));
+    assert!(diagram.contains("Router->>+Vault: ETH transfer(amount: uint256)"));
+    assert!(diagram.contains("Router->>+Recipient: ETH transfer(amount: uint256)"));
+    assert!(!diagram.contains("0x1234"));
+}
+
+#[test]
+fn test_call_matrix_counts_call_sites() {
+    let ast = source_unit(vec![contract(
+        "Router",
+        "contract",
+        vec![
+            function(
+                "deposit",
+                "external",
+                vec![],
+                vec![
+                    member_call(identifier("vault", None), "deposit", vec![]),
+                    member_call(identifier("oracle", None), "price", vec![]),
+                ],
+            ),
+            function(
+                "redeposit",
+                "external",
+                vec![],
+                vec![member_call(identifier("vault", None), "deposit", vec![])],
+            ),
+        ],
+    )]);
+
+    let config = Config { emit_call_matrix: true, ..Default::default() };
+    let output = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    let matrix: Vec<&str> = output.lines().skip_while(|line| *line != "## Call Matrix").collect();
+
+    assert_eq!(matrix[2], "| Caller \\ Callee | oracle | vault |");
+    assert_eq!(matrix.len(), 5);
+    assert_eq!(matrix[4], "| Router | 1 | 2 |");
+
+    // Repeated call sites still yield a single `calls` relationship per target
+    let data = analyze_ast(&ast, &Config::default()).unwrap();
+    let calls: Vec<(&str, &str)> = data
+        .contract_relationships
+        .iter()
+        .filter(|rel| rel.relation_type == "calls")
+        .map(|rel| (rel.source.as_str(), rel.target.as_str()))
+        .collect();
+    assert_eq!(calls, vec![("Router", "vault"), ("Router", "oracle")]);
+}
+
+#[test]
+fn test_bare_revert_and_legacy_throw_are_rendered() {
+    let bare_revert = identifier_call("revert", vec![]);
+    let ast = source_unit(vec![contract(
+        "Guarded",
+        "contract",
+        vec![
+            function("fail", "external", vec![], vec![bare_revert]),
+            function("legacyFail", "public", vec![], vec![json!({ "nodeType": "Throw" })]),
+        ],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Note over Guarded: revert"));
+    assert!(diagram.contains("Note over Guarded: throw"));
+}
+
+#[test]
+fn test_structurizr_output_lists_components_and_relationships() {
+    let mut token = contract("Token", "contract", vec![]);
+    token["baseContracts"] = json!([{ "baseName": { "name": "ERC20" } }]);
+    // Names that would clash with the workspace's own ids or with each other once sanitized
+    let ast = source_unit(vec![
+        contract("ERC20", "abstract", vec![]),
+        token,
+        contract("system", "contract", vec![]),
+        contract("A$B", "contract", vec![]),
+        contract("A_B", "contract", vec![]),
+    ]);
+
+    let config = Config { output_format: OutputFormat::Structurizr, ..Default::default() };
+    let dsl = generate_sequence_diagram_with_config(&ast, config).unwrap();
+
+    assert!(dsl.starts_with("workspace \"Smart Contracts\" {"));
+    assert!(dsl.contains("c_Token = component \"Token\" \"Test.sol\" \"contract\""));
+    assert!(dsl.contains("c_Token -> c_ERC20 \"inherits\""));
+    assert!(dsl.contains("c_system = component \"system\""));
+    assert!(dsl.contains("c_A_B = component \"A$B\""));
+    assert!(dsl.contains("c_A_B_2 = component \"A_B\""));
+    assert!(!dsl.contains("sequenceDiagram"));
+}
+
+#[test]
+fn test_synthetic_participants_can_be_renamed() {
+    let payable = json!({ "nodeType": "ElementaryTypeNameExpression", "typeName": "payable" });
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function(
+            "withdraw",
+            "external",
+            vec![],
+            vec![
+                member_call(identifier("token", None), "transferFrom", vec![]),
+                member_call(
+                    conversion(payable, identifier("to", None)),
+                    "send",
+                    vec![identifier("amount", None)],
+                ),
+            ],
+        )],
+    )]);
+
+    let config = Config {
+        token_participant: "Erc20".to_string(),
+        token_participant_label: "External Token".to_string(),
+        recipient_participant: "Payee".to_string(),
+        recipient_participant_label: "Payment Receiver".to_string(),
+        ..Default::default()
+    };
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+
+    assert!(diagram.contains("participant Erc20 as \"External Token\""));
+    assert!(diagram.contains("participant Payee as \"Payment Receiver\""));
+    assert!(diagram.contains("Vault->>+Erc20: transferFrom()"));
+    assert!(diagram.contains("Vault->>+Payee: ETH send(amount: uint256)"));
+    assert!(!diagram.contains("TokenContract"));
+    assert!(!diagram.contains("Recipient"));
+}
+
+#[test]
+fn test_msg_sender_gets_a_caller_lifeline_when_enabled() {
+    let payable = json!({ "nodeType": "ElementaryTypeNameExpression", "typeName": "payable" });
+    let msg_sender = json!({
+        "nodeType": "MemberAccess",
+        "memberName": "sender",
+        "expression": identifier("msg", None),
+    });
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function(
+            "withdraw",
+            "external",
+            vec![],
+            vec![
+                member_call(
+                    conversion(payable.clone(), msg_sender.clone()),
+                    "transfer",
+                    vec![identifier("amount", None)],
+                ),
+                member_call(
+                    conversion(payable, identifier("to", None)),
+                    "transfer",
+                    vec![identifier("amount", None)],
+                ),
+                member_call(msg_sender, "call", vec![]),
+            ],
+        )],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(!diagram.contains("Caller"));
+
+    let config = Config::builder().show_msg_sender(true).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.contains("participant Caller as \"Caller (msg.sender)\""));
+    assert!(diagram.contains("Vault->>+Caller: ETH transfer(amount: uint256)"));
+    assert!(diagram.contains("Vault->>+Recipient: ETH transfer(amount: uint256)"));
+    assert!(diagram.contains("Vault->>+Caller: call()"));
+}
+
+#[test]
+fn test_show_selectors_appends_function_selectors() {
+    let transfer = function(
+        "transfer",
+        "external",
+        vec![param("to", "address"), param("amount", "uint")],
+        vec![],
+    );
+    let mut approve = function("approve", "external", vec![], vec![]);
+    approve["functionSelector"] = json!("095ea7b3");
+    // Struct parameters are tuples in the ABI, so no selector is guessed from the name
+    let mut order = typed_param("order", "Order");
+    order["typeDescriptions"] = json!({ "typeString": "struct Token.Order memory" });
+    let fill = function("fill", "external", vec![order], vec![]);
+    let ast = source_unit(vec![contract("Token", "contract", vec![transfer, approve, fill])]);
+
+    let diagram = render(&ast);
+    assert!(!diagram.contains("[0x"));
+
+    let config = Config::builder().show_selectors(true).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.contains("User->>+Token: transfer(to: address, amount: uint) [0xa9059cbb]"));
+    assert!(diagram.contains("User->>+Token: approve() [0x095ea7b3]"));
+    assert!(diagram.contains("User->>+Token: fill(order: Order)\n"), "{}", diagram);
+}
+
+#[test]
+fn test_compact_view_returns_fold_into_call_arrow() {
+    let mut balance_of = function(
+        "balanceOf",
+        "external",
+        vec![param("owner", "address")],
+        vec![],
+    );
+    balance_of["stateMutability"] = json!("view");
+    balance_of["returnParameters"] = json!({ "parameters": [param("", "uint256")] });
+    let ast = source_unit(vec![contract(
+        "Token",
+        "contract",
+        vec![balance_of, function("mint", "external", vec![], vec![])],
+    )]);
+
+    let config = Config::builder().compact_view_returns(true).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+
+    assert!(diagram.contains("User->>Token: balanceOf(owner: address): returns uint256"));
+    assert!(!diagram.contains("User->>+Token: balanceOf"));
+    assert!(!diagram.contains("Token-->>-User: return uint256"));
+    assert!(diagram.contains("User->>+Token: mint()"));
+    assert_balanced_activations(&diagram);
+}
+
+#[test]
+fn test_legend_events_and_relationships_sections_can_be_hidden() {
+    let event = json!({
+        "nodeType": "EventDefinition",
+        "name": "Synced",
+        "parameters": { "parameters": [] },
+    });
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![event, function("sync", "external", vec![], vec![])],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Diagram Legend"));
+    assert!(diagram.contains("Event Definitions"));
+    assert!(diagram.contains("Contract Relationships"));
+
+    let config = Config::builder()
+        .show_legend(false)
+        .show_events_section(false)
+        .show_relationships_section(false)
+        .build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(!diagram.contains("Diagram Legend"));
+    assert!(!diagram.contains("Event Definitions"));
+    assert!(!diagram.contains("Contract Relationships"));
+    assert!(diagram.contains("User->>+Vault: sync()"));
+
+    let config = Config::builder()
+        .show_events_section(false)
+        .output_format(OutputFormat::PlantUml)
+        .build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.starts_with("@startuml"));
+    assert!(!diagram.contains("Event Definitions"));
+}
+
+#[test]
+fn test_custom_title_is_sanitized_for_the_mermaid_header() {
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function("sync", "external", vec![], vec![])],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("\ntitle Smart Contract Interaction Sequence Diagram\n"));
+
+    let config = Config::builder()
+        .title("Vault: deposit; #1 flow\nv2")
+        .build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.contains("\ntitle Vault: deposit, No.1 flow v2\n"));
+}
+
+#[test]
+fn test_unused_default_participants_are_dropped() {
+    let ast = source_unit(vec![contract(
+        "Math",
+        "library",
+        vec![function("mulDiv", "internal", vec![], vec![])],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("participant Math"));
+    assert!(!diagram.contains("participant User"));
+    assert!(!diagram.contains("participant Events"));
+    assert!(!diagram.contains("TokenContract"));
+    assert!(diagram.contains("Note over Math: User Interactions"));
+    assert!(!diagram.contains("Note over User"));
+
+    let event = json!({
+        "nodeType": "EventDefinition",
+        "name": "Synced",
+        "parameters": { "parameters": [] },
+    });
+    let emit = json!({
+        "nodeType": "EmitStatement",
+        "eventCall": {
+            "nodeType": "FunctionCall",
+            "expression": { "nodeType": "Identifier", "name": "Synced" },
+            "arguments": [],
+        },
+    });
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![event, function("sync", "external", vec![], vec![emit])],
+    )]);
+
+    let data = analyze_ast(&ast, &Config::default()).unwrap();
+    assert!(data.participants.contains("User"));
+    assert!(data.participants.contains("Events"));
+    assert!(!data.participants.contains("TokenContract"));
+}
+
+#[test]
+fn test_safe_transfer_from_renders_receiver_callback() {
+    let ast = source_unit(vec![contract(
+        "Marketplace",
+        "contract",
+        vec![function(
+            "buy",
+            "external",
+            vec![],
+            vec![
+                member_call(
+                    identifier("nftToken", None),
+                    "safeTransferFrom",
+                    vec![
+                        identifier("seller", None),
+                        identifier("buyer", None),
+                        identifier("tokenId", None),
+                    ],
+                ),
+                member_call(
+                    identifier("itemToken", None),
+                    "safeTransferFrom",
+                    vec![
+                        identifier("seller", None),
+                        conversion(
+                            json!({ "nodeType": "ElementaryTypeNameExpression" }),
+                            identifier("this", None),
+                        ),
+                        identifier("id", None),
+                        identifier("amount", None),
+                        identifier("data", None),
+                    ],
+                ),
+            ],
+        )],
+    )]);
+
+    let diagram = render(&ast);
+    let lines: Vec<&str> = diagram.lines().collect();
+    let call = lines
+        .iter()
+        .position(|l| l.starts_with("Marketplace->>+TokenContract: safeTransferFrom("))
+        .expect("missing transfer arrow");
+
+    // The callback goes to the `to` argument
+    assert_eq!(
+        lines[call + 1],
+        "TokenContract->>+buyer: onERC721Received(operator, from, tokenId, data)"
+    );
+    assert_eq!(
+        lines[call + 2],
+        "buyer-->>-TokenContract: return (selector)"
+    );
+    assert_eq!(
+        lines[call + 3],
+        "TokenContract-->>-Marketplace: return (success)"
+    );
+
+    // The five-argument form is ERC1155's, whatever the token variable is called
+    assert_eq!(
+        lines[call + 5],
+        "TokenContract->>+Marketplace: onERC1155Received(operator, from, id, value, data)"
+    );
+    assert_eq!(
+        lines[call + 6],
+        "Marketplace-->>-TokenContract: return (selector)"
+    );
+}
+
+#[test]
+fn test_actor_map_routes_entrypoints_from_mapped_actors() {
+    let ast = source_unit(vec![contract(
+        "Strategy",
+        "contract",
+        vec![
+            function("harvest", "external", vec![], vec![]),
+            function("setFee", "external", vec![], vec![]),
+            function("deposit", "external", vec![], vec![]),
+        ],
+    )]);
+
+    let mut config = Config::default();
+    config.actor_map.insert("harvest".to_string(), "Keeper".to_string());
+    config.actor_map.insert("set*".to_string(), "Governance".to_string());
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+
+    assert!(diagram.contains("Keeper->>+Strategy: harvest()"));
+    assert!(diagram.contains("Strategy-->>-Keeper: return"));
+    assert!(diagram.contains("Governance->>+Strategy: setFee()"));
+    assert!(diagram.contains("User->>+Strategy: deposit()"));
+    assert!(diagram.contains("participant Keeper"));
+}
+
+#[test]
+fn test_static_library_calls_are_distinguished_from_instance_calls() {
+    let ast = source_unit(vec![
+        contract("Math", "library", vec![function("max", "internal", vec![], vec![])]),
+        contract(
+            "Pool",
+            "contract",
+            vec![function(
+                "settle",
+                "external",
+                vec![],
+                vec![
+                    member_call(
+                        identifier("Math", Some("type(library Math)")),
+                        "max",
+                        vec![identifier("a", None), identifier("b", None)],
+                    ),
+                    member_call(
+                        identifier("token", Some("contract IERC20")),
+                        "transfer",
+                        vec![identifier("to", None)],
+                    ),
+                    // `uint m = Math.max(a, b);` and `Base.hook()` with a result
+                    declaration(
+                        &["m"],
+                        member_call(
+                            identifier("Math", Some("type(library Math)")),
+                            "max",
+                            vec![identifier("a", None), identifier("b", None)],
+                        )["expression"]
+                            .clone(),
+                    ),
+                    declaration(
+                        &["h"],
+                        member_call(
+                            identifier("Base", Some("type(contract Base)")),
+                            "hook",
+                            vec![],
+                        )["expression"]
+                            .clone(),
+                    ),
+                ],
+            )],
+        ),
+    ]);
+
+    let diagram = render(&ast);
+    let lines: Vec<&str> = diagram.lines().collect();
+    let declared_library_call = lines
+        .iter()
+        .position(|l| *l == "Pool->>Math: max(a: any, b: any) → m")
+        .unwrap();
+    assert_eq!(
+        lines[declared_library_call - 1],
+        "Note right of Pool: library call (no separate context)"
+    );
+    let declared_static_call = lines
+        .iter()
+        .position(|l| *l == "Pool->>+Base: hook()")
+        .unwrap();
+    assert_eq!(
+        lines[declared_static_call - 1],
+        "Note right of Pool: static call"
+    );
+    assert_eq!(lines[declared_static_call + 1], "Base-->>-Pool: return → h");
+
+    let library_call = lines
+        .iter()
+        .position(|l| l.starts_with("Pool->>Math: max("))
+        .unwrap();
+    let instance_call = lines
+        .iter()
+        .position(|l| l.starts_with("Pool->>+IERC20: transfer("))
+        .unwrap();
+
+    assert_eq!(
+        lines[library_call - 1],
+        "Note right of Pool: library call (no separate context)"
+    );
+    assert!(!lines[instance_call - 1].contains("library call"));
+    assert!(!diagram.contains("Math-->>"));
+    assert!(diagram.contains("participant Math as \"Math (library)"));
+}
+
+#[test]
+fn test_library_results_assigned_to_locals_keep_the_library_arrow() {
+    // `uint c = SafeMath.add(a, b);`
+    let ast = source_unit(vec![
+        contract(
+            "SafeMath",
+            "library",
+            vec![function("add", "internal", vec![], vec![])],
+        ),
+        contract(
+            "Pool",
+            "contract",
+            vec![function(
+                "deposit",
+                "external",
+                vec![],
+                vec![declaration(
+                    &["c"],
+                    member_call(
+                        identifier("SafeMath", Some("type(library SafeMath)")),
+                        "add",
+                        vec![identifier("a", None), identifier("b", None)],
+                    )["expression"]
+                        .clone(),
+                )],
+            )],
+        ),
+    ]);
+
+    let diagram = render(&ast);
+    let lines: Vec<&str> = diagram.lines().collect();
+    let call = lines
+        .iter()
+        .position(|l| *l == "Pool->>SafeMath: add(a: any, b: any) → c")
+        .unwrap();
+    assert_eq!(
+        lines[call - 1],
+        "Note right of Pool: library call (no separate context)"
+    );
+    assert!(!diagram.contains("Pool->>+SafeMath"));
+    assert!(!diagram.contains("SafeMath-->>"));
+}
+
+#[test]
+fn test_guards_are_rendered_as_notes() {
+    let condition = json!({
+        "nodeType": "BinaryOperation",
+        "operator": "==",
+        "leftExpression": { "nodeType": "MemberAccess", "memberName": "sender", "expression": identifier("msg", None) },
+        "rightExpression": identifier("owner", None),
+    });
+    let reason = json!({ "nodeType": "Literal", "kind": "string", "value": "not owner" });
+    let custom_error = json!({
+        "nodeType": "RevertStatement",
+        "errorCall": {
+            "nodeType": "FunctionCall",
+            "expression": identifier("InsufficientBalance", None),
+            "arguments": [identifier("amount", None)],
+        },
+    });
+
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function(
+            "withdraw",
+            "external",
+            vec![],
+            vec![
+                identifier_call("require", vec![condition, reason]),
+                identifier_call("assert", vec![identifier("ok", None)]),
+                custom_error,
+            ],
+        )],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Note over Vault: require(msg.sender == owner, \"not owner\")"));
+    assert!(diagram.contains("Note over Vault: assert(ok)"));
+    assert!(diagram.contains("Note over Vault: revert InsufficientBalance(amount)"));
+}
+
+#[test]
+fn test_try_catch_renders_alt_with_catch_branches() {
+    let try_statement = json!({
+        "nodeType": "TryStatement",
+        "externalCall": {
+            "nodeType": "FunctionCall",
+            "expression": {
+                "nodeType": "MemberAccess",
+                "memberName": "getPrice",
+                "expression": identifier("oracle", None),
+            },
+            "arguments": [],
+        },
+        "clauses": [
+            {
+                "nodeType": "TryCatchClause",
+                "errorName": "",
+                "block": { "statements": [member_call(identifier("vault", None), "rebalance", vec![])] },
+            },
+            {
+                "nodeType": "TryCatchClause",
+                "errorName": "Error",
+                "parameters": { "parameters": [param("reason", "string")] },
+                "block": { "statements": [identifier_call("revert", vec![])] },
+            },
+            {
+                "nodeType": "TryCatchClause",
+                "errorName": "",
+                "parameters": { "parameters": [param("lowLevelData", "bytes")] },
+                "block": { "statements": [] },
+            },
+        ],
+    });
+    let ast = source_unit(vec![contract(
+        "Keeper",
+        "contract",
+        vec![function("poke", "external", vec![], vec![try_statement])],
+    )]);
+
+    let diagram = render(&ast);
+    let expected = [
+        "alt try",
+        "    Keeper->>+oracle: getPrice()",
+        "    oracle-->>-Keeper: return",
+        "    Keeper->>+vault: rebalance()",
+        "    vault-->>-Keeper: return",
+        "else catch Error(string reason)",
+        "    Note over Keeper: revert",
+        "else catch (bytes lowLevelData)",
+        "end",
+    ]
+    .join("\n");
+    assert!(diagram.contains(&expected), "{}", diagram);
+}
+
+#[test]
+fn test_ternary_initial_values_note_branches_and_render_calls() {
+    let internal = json!({
+        "nodeType": "VariableDeclarationStatement",
+        "declarations": [param("x", "uint256")],
+        "initialValue": {
+            "nodeType": "Conditional",
+            "condition": identifier("flag", Some("bool")),
+            "trueExpression": identifier_call("a", vec![])["expression"],
+            "falseExpression": identifier_call("b", vec![])["expression"],
+        },
+    });
+    let external = json!({
+        "nodeType": "VariableDeclarationStatement",
+        "declarations": [param("y", "uint256")],
+        "initialValue": {
+            "nodeType": "Conditional",
+            "condition": identifier("flag", Some("bool")),
+            "trueExpression": member_call(
+                identifier("oracle", Some("contract IOracle")),
+                "price",
+                vec![]
+            )["expression"],
+            "falseExpression": { "nodeType": "Literal", "kind": "number", "value": "0" },
+        },
+    });
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![
+            function("a", "internal", vec![], vec![]),
+            function("b", "internal", vec![], vec![]),
+            function("run", "external", vec![], vec![internal, external]),
+        ],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(
+        diagram.contains("Note over Vault: x = flag ? a() : b()"),
+        "{}",
+        diagram
+    );
+    assert!(diagram.contains("Vault->>Vault: a()"));
+    assert!(diagram.contains("Vault->>Vault: b()"));
+    assert!(diagram.contains("Note over Vault: y = flag ? oracle.price() : 0"));
+    assert!(diagram.contains("Vault->>+IOracle: price()"));
+    assert!(diagram.contains("IOracle-->>-Vault: return → y (conditional)"));
+}
+
+#[test]
+fn test_returned_ternaries_note_branches_and_render_calls() {
+    // `return flag ? a() : oracle.price();`
+    let returned = json!({
+        "nodeType": "Return",
+        "expression": {
+            "nodeType": "Conditional",
+            "condition": identifier("flag", Some("bool")),
+            "trueExpression": identifier_call("a", vec![])["expression"],
+            "falseExpression": member_call(
+                identifier("oracle", Some("contract IOracle")),
+                "price",
+                vec![]
+            )["expression"],
+        },
+    });
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![
+            function("a", "internal", vec![], vec![]),
+            function("run", "external", vec![], vec![returned]),
+        ],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(
+        diagram.contains("Note over Vault: return flag ? a() : oracle.price()"),
+        "{}",
+        diagram
+    );
+    assert!(diagram.contains("Vault->>Vault: a()"));
+    assert!(diagram.contains("Vault->>+IOracle: price()"));
+    assert!(diagram.contains("IOracle-->>-Vault: return → result (conditional)"));
+}
+
+#[test]
+fn test_new_contract_deployments_render_deploy_arrows() {
+    let new_pair = json!({
+        "nodeType": "VariableDeclarationStatement",
+        "declarations": [typed_param("pair", "Pair")],
+        "initialValue": {
+            "nodeType": "FunctionCall",
+            "expression": {
+                "nodeType": "FunctionCallOptions",
+                "names": ["salt"],
+                "options": [identifier("salt", None)],
+                "expression": {
+                    "nodeType": "NewExpression",
+                    "typeName": { "nodeType": "UserDefinedTypeName", "name": "Pair" },
+                },
+            },
+            "arguments": [identifier("tokenA", None)],
+        },
+    });
+    let ast = source_unit(vec![contract(
+        "Factory",
+        "contract",
+        vec![function("createPair", "external", vec![], vec![new_pair])],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Factory->>+Pair: deploy{salt: salt}(tokenA: any)"));
+    assert!(diagram.contains("Pair-->>-Factory: return (address)"));
+    assert!(diagram.contains("participant Pair"));
+}
+
+#[test]
+fn test_low_level_calls_render_options_and_delegatecall_note() {
+    let value_call = json!({
+        "nodeType": "VariableDeclarationStatement",
+        "declarations": [param("ok", "bool"), null],
+        "initialValue": {
+            "nodeType": "FunctionCall",
+            "expression": {
+                "nodeType": "FunctionCallOptions",
+                "names": ["value"],
+                "options": [identifier("amount", None)],
+                "expression": {
+                    "nodeType": "MemberAccess",
+                    "memberName": "call",
+                    "expression": identifier("recipient", Some("address payable")),
+                },
+            },
+            "arguments": [{ "nodeType": "Literal", "kind": "string", "value": "" }],
+        },
+    });
+    let ast = source_unit(vec![contract(
+        "Proxy",
+        "contract",
+        vec![function(
+            "forward",
+            "external",
+            vec![],
+            vec![
+                value_call,
+                member_call(identifier("implementation", None), "delegatecall", vec![identifier("data", None)]),
+            ],
+        )],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Proxy->>+recipient: call{value: amount}(\"\")"));
+    assert!(diagram.contains("recipient-->>-Proxy: return (success, data)"));
+    assert!(diagram.contains("Note over Proxy: delegatecall (runs in Proxy's context)"));
+    assert!(diagram.contains("Proxy->>+implementation: delegatecall(data)"));
+    assert_eq!(diagram.matches("->>+implementation").count(), 1);
+}
+
+#[test]
+fn test_internal_calls_are_inlined() {
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![
+            function(
+                "deposit",
+                "external",
+                vec![],
+                vec![identifier_call("_pull", vec![identifier("amount", None)])],
+            ),
+            function(
+                "_pull",
+                "internal",
+                vec![param("amount", "uint256")],
+                vec![member_call(identifier("asset", None), "pull", vec![])],
+            ),
+        ],
+    )]);
+
+    let diagram = render(&ast);
+    let expected = [
+        "Vault->>Vault: _pull(amount: uint256)",
+        "Vault->>+asset: pull()",
+        "asset-->>-Vault: return",
+    ]
+    .join("\n");
+    assert!(diagram.contains(&expected), "{}", diagram);
+}
+
+#[test]
+fn test_internal_calls_resolve_overloads_and_inherited_helpers() {
+    let mut move_amount = function(
+        "_move",
+        "internal",
+        vec![param("amount", "uint256")],
+        vec![member_call(identifier("asset", None), "pull", vec![])],
+    );
+    move_amount["id"] = json!(10);
+    let mut move_to = function(
+        "_move",
+        "internal",
+        vec![param("to", "address")],
+        vec![member_call(identifier("asset", None), "push", vec![])],
+    );
+    move_to["id"] = json!(11);
+    let credit = function(
+        "_credit",
+        "internal",
+        vec![],
+        vec![member_call(identifier("ledger", None), "credit", vec![])],
+    );
+
+    let mut call_move_to = identifier_call("_move", vec![identifier("to", None)]);
+    call_move_to["expression"]["expression"]["referencedDeclaration"] = json!(11);
+    let mut vault = contract(
+        "Vault",
+        "contract",
+        vec![function(
+            "deposit",
+            "external",
+            vec![],
+            vec![call_move_to, identifier_call("_credit", vec![])],
+        )],
+    );
+    vault["baseContracts"] = json!([{ "baseName": { "name": "Base" } }]);
+    let ast = source_unit(vec![
+        contract("Base", "abstract", vec![move_amount, move_to, credit]),
+        vault,
+    ]);
+
+    let diagram = render(&ast);
+    let expected = [
+        "Vault->>Vault: _move(to: any)",
+        "Vault->>+asset: push()",
+        "asset-->>-Vault: return",
+        "Vault->>Vault: _credit()",
+        "Vault->>+ledger: credit()",
+    ]
+    .join("\n");
+    assert!(diagram.contains(&expected), "{}", diagram);
+    assert!(!diagram.contains("pull()"));
+}
+
+#[test]
+fn test_nested_cross_contract_calls_return_in_lifo_order() {
+    let ast = source_unit(vec![
+        contract(
+            "Router",
+            "contract",
+            vec![function(
+                "route",
+                "external",
+                vec![],
+                vec![member_call(
+                    identifier("a", Some("contract A")),
+                    "foo",
+                    vec![],
+                )],
+            )],
+        ),
+        contract(
+            "A",
+            "contract",
+            vec![function(
+                "foo",
+                "external",
+                vec![],
+                vec![member_call(
+                    identifier("b", Some("contract B")),
+                    "bar",
+                    vec![],
+                )],
+            )],
+        ),
+        contract(
+            "B",
+            "contract",
+            vec![function("bar", "external", vec![], vec![])],
+        ),
+    ]);
+
+    let diagram = render(&ast);
+    let expected = [
+        "Router->>+A: foo()",
+        "A->>+B: bar()",
+        "B-->>-A: return",
+        "A-->>-Router: return",
+    ]
+    .join("\n");
+    assert!(diagram.contains(&expected), "{}", diagram);
+    assert_balanced_activations(&diagram);
+}
+
+#[test]
+fn test_recursive_internal_calls_are_cut_off() {
+    let ast = source_unit(vec![contract(
+        "Walker",
+        "contract",
+        vec![
+            function(
+                "walk",
+                "public",
+                vec![],
+                vec![identifier_call("_step", vec![])],
+            ),
+            function(
+                "_step",
+                "internal",
+                vec![],
+                vec![identifier_call("_step", vec![])],
+            ),
+        ],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(
+        diagram.contains("Walker->>Walker: _step()\nNote over Walker: _step (recursion)"),
+        "{}",
+        diagram
+    );
+    assert_eq!(diagram.matches("Walker->>Walker: _step()").count(), 2);
+    assert!(diagram.trim_end().ends_with("```"));
+
+    // Definitions carrying solc ids are tracked by id
+    let mut with_ids = ast.clone();
+    with_ids["nodes"][0]["nodes"][0]["id"] = json!(1);
+    with_ids["nodes"][0]["nodes"][1]["id"] = json!(2);
+    assert_eq!(render(&with_ids), diagram);
+
+    let config = Config {
+        max_body_depth: 1,
+        ..Default::default()
+    };
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.contains("Note over Walker: _step (max depth reached)"));
+}
+
+#[test]
+fn test_modifiers_are_rendered_before_the_call() {
+    let mut pause = function("pause", "external", vec![], vec![]);
+    pause["modifiers"] = json!([
+        { "nodeType": "ModifierInvocation", "modifierName": { "name": "onlyOwner" } },
+        { "nodeType": "ModifierInvocation", "modifierName": { "name": "checked" } },
+    ]);
+    let ast = source_unit(vec![contract("Vault", "contract", vec![pause])]);
+
+    let diagram = render(&ast);
+    let expected = [
+        "Note over User,Vault: requires onlyOwner (caller must be the owner)",
+        "Note over User,Vault: requires checked",
+        "User->>+Vault: pause()",
+    ]
+    .join("\n");
+    assert!(diagram.contains(&expected), "{}", diagram);
+}
+
+#[test]
+fn test_user_defined_parameter_types_use_short_names() {
+    let mut status = typed_param("status", "Vault.Status");
+    status["typeName"]["typeDescriptions"] = json!({ "typeString": "enum Vault.Status" });
+    let mut order = typed_param("order", "Vault.Order");
+    order["typeName"]["typeDescriptions"] = json!({ "typeString": "struct Vault.Order" });
+    let mut token = typed_param("token", "IERC20");
+    token["typeName"]["typeDescriptions"] = json!({ "typeString": "contract IERC20" });
+
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function(
+            "settle",
+            "external",
+            vec![status, order, token],
+            vec![],
+        )],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(
+        diagram.contains("User->>+Vault: settle(status: Status, order: Order, token: IERC20)"),
+        "{}",
+        diagram
+    );
+}
+
+#[test]
+fn test_user_defined_value_type_parameters_use_alias_name() {
+    let price = json!({
+        "nodeType": "VariableDeclaration",
+        "name": "price",
+        "typeName": {
+            "nodeType": "UserDefinedTypeName",
+            "pathNode": { "nodeType": "IdentifierPath", "name": "Pricing.USD" },
+            "typeDescriptions": { "typeString": "Pricing.USD" },
+        },
+    });
+    let ast = source_unit(vec![
+        json!({ "nodeType": "UserDefinedValueTypeDefinition", "name": "USD" }),
+        contract(
+            "Market",
+            "contract",
+            vec![function("quote", "external", vec![price], vec![])],
+        ),
+    ]);
+
+    let diagram = render(&ast);
+    assert!(
+        diagram.contains("User->>+Market: quote(price: USD)"),
+        "{}",
+        diagram
+    );
+}
+
+#[test]
+fn test_plantuml_output_translates_arrows_and_blocks() {
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function(
+            "sync",
+            "external",
+            vec![],
+            vec![json!({
+                "nodeType": "ForStatement",
+                "body": { "nodeType": "Block", "statements": [
+                    member_call(identifier("oracle", None), "poke", vec![]),
+                ] },
+            })],
+        )],
+    )]);
+
+    let config = Config {
+        output_format: OutputFormat::PlantUml,
+        ..Default::default()
+    };
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+
+    assert!(diagram.starts_with("@startuml"));
+    assert!(diagram.ends_with("@enduml"));
+    assert!(diagram.contains("participant \"External User\" as User"));
+    assert!(diagram.contains("participant \"Vault\\nfrom Test.sol\" as Vault"));
+    assert!(diagram.contains("User -> Vault ++ : sync()"));
+    assert!(diagram.contains("Vault -> oracle ++ : poke()"));
+    assert!(diagram.contains("oracle --> Vault -- : return"));
+    assert!(diagram.contains("\nend\n"));
+    assert!(!diagram.contains("->>"));
+}
+
+#[test]
+fn test_generate_diagram_from_str_round_trips_an_ast_string() {
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function("sync", "external", vec![], vec![])],
+    )]);
+
+    let config = Config::builder().output_file("never-written.md").build();
+    let diagram = sol2seq::generate_diagram_from_str(&ast.to_string(), config).unwrap();
+    assert_eq!(diagram, render(&ast));
+    assert!(!std::path::Path::new("never-written.md").exists());
+
+    let error = sol2seq::generate_diagram_from_str("{ not json", Config::default()).unwrap_err();
+    assert!(matches!(error, Sol2SeqError::JsonParse { .. }));
+}
+
+#[test]
+fn test_inline_bodies_splice_calls_under_each_entrypoint() {
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![
+            function(
+                "deposit",
+                "external",
+                vec![],
+                vec![member_call(
+                    identifier("oracle", Some("contract IOracle")),
+                    "poke",
+                    vec![],
+                )],
+            ),
+            function("pause", "external", vec![], vec![]),
+        ],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Contract-to-Contract Interactions"));
+
+    let config = Config::builder().inline_bodies(true).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    let expected = [
+        "User->>+Vault: deposit()",
+        "Vault->>+IOracle: poke()",
+        "IOracle-->>-Vault: return",
+        "Vault-->>-User: return",
+        "User->>+Vault: pause()",
+    ]
+    .join("\n");
+    assert!(diagram.contains(&expected), "{}", diagram);
+    assert!(!diagram.contains("Contract-to-Contract Interactions"));
+    assert!(!diagram.contains("Processing deposit"));
+}
+
+#[test]
+fn test_plain_output_omits_theme_blocks_and_rects() {
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function(
+            "sync",
+            "external",
+            vec![],
+            vec![member_call(identifier("oracle", None), "poke", vec![])],
+        )],
+    )]);
+
+    let config = Config::builder().plain(true).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.contains("sequenceDiagram"));
+    assert!(!diagram.contains("%%{init"), "{}", diagram);
+    assert!(!diagram.lines().any(|line| line.starts_with("rect ")));
+    assert!(!diagram.lines().any(|line| line == "end"));
+    assert!(diagram.contains("Note over User: User Interactions"));
+    assert!(diagram.contains("Note over User: Diagram Legend"));
+    assert!(diagram.contains("Vault->>+oracle: poke()"));
+    assert_balanced_activations(&diagram);
+
+    let config = Config::builder()
+        .plain(true)
+        .diagram_type(DiagramType::Class)
+        .build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(!diagram.contains("%%{init"));
+}
+
+#[test]
+fn test_legacy_ast_layout_is_translated() {
+    // Hand-written in the `name`/`attributes`/`children` layout of solc 0.4.x
+    // `--combined-json ast`; not actual compiler output
+    let ast: Value =
+        serde_json::from_str(include_str!("fixtures/synthetic_legacy_ast.json")).unwrap();
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("User->>+Ledger: record(account: address, amount: uint256)"));
+    assert!(diagram.contains("User->>+Vault: deploy / constructor(_ledger: Ledger)"));
+    assert!(diagram.contains("User->>+Vault: deposit(amount: uint256)"));
+    assert!(diagram.contains("Note over Vault: require(amount > 0)"));
+    assert!(diagram.contains("loop for each i (0..3)"));
+    assert!(diagram.contains("    Vault->>+Ledger: record("));
+    assert!(diagram.contains("Ledger->>Events: emit Recorded("));
+    assert!(diagram.contains("Note over Ledger: storage: balances[account] += amount"));
+
+    let report = validate_ast(&ast, &Config::default()).unwrap();
+    assert_eq!(report.contracts, 2);
+    assert!(report.is_valid(), "{:?}", report);
+}
+
+#[test]
+fn test_validate_ast_reports_counts_and_problems() {
+    let mut stub = function("pause", "external", vec![], vec![]);
+    stub.as_object_mut().unwrap().remove("body");
+    let mut no_visibility = function("sweep", "external", vec![], vec![]);
+    no_visibility.as_object_mut().unwrap().remove("visibility");
+    let ast = source_unit(vec![
+        contract(
+            "Vault",
+            "contract",
+            vec![
+                function(
+                    "deposit",
+                    "external",
+                    vec![],
+                    vec![member_call(
+                        identifier("token", Some("contract IERC20")),
+                        "transferFrom",
+                        vec![],
+                    )],
+                ),
+                no_visibility,
+            ],
+        ),
+        contract("Pausable", "contract", vec![stub.clone()]),
+        contract("IVault", "interface", vec![stub]),
+    ]);
+
+    let report = validate_ast(&ast, &Config::default()).unwrap();
+    assert_eq!(report.contracts, 3);
+    assert_eq!(report.functions, 4);
+    assert!(report.interactions > 0);
+    assert_eq!(report.contracts_without_bodies, vec!["Pausable"]);
+    assert_eq!(report.missing_fields, vec!["Vault.sweep.visibility"]);
+    assert!(!report.is_valid());
+
+    let empty = validate_ast(&source_unit(vec![]), &Config::default()).unwrap();
+    assert_eq!(empty.contracts, 0);
+    assert!(!empty.is_valid());
+
+    // Counts follow the same filters as `generate`
+    let config = Config::builder().contract("Vault").build();
+    let filtered = validate_ast(&ast, &config).unwrap();
+    assert_eq!(filtered.contracts, 1);
+    assert_eq!(filtered.functions, 2);
+}
+
+#[test]
+fn test_analyze_ast_returns_structured_model() {
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function(
+            "sync",
+            "external",
+            vec![],
+            vec![member_call(identifier("oracle", None), "poke", vec![])],
+        )],
+    )]);
+
+    let data = analyze_ast(&ast, &Config::default()).unwrap();
+
+    assert_eq!(data.contracts["Vault"].functions, vec!["sync".to_string()]);
+    assert!(data.participants.contains("User"));
+    assert_eq!(
+        data.participants.iter().collect::<Vec<_>>(),
+        ["Vault", "User"]
+    );
+    assert_eq!(
+        data.contract_interactions["Vault.sync"][0],
+        "Vault->>+oracle: poke()"
+    );
+}
+
+#[test]
+fn test_free_functions_get_their_own_lifeline() {
+    let mut free_function = function(
+        "settle",
+        "internal",
+        vec![param("amount", "uint256")],
+        vec![member_call(identifier("oracle", None), "poke", vec![])],
+    );
+    free_function["id"] = json!(20);
+    // An overload declared first must not shadow the definition the call is bound to
+    let mut overload = function(
+        "settle",
+        "internal",
+        vec![param("to", "address")],
+        vec![member_call(identifier("oracle", None), "skip", vec![])],
+    );
+    overload["id"] = json!(21);
+    let mut call_settle = identifier_call("settle", vec![identifier("amount", None)]);
+    call_settle["expression"]["expression"]["referencedDeclaration"] = json!(20);
+    let ast = source_unit(vec![
+        overload,
+        free_function,
+        contract(
+            "Vault",
+            "contract",
+            vec![function("withdraw", "external", vec![], vec![call_settle])],
+        ),
+    ]);
+
+    let diagram = render(&ast);
+
+    assert!(diagram.contains("participant FreeFunctions as \"FreeFunctions (free functions)"));
+    assert!(diagram.contains("Vault->>FreeFunctions: settle(amount: uint256)"));
+    assert!(diagram.contains("FreeFunctions->>+oracle: poke()"));
+    assert!(!diagram.contains("skip()"));
+    assert!(diagram.contains("Note over FreeFunctions: Functions: settle, settle"));
+    assert!(!diagram.contains("User->>+FreeFunctions"));
+}
+
+#[test]
+fn test_first_seen_participant_order() {
+    let entrypoint = || vec![function("run", "external", vec![], vec![])];
+    let ast = source_unit(vec![
+        contract("Zeta", "contract", entrypoint()),
+        contract("Alpha", "contract", entrypoint()),
+        contract("Mid", "contract", entrypoint()),
+    ]);
+
+    let participant_lines = |order: ParticipantOrder| -> Vec<String> {
+        let config = Config::builder().participant_order(order).build();
+        generate_sequence_diagram_with_config(&ast, config)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("participant "))
+            .map(|line| line.split(' ').next().unwrap().to_string())
+            .collect()
+    };
+
+    assert_eq!(
+        participant_lines(ParticipantOrder::Alphabetical),
+        ["User", "Alpha", "Mid", "Zeta"]
+    );
+    assert_eq!(
+        participant_lines(ParticipantOrder::FirstSeen),
+        ["User", "Zeta", "Alpha", "Mid"]
+    );
+    assert_eq!(
+        "first-seen".parse::<ParticipantOrder>(),
+        Ok(ParticipantOrder::FirstSeen)
+    );
+}
+
+#[test]
+fn test_rendering_is_deterministic_across_runs() {
+    let names = ["Zeta", "Alpha", "Omega", "Mid", "Beta", "Gamma"];
+    let ast = source_unit(
+        names
+            .iter()
+            .map(|name| {
+                let kind = if *name == "Gamma" {
+                    "library"
+                } else {
+                    "contract"
+                };
+                contract(
+                    name,
+                    kind,
+                    vec![function("run", "external", vec![], vec![])],
+                )
+            })
+            .collect(),
+    );
+
+    let first = render(&ast);
+    for _ in 0..5 {
+        assert_eq!(first, render(&ast));
+    }
+
+    let summaries: Vec<&str> = first
+        .lines()
+        .filter(|line| line.contains(": Functions: run"))
+        .map(|line| {
+            line.trim()
+                .trim_start_matches("Note over ")
+                .split(':')
+                .next()
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(
+        summaries,
+        ["Alpha", "Beta", "Gamma", "Mid", "Omega", "Zeta"]
+    );
+}
+
+#[test]
+fn test_json_format_exports_sorted_model() {
+    let ast = source_unit(vec![
+        contract(
+            "Vault",
+            "contract",
+            vec![function(
+                "sync",
+                "external",
+                vec![],
+                vec![member_call(identifier("oracle", None), "poke", vec![])],
+            )],
+        ),
+        contract("Oracle", "contract", vec![]),
+    ]);
+
+    let config = Config {
+        output_format: OutputFormat::Json,
+        ..Default::default()
+    };
+    let output = generate_sequence_diagram_with_config(&ast, config.clone()).unwrap();
+    let json: Value = serde_json::from_str(&output).unwrap();
+
+    let participants: Vec<&str> = json["participants"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|participant| participant.as_str().unwrap())
+        .collect();
+    let mut sorted = participants.clone();
+    sorted.sort();
+    assert_eq!(participants, sorted);
+
+    let contracts: Vec<&String> = json["contracts"].as_object().unwrap().keys().collect();
+    assert_eq!(contracts, ["Oracle", "Vault"]);
+    assert_eq!(json["contracts"]["Vault"]["functions"], json!(["sync"]));
+    assert_eq!(
+        json["contract_interactions"]["Vault.sync"][0],
+        "Vault->>+oracle: poke()"
+    );
+
+    // Repeated exports are byte-for-byte identical
+    assert_eq!(
+        output,
+        generate_sequence_diagram_with_config(&ast, config).unwrap()
+    );
+    assert_eq!("json".parse::<OutputFormat>(), Ok(OutputFormat::Json));
+}
+
+#[test]
+fn test_config_builder_chains_setters() {
+    let config = Config::builder()
+        .output_file("diagram.md")
+        .output_format(OutputFormat::PlantUml)
+        .actor("pause", "Admin")
+        .max_body_depth(4)
+        .build();
+
+    assert_eq!(config.output_file, Some("diagram.md".into()));
+    assert_eq!(config.output_format, OutputFormat::PlantUml);
+    assert_eq!(config.actor_map["pause"], "Admin");
+    assert_eq!(config.max_body_depth, 4);
+    assert!(config.show_storage_updates);
+}
+
+#[test]
+fn test_show_storage_updates_is_honored_by_literal_and_builder_configs() {
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![
+            param("total", "uint256"),
+            function(
+                "deposit",
+                "external",
+                vec![param("amount", "uint256")],
+                vec![assignment(
+                    identifier("total", None),
+                    "+=",
+                    identifier("amount", None),
+                )],
+            ),
+        ],
+    )]);
+    let storage_note = "Note over Vault: storage: total += amount";
+
+    let literal = Config {
+        show_storage_updates: false,
+        ..Default::default()
+    };
+    let built = Config::builder().show_storage_updates(false).build();
+    for config in [literal, built] {
+        let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+        assert!(!diagram.contains(storage_note));
+    }
+
+    let literal = Config {
+        show_storage_updates: true,
+        ..Default::default()
+    };
+    let built = Config::builder().show_storage_updates(true).build();
+    for config in [literal, built] {
+        let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+        assert!(diagram.contains(storage_note), "{}", diagram);
+    }
+}
+
+#[test]
+fn test_storage_notes_cover_mappings_and_inherited_state_only() {
+    let mut vault = contract(
+        "Vault",
+        "contract",
+        vec![function(
+            "credit",
+            "external",
+            vec![param("account", "address")],
+            vec![
+                assignment(
+                    json!({
+                        "nodeType": "IndexAccess",
+                        "baseExpression": identifier("balances", None),
+                        "indexExpression": identifier("account", None),
+                    }),
+                    "=",
+                    json!({ "nodeType": "Literal", "kind": "number", "value": "1" }),
+                ),
+                assignment(
+                    identifier("scratch", None),
+                    "=",
+                    identifier("account", None),
+                ),
+            ],
+        )],
+    );
+    vault["baseContracts"] = json!([{ "baseName": { "name": "Ledger" } }]);
+    let ast = source_unit(vec![
+        contract("Ledger", "contract", vec![param("balances", "mapping")]),
+        vault,
+    ]);
+
+    let diagram = render(&ast);
+    assert!(
+        diagram.contains("Note over Vault: storage: balances[account] = 1"),
+        "{}",
+        diagram
+    );
+    assert!(!diagram.contains("storage: scratch"));
+}
+
+#[test]
+fn test_builtin_calls_get_notes_instead_of_lifelines() {
+    let call = |base: Value, member: &str, args: Vec<Value>| {
+        json!({
+            "nodeType": "FunctionCall",
+            "expression": { "nodeType": "MemberAccess", "memberName": member, "expression": base },
+            "arguments": args,
+        })
+    };
+    let declare = |name: &str, type_name: &str, initial_value: Value| {
+        json!({
+            "nodeType": "VariableDeclarationStatement",
+            "declarations": [param(name, type_name)],
+            "initialValue": initial_value,
+        })
+    };
+    let hash = json!({
+        "nodeType": "FunctionCall",
+        "expression": identifier("keccak256", None),
+        "arguments": [call(identifier("abi", None), "encode", vec![identifier("x", None)])],
+    });
+    let decl = declare("h", "bytes32", hash);
+    let encoded = call(
+        identifier("abi", None),
+        "encodePacked",
+        vec![identifier("x", None)],
+    );
+    let decl2 = declare("d", "bytes", encoded);
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function(
+            "f",
+            "external",
+            vec![],
+            vec![
+                member_call(
+                    identifier("abi", None),
+                    "encode",
+                    vec![identifier("x", None)],
+                ),
+                identifier_call("keccak256", vec![identifier("x", None)]),
+                identifier_call("ecrecover", vec![identifier("x", None)]),
+                decl,
+                decl2,
+                member_call(identifier("block", None), "blockhash", vec![]),
+                member_call(identifier("string", None), "concat", vec![]),
+            ],
+        )],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Note over Vault: abi.encode(x)"));
+    assert!(diagram.contains("Note over Vault: ecrecover(x)"));
+    assert!(diagram.contains("Note over Vault: h = keccak256(abi.encode(x))"));
+    assert!(diagram.contains("Note over Vault: d = abi.encodePacked(x)"));
+    for builtin in ["abi", "keccak256", "block", "string"] {
+        assert!(!diagram.contains(&format!("->>+{}:", builtin)));
+        assert!(!diagram.contains(&format!("{}-->>-", builtin)));
+    }
+    assert!(!diagram.contains("blockhash"));
+}
+
+#[test]
+fn test_array_push_pop_and_delete_render_as_storage_notes() {
+    let delete = json!({
+        "nodeType": "ExpressionStatement",
+        "expression": {
+            "nodeType": "UnaryOperation",
+            "operator": "delete",
+            "prefix": true,
+            "subExpression": {
+                "nodeType": "IndexAccess",
+                "baseExpression": identifier("balances", None),
+                "indexExpression": identifier("account", None),
+            },
+        },
+    });
+    let ast = source_unit(vec![contract(
+        "Registry",
+        "contract",
+        vec![
+            param("users", "address[]"),
+            param("balances", "mapping"),
+            function(
+                "rotate",
+                "external",
+                vec![param("account", "address")],
+                vec![
+                    member_call(
+                        identifier("users", Some("address[] storage ref")),
+                        "push",
+                        vec![identifier("account", None)],
+                    ),
+                    member_call(identifier("users", None), "pop", vec![]),
+                    delete,
+                    member_call(identifier("queue", Some("contract IQueue")), "push", vec![]),
+                ],
+            ),
+        ],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Note over Registry: storage: push to users"));
+    assert!(diagram.contains("Note over Registry: storage: pop from users"));
+    assert!(diagram.contains("Note over Registry: storage: delete balances[account]"));
+    assert!(!diagram.contains("->>+users"));
+    assert!(!diagram.contains("participant users"));
+    assert!(diagram.contains("Registry->>+IQueue: push()"));
+
+    let config = Config::builder().show_storage_updates(false).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(!diagram.contains("storage:"));
+    assert!(!diagram.contains("->>+users"));
+}
+
+#[test]
+fn test_state_writes_after_external_calls_are_flagged() {
+    let reset = || {
+        assignment(
+            identifier("balance", None),
+            "=",
+            json!({ "nodeType": "Literal", "kind": "number", "value": "0" }),
+        )
+    };
+    let payout = || {
+        member_call(
+            identifier("token", Some("contract IERC20")),
+            "transfer",
+            vec![identifier("amount", None)],
+        )
+    };
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![
+            param("balance", "uint256"),
+            function(
+                "unsafeWithdraw",
+                "external",
+                vec![],
+                vec![payout(), reset()],
+            ),
+            function("safeWithdraw", "external", vec![], vec![reset(), payout()]),
+        ],
+    )]);
+    let warning = "Note over Vault: ⚠ state change after external call";
+
+    assert!(!render(&ast).contains(warning));
+
+    let config = Config::builder().flag_reentrancy(true).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert_eq!(diagram.matches(warning).count(), 1);
+    let lines: Vec<&str> = diagram.lines().collect();
+    let flagged = lines.iter().position(|line| *line == warning).unwrap();
+    assert_eq!(lines[flagged + 1], "Note over Vault: storage: balance = 0");
+    let processing = |name: &str| {
+        let note = format!("Note right of Vault: Processing {}", name);
+        lines.iter().position(|line| *line == note).unwrap()
+    };
+    assert!(processing("unsafeWithdraw") < flagged && flagged < processing("safeWithdraw"));
+}
+
+#[test]
+fn test_constructors_render_as_deployment_entrypoints() {
+    let body = vec![member_call(
+        identifier("registry", Some("contract IRegistry")),
+        "register",
+        vec![],
+    )];
+    let params = vec![typed_param("token", "IERC20"), param("cap", "uint256")];
+    let mut constructor = function("", "internal", params, body);
+    constructor["kind"] = json!("constructor");
+    let ast = source_unit(vec![contract("Vault", "abstract", vec![constructor])]);
+
+    let diagram = render(&ast);
+    assert!(
+        diagram.contains("User->>+Vault: deploy / constructor(token: IERC20, cap: uint256)"),
+        "{}",
+        diagram
+    );
+    assert!(diagram.contains("Vault->>+IRegistry: register()"));
+    assert!(diagram.contains("Vault-->>-User: return"));
+}
+
+#[test]
+fn test_base_arguments_appear_in_inheritance_note() {
+    let string = |value: &str| json!({ "nodeType": "Literal", "kind": "string", "value": value });
+    let sender = json!({
+        "nodeType": "MemberAccess",
+        "memberName": "sender",
+        "expression": identifier("msg", None),
+    });
+    let mut token = contract("Token", "contract", vec![]);
+    token["baseContracts"] = json!([
+        { "baseName": { "name": "ERC20" }, "arguments": [string("Name"), string("SYM")] },
+        { "baseName": { "name": "Ownable" }, "arguments": [sender] },
+        { "baseName": { "name": "Pausable" }, "arguments": null },
+    ]);
+    let ast = source_unit(vec![
+        contract("ERC20", "contract", vec![]),
+        contract("Ownable", "abstract", vec![]),
+        contract("Pausable", "abstract", vec![]),
+        token,
+    ]);
+
+    let diagram = render(&ast);
+    let note = "Note right of Token: Inherits from: \
+                ERC20(\"Name\", \"SYM\"), Ownable(msg.sender), Pausable";
+    assert!(diagram.contains(note), "{}", diagram);
+
+    let data = analyze_ast(&ast, &Config::default()).unwrap();
+    let bases: Vec<(&str, Option<usize>)> = data
+        .contract_relationships
+        .iter()
+        .filter(|rel| rel.relation_type == "inherits")
+        .map(|rel| (rel.target.as_str(), rel.order))
+        .collect();
+    assert_eq!(
+        bases,
+        vec![
+            ("ERC20", Some(0)),
+            ("Ownable", Some(1)),
+            ("Pausable", Some(2))
+        ]
+    );
+}
+
+#[test]
+fn test_base_constructor_calls_follow_inheritance_order() {
+    let mut owner = param("owner", "address");
+    owner["id"] = json!(7);
+    let mut constructor = function("", "public", vec![owner], vec![]);
+    constructor["kind"] = json!("constructor");
+    constructor["modifiers"] = json!([
+        {
+            "nodeType": "ModifierInvocation",
+            "kind": "baseConstructorSpecifier",
+            "modifierName": { "nodeType": "IdentifierPath", "name": "Pausable" },
+            "arguments": [{ "nodeType": "Literal", "kind": "bool", "value": "true" }],
+        },
+        {
+            "nodeType": "ModifierInvocation",
+            "modifierName": { "nodeType": "Identifier", "name": "Ownable" },
+            "arguments": [{ "nodeType": "Identifier", "name": "owner", "referencedDeclaration": 7 }],
+        },
+    ]);
+    let mut vault = contract("Vault", "contract", vec![constructor]);
+    vault["baseContracts"] = json!([
+        { "baseName": { "name": "Ownable" } },
+        { "baseName": { "name": "Pausable" } },
+    ]);
+    let ast = source_unit(vec![
+        contract("Ownable", "abstract", vec![]),
+        contract("Pausable", "abstract", vec![]),
+        vault,
+    ]);
+
+    let diagram = render(&ast);
+    let lines: Vec<&str> = diagram.lines().collect();
+    let ownable = lines
+        .iter()
+        .position(|line| *line == "Vault->>Ownable: constructor(owner: address)")
+        .unwrap();
+    let pausable = lines
+        .iter()
+        .position(|line| *line == "Vault->>Pausable: constructor(\"true\": bool)")
+        .unwrap();
+    assert!(ownable < pausable);
+    assert!(!diagram.contains("requires Ownable"));
+}
+
+#[test]
+fn test_payable_entrypoints_are_tagged_and_noted() {
+    let msg_value = json!({
+        "nodeType": "MemberAccess",
+        "memberName": "value",
+        "expression": identifier("msg", None),
+    });
+    let mut deposit = function(
+        "deposit",
+        "external",
+        vec![],
+        vec![assignment(identifier("total", None), "+=", msg_value)],
+    );
+    deposit["stateMutability"] = json!("payable");
+    let mut donate = function("donate", "external", vec![], vec![]);
+    donate["stateMutability"] = json!("payable");
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![
+            deposit,
+            donate,
+            function("sync", "external", vec![], vec![]),
+        ],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("User->>+Vault: deposit() [payable]"));
+    assert!(diagram.contains("Note over User,Vault: sends ETH (uses msg.value)"));
+    assert!(diagram.contains("User->>+Vault: donate() [payable]"));
+    assert!(diagram.contains("Note over User,Vault: sends ETH\n"));
+    assert!(diagram.contains("User->>+Vault: sync()\n"));
+    assert_eq!(diagram.matches("sends ETH").count(), 2);
+}
+
+#[test]
+fn test_virtual_and_override_functions_are_tagged() {
+    let mut deposit = function("deposit", "external", vec![], vec![]);
+    deposit["overrides"] = json!({
+        "nodeType": "OverrideSpecifier",
+        "overrides": [
+            { "nodeType": "IdentifierPath", "name": "Base" },
+            { "nodeType": "IdentifierPath", "name": "IVault" },
+        ],
+    });
+    let mut withdraw = function("withdraw", "external", vec![], vec![]);
+    withdraw["overrides"] = json!({ "nodeType": "OverrideSpecifier", "overrides": [] });
+    withdraw["virtual"] = json!(true);
+    let mut hook = function("hook", "public", vec![], vec![]);
+    hook["virtual"] = json!(true);
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![
+            deposit,
+            withdraw,
+            hook,
+            function("sync", "external", vec![], vec![]),
+        ],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("User->>+Vault: deposit() (overrides Base.deposit, IVault.deposit)"));
+    assert!(diagram.contains("User->>+Vault: withdraw() (virtual) (override)"));
+    assert!(diagram.contains("User->>+Vault: hook() (virtual)\n"));
+    assert!(diagram.contains("User->>+Vault: sync()\n"));
+}
+
+#[test]
+fn test_contracts_are_boxed_by_source_file() {
+    let unit = |path: &str, contracts: Vec<Value>| {
+        let mut unit = source_unit(contracts);
+        unit["absolutePath"] = json!(path);
+        json!({ "ast": unit })
+    };
+    let sync = function(
+        "sync",
+        "external",
+        vec![],
+        vec![member_call(
+            identifier("token", Some("contract IToken")),
+            "poke",
+            vec![],
+        )],
+    );
+    let ast = json!({ "sources": {
+        "A.sol": unit("A.sol", vec![
+            contract("Pool", "contract", vec![]),
+            contract("Vault", "contract", vec![sync]),
+        ]),
+        "B.sol": unit("B.sol", vec![contract("Oracle", "contract", vec![])]),
+    }});
+
+    let config = Config::builder().group_by_source_file(true).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    let declarations: Vec<&str> = diagram
+        .lines()
+        .skip_while(|line| !line.starts_with("participant "))
+        .take_while(|line| !line.is_empty())
+        .map(|line| line.split(" as ").next().unwrap())
+        .collect();
+    assert_eq!(
+        declarations,
+        [
+            "participant User",
+            "participant IToken",
+            "box B.sol",
+            "participant Oracle",
+            "end",
+            "box A.sol",
+            "participant Pool",
+            "participant Vault",
+            "end",
+        ]
+    );
+    assert!(!render(&ast).contains("box "));
+}
+
+#[test]
+fn test_emits_use_declared_event_parameters() {
+    let mut from = param("from", "address");
+    from["indexed"] = json!(true);
+    let transfer = json!({
+        "nodeType": "EventDefinition",
+        "name": "Transfer",
+        "parameters": { "parameters": [from, param("value", "uint256")] },
+    });
+    let sync = json!({
+        "nodeType": "EventDefinition",
+        "name": "Sync",
+        "parameters": { "parameters": [param("reserve", "uint256")] },
+    });
+    let emit = |event: &str, arguments: Vec<Value>| {
+        json!({
+            "nodeType": "EmitStatement",
+            "eventCall": {
+                "nodeType": "FunctionCall",
+                "expression": identifier(event, None),
+                "arguments": arguments,
+            },
+        })
+    };
+    let mut emit_transfer = emit(
+        "Transfer",
+        vec![identifier("msg.sender", None), identifier("amount", None)],
+    );
+    let mut bound_transfer = emit_transfer.clone();
+    bound_transfer["eventCall"]["expression"]["referencedDeclaration"] = json!(30);
+    // An unrelated contract's event of the same name is not the one being emitted
+    emit_transfer["eventCall"]["expression"]["referencedDeclaration"] = json!(99);
+    let mut token = contract(
+        "Token",
+        "contract",
+        vec![function(
+            "send",
+            "external",
+            vec![],
+            vec![
+                bound_transfer,
+                emit_transfer,
+                emit("Sync", vec![identifier("amount", None)]),
+            ],
+        )],
+    );
+    token["baseContracts"] = json!([{ "baseName": { "name": "Base" } }]);
+    let mut base = contract("Base", "abstract", vec![transfer]);
+    base["nodes"][0]["id"] = json!(30);
+    let ast = source_unit(vec![base, contract("Pair", "contract", vec![sync]), token]);
+
+    let diagram = render(&ast);
+    assert!(
+        diagram.contains("Token->>Events: emit Transfer(from: address idx, value: uint256)"),
+        "{}",
+        diagram
+    );
+    assert!(diagram.contains("Token->>Events: emit Transfer(msg.sender: any, amount: uint256)"));
+    assert!(diagram.contains("Token->>Events: emit Sync(amount: uint256)"));
+}
+
+#[test]
+fn test_event_definitions_list_parameter_signatures() {
+    let mut from = param("from", "address");
+    from["indexed"] = json!(true);
+    let transfer = json!({
+        "nodeType": "EventDefinition",
+        "name": "Transfer",
+        "parameters": { "parameters": [from, param("to", "address"), param("value", "uint256")] },
+    });
+    let paused = json!({
+        "nodeType": "EventDefinition",
+        "name": "Paused",
+        "parameters": { "parameters": [] },
+    });
+    let ast = source_unit(vec![contract("Token", "contract", vec![transfer, paused])]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains(
+        "Note over Token,Token: Event: Transfer(from: address, to: address, value: uint256)"
+    ));
+    assert!(diagram.contains("Note over Token,Token: Event: Paused()"));
+}
+
+#[test]
+fn test_error_definitions_section_lists_custom_errors() {
+    let unauthorized = json!({
+        "nodeType": "ErrorDefinition",
+        "name": "Unauthorized",
+        "parameters": { "parameters": [param("caller", "address"), param("needed", "uint256")] },
+    });
+    let ast = source_unit(vec![contract("Vault", "contract", vec![unauthorized])]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Note over Vault: Error Definitions"));
+    assert!(diagram
+        .contains("Note over Vault,Vault: Error: Unauthorized(caller: address, needed: uint256)"));
+
+    let config = Config::builder().show_errors_section(false).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(!diagram.contains("Error Definitions"));
+}
+
+#[test]
+fn test_argument_types_follow_referenced_declarations() {
+    let mut token_id = param("tokenId", "uint256");
+    token_id["id"] = json!(7);
+    let mut argument = identifier("tokenId", None);
+    argument["referencedDeclaration"] = json!(7);
+
+    let ast = source_unit(vec![contract(
+        "Market",
+        "contract",
+        vec![function(
+            "list",
+            "external",
+            vec![token_id],
+            vec![member_call(
+                identifier("registry", None),
+                "register",
+                vec![argument],
+            )],
+        )],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(
+        diagram.contains("Market->>+registry: register(tokenId: uint256)"),
+        "{}",
+        diagram
+    );
+}
+
+#[test]
+fn test_call_receivers_resolve_to_contract_types() {
+    let mut vault = typed_param("vault", "Vault");
+    vault["id"] = json!(3);
+    let mut receiver = identifier("vault", None);
+    receiver["referencedDeclaration"] = json!(3);
+
+    let ast = source_unit(vec![
+        contract(
+            "Vault",
+            "contract",
+            vec![function("deposit", "external", vec![], vec![])],
+        ),
+        contract(
+            "Router",
+            "contract",
+            vec![function(
+                "route",
+                "external",
+                vec![vault],
+                vec![
+                    member_call(receiver, "deposit", vec![]),
+                    member_call(
+                        identifier("feed", Some("contract IOracle")),
+                        "latest",
+                        vec![],
+                    ),
+                    member_call(identifier("helper", None), "poke", vec![]),
+                ],
+            )],
+        ),
+    ]);
+
+    let diagram = render(&ast);
+    assert!(
+        diagram.contains("Router->>+Vault: deposit()"),
+        "{}",
+        diagram
+    );
+    assert!(diagram.contains("Router->>+IOracle: latest()"));
+    assert!(diagram.contains("participant IOracle"));
+    assert!(diagram.contains("Router->>+helper: poke()"));
+}
+
+#[test]
+fn test_chained_calls_render_each_hop_in_order() {
+    let mut get_vault =
+        member_call(identifier("registry", None), "getVault", vec![])["expression"].clone();
+    get_vault["typeDescriptions"] = json!({ "typeString": "contract IVault" });
+    let two_level = member_call(get_vault, "deposit", vec![]);
+
+    let first = member_call(identifier("a", None), "b", vec![])["expression"].clone();
+    let second = member_call(first, "c", vec![])["expression"].clone();
+    let three_level = member_call(second, "d", vec![]);
+
+    let ast = source_unit(vec![contract(
+        "Pool",
+        "contract",
+        vec![function(
+            "run",
+            "external",
+            vec![],
+            vec![two_level, three_level],
+        )],
+    )]);
+
+    let diagram = render(&ast);
+    let expected = [
+        "Pool->>+registry: getVault()",
+        "registry-->>-Pool: return",
+        "Pool->>+IVault: deposit()",
+        "IVault-->>-Pool: return",
+        "Pool->>+a: b()",
+        "a-->>-Pool: return",
+        "Pool->>+a_b: c()",
+        "a_b-->>-Pool: return",
+        "Pool->>+a_b_c: d()",
+        "a_b_c-->>-Pool: return",
+    ]
+    .join("\n");
+    assert!(diagram.contains(&expected), "{}", diagram);
+}
+
+#[test]
+fn test_chained_calls_assigned_to_locals_render_each_hop() {
+    // `uint v = registry.getVault(id).balance();`
+    let mut get_vault = member_call(
+        identifier("registry", None),
+        "getVault",
+        vec![identifier("id", None)],
+    )["expression"]
+        .clone();
+    get_vault["typeDescriptions"] = json!({ "typeString": "contract IVault" });
+    let two_level = declaration(
+        &["v"],
+        member_call(get_vault, "balance", vec![])["expression"].clone(),
+    );
+
+    // `uint x = a.b().c();`
+    let first = member_call(identifier("a", None), "b", vec![])["expression"].clone();
+    let three_level = declaration(
+        &["x"],
+        member_call(first, "c", vec![])["expression"].clone(),
+    );
+
+    let ast = source_unit(vec![contract(
+        "Pool",
+        "contract",
+        vec![function(
+            "run",
+            "external",
+            vec![],
+            vec![two_level, three_level],
+        )],
+    )]);
+
+    let diagram = render(&ast);
+    let expected = [
+        "Pool->>+registry: getVault(id: bytes32)",
+        "registry-->>-Pool: return",
+        "Pool->>+IVault: balance()",
+        "IVault-->>-Pool: return → v",
+        "Pool->>+a: b()",
+        "a-->>-Pool: return",
+        "Pool->>+a_b: c()",
+        "a_b-->>-Pool: return → x",
+    ]
+    .join("\n");
+    assert!(diagram.contains(&expected), "{}", diagram);
+}
+
+#[test]
+fn test_this_calls_render_as_external_self_calls() {
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function(
+            "sweep",
+            "external",
+            vec![],
+            vec![member_call(
+                identifier("this", Some("contract Vault")),
+                "flush",
+                vec![],
+            )],
+        )],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("Note over Vault: external call to itself (msg.sender becomes Vault)"));
+    assert!(diagram.contains("Vault->>Vault: flush() [external self-call]"));
+    assert!(!diagram.contains("participant this"));
+    assert!(!diagram.contains("->>+this"));
+}
+
+#[test]
+fn test_contract_using_for_attributes_member_calls_to_library() {
+    let using_for = json!({
+        "nodeType": "UsingForDirective",
+        "libraryName": { "nodeType": "IdentifierPath", "name": "SafeERC20" },
+        "typeName": { "nodeType": "UserDefinedTypeName", "name": "IERC20" },
+    });
+    let mut token = typed_param("token", "IERC20");
+    token["id"] = json!(11);
+    let mut receiver = identifier("token", None);
+    receiver["referencedDeclaration"] = json!(11);
+
+    let ast = source_unit(vec![
+        contract(
+            "SafeERC20",
+            "library",
+            vec![function("safeTransfer", "internal", vec![], vec![])],
+        ),
+        contract(
+            "Vault",
+            "contract",
+            vec![
+                using_for,
+                function(
+                    "pay",
+                    "external",
+                    vec![token],
+                    vec![member_call(
+                        receiver,
+                        "safeTransfer",
+                        vec![identifier("to", None)],
+                    )],
+                ),
+            ],
+        ),
+    ]);
+
+    let diagram = render(&ast);
+    assert!(
+        diagram.contains("Vault->>SafeERC20: safeTransfer(token, to: any)"),
+        "{}",
+        diagram
+    );
+    assert!(!diagram.contains("->>+TokenContract"));
+}
+
+#[test]
+fn test_receive_and_fallback_are_entrypoints() {
+    let mut receive = function("", "external", vec![], vec![]);
+    receive["kind"] = json!("receive");
+    receive["stateMutability"] = json!("payable");
+    let mut fallback = function("", "external", vec![], vec![]);
+    fallback["kind"] = json!("fallback");
+
+    let ast = source_unit(vec![contract(
+        "Wallet",
+        "contract",
+        vec![receive, fallback],
+    )]);
+
+    let diagram = render(&ast);
+    let expected = [
+        "Note over User,Wallet: receive handles plain ETH transfers",
+        "User->>+Wallet: receive() [ETH transfer]",
+    ]
+    .join("\n");
+    assert!(diagram.contains(&expected), "{}", diagram);
+    assert!(diagram.contains("Note over User,Wallet: fallback handles unmatched calls"));
+    assert!(diagram.contains("User->>+Wallet: fallback(bytes)"));
+    assert!(!diagram.contains("User->>+Wallet: ()"));
+}
+
+#[test]
+fn test_interfaces_are_labeled_without_entrypoints() {
+    let mut balance_of = function(
+        "balanceOf",
+        "external",
+        vec![param("owner", "address")],
+        vec![],
+    );
+    balance_of["implemented"] = json!(false);
+    balance_of.as_object_mut().unwrap().remove("body");
+
+    let mut base = contract("Base", "contract", vec![balance_of.clone()]);
+    base["abstract"] = json!(true);
+    let ast = source_unit(vec![
+        contract("IERC20", "interface", vec![balance_of]),
+        base,
+    ]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("participant IERC20 as \"«interface» IERC20"));
+    assert!(diagram.contains("participant Base as \"«abstract» Base"));
+    assert!(!diagram.contains("User->>"), "{}", diagram);
+}
+
+#[test]
+fn test_inherited_functions_are_listed_on_derived_contracts() {
+    let inheriting = |name: &str, bases: &[&str], functions: Vec<Value>| {
+        let mut node = contract(name, "contract", functions);
+        node["baseContracts"] = bases
+            .iter()
+            .map(|b| json!({ "baseName": { "name": b } }))
+            .collect();
+        node
+    };
+
+    let ast = source_unit(vec![
+        inheriting(
+            "Token",
+            &[],
+            vec![
+                function("mint", "public", vec![], vec![]),
+                function("burn", "public", vec![], vec![]),
+                function("_move", "internal", vec![], vec![]),
+            ],
+        ),
+        inheriting(
+            "Pausable",
+            &["Token"],
+            vec![function("pause", "external", vec![], vec![])],
+        ),
+        inheriting(
+            "MyToken",
+            &["Token", "Pausable"],
+            vec![function("burn", "public", vec![], vec![])],
+        ),
+        inheriting("Loop", &["Loop"], vec![]),
+    ]);
+
+    let diagram = render(&ast);
+    assert!(
+        diagram.contains(
+            "Note over MyToken: Functions: burn, pause (inherited from Pausable), \
+             mint (inherited from Token)"
+        ),
+        "{}",
+        diagram
+    );
+    assert!(!diagram.contains("_move (inherited"));
+}
+
+#[test]
+fn test_inherited_functions_follow_linearization_and_are_entrypoints() {
+    // D is B, C; B is A; C is A. C3 gives D, C, B, A, so B's `f` shadows A's.
+    let linearized = |id: i64, name: &str, bases: &[&str], lineage: &[i64], functions| {
+        let mut node = contract(name, "contract", functions);
+        node["id"] = json!(id);
+        node["baseContracts"] = bases
+            .iter()
+            .map(|b| json!({ "baseName": { "name": b } }))
+            .collect();
+        node["linearizedBaseContracts"] = json!(lineage);
+        node
+    };
+    let f = || vec![function("f", "public", vec![], vec![])];
+    let ast = source_unit(vec![
+        linearized(1, "A", &[], &[1], f()),
+        linearized(2, "B", &["A"], &[2, 1], f()),
+        linearized(3, "C", &["A"], &[3, 1], vec![]),
+        linearized(4, "D", &["B", "C"], &[4, 3, 2, 1], vec![]),
+    ]);
+
+    let diagram = render(&ast);
+    assert!(
+        diagram.contains("Note over D: Functions: f (inherited from B)"),
+        "{}",
+        diagram
+    );
+    assert!(diagram.contains("User->>+D: f() (inherited from B)"));
+    assert!(diagram.contains("D-->>-User: return"));
+    assert!(!diagram.contains("User->>+D: f() (inherited from A)"));
+    assert!(diagram.contains("User->>+C: f() (inherited from A)"));
+}
+
+#[test]
+fn test_dark_theme_uses_dark_colors() {
+    let ast = source_unit(vec![contract("Vault", "contract", vec![])]);
+
+    let config = Config::builder().theme(Theme::Dark).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.contains("'primaryColor': '#1f2937',"));
+    assert!(diagram.contains("'primaryTextColor': '#e5e7eb',"));
+    assert!(diagram.contains("rect rgb(31, 41, 55)"));
+
+    // An explicit theme wins over the `light_colors` shorthand
+    let config = Config::builder()
+        .theme(Theme::Dark)
+        .light_colors(true)
+        .build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.contains("'primaryColor': '#1f2937',"));
+
+    assert_eq!("dark".parse::<Theme>(), Ok(Theme::Dark));
+}
+
+#[test]
+fn test_missing_solc_binary_is_reported_with_its_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("Vault.sol");
+    std::fs::write(&source, "contract Vault {}").unwrap();
+
+    let config = Config::builder()
+        .solc_path("/nonexistent/solc-0.8.24")
+        .solc_arg("--optimize")
+        .build();
+    let error = sol2seq::generate_diagram_from_sources(&[&source], config).unwrap_err();
+
+    assert!(
+        format!("{:#}", error).contains("/nonexistent/solc-0.8.24"),
+        "{:#}",
+        error
+    );
+    assert!(
+        matches!(error, Sol2SeqError::SolcNotFound(_)),
+        "{:?}",
+        error
+    );
+}
+
+#[test]
+fn test_public_api_returns_typed_errors() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let error =
+        sol2seq::generate_diagram_from_sources(&[dir.path()], Config::default()).unwrap_err();
+    assert!(
+        matches!(error, Sol2SeqError::NoSolidityFiles),
+        "{:?}",
+        error
+    );
+
+    let ast_file = dir.path().join("ast.json");
+    std::fs::write(&ast_file, "{ not json").unwrap();
+    let error = sol2seq::generate_diagram_from_file(&ast_file, Config::default()).unwrap_err();
+    assert!(
+        matches!(error, Sol2SeqError::JsonParse { .. }),
+        "{:?}",
+        error
+    );
+
+    let error =
+        sol2seq::generate_diagram_from_file(dir.path().join("missing.json"), Config::default())
+            .unwrap_err();
+    assert!(matches!(error, Sol2SeqError::Io { .. }), "{:?}", error);
+
+    let error = analyze_ast(&json!({ "contracts": [] }), &Config::default()).unwrap_err();
+    assert!(
+        matches!(error, Sol2SeqError::UnsupportedAstFormat(_)),
+        "{:?}",
+        error
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_solc_failure_carries_its_stderr() {
+    let dir = tempfile::tempdir().unwrap();
+    let solc = fake_solc(
+        dir.path(),
+        &Value::Null,
+        Some("echo 'ParserError: Expected pragma' >&2\nexit 1"),
+    );
+    let source = dir.path().join("Vault.sol");
+    std::fs::write(&source, "contract Vault {").unwrap();
+
+    let config = Config::builder().solc_path(&solc).build();
+    let error = sol2seq::generate_diagram_from_sources(&[&source], config).unwrap_err();
+
+    match error {
+        Sol2SeqError::SolcFailed { stderr, .. } => assert!(stderr.contains("Expected pragma")),
+        other => panic!("expected SolcFailed, got {:?}", other),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_remappings_are_forwarded_to_solc() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("Vault.sol");
+    std::fs::write(&source, "contract Vault {}").unwrap();
+    std::fs::write(
+        dir.path().join("remappings.txt"),
+        "# deps\n@oz/=lib/oz/\n\n",
+    )
+    .unwrap();
+
+    // Stand-in for solc that records its arguments and prints an empty combined-json
+    let args_file = dir.path().join("args.txt");
+    let script = format!("echo \"$@\" > {}\ncat \"$out\"", args_file.display());
+    let solc = fake_solc(dir.path(), &json!({ "sources": {} }), Some(&script));
+
+    let config = Config::builder()
+        .solc_path(&solc)
+        .remapping("ds-test/=lib/ds-test/")
+        .build();
+    sol2seq::generate_diagram_from_sources(&[&source], config).unwrap();
+
+    let args = std::fs::read_to_string(args_file).unwrap();
+    assert!(
+        args.contains("ds-test/=lib/ds-test/ @oz/=lib/oz/ --combined-json ast"),
+        "{}",
+        args
+    );
+    assert_eq!(
+        sol2seq::read_remappings_file(dir.path().join("remappings.txt")).unwrap(),
+        vec!["@oz/=lib/oz/".to_string()]
+    );
+}
+
+#[test]
+fn test_foundry_artifacts_are_merged_once_per_source() {
+    let out = tempfile::tempdir().unwrap();
+    let mut ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function("deposit", "external", vec![], vec![])],
+    )]);
+    ast["absolutePath"] = json!("src/Vault.sol");
+    let artifact = json!({ "abi": [], "ast": ast }).to_string();
+
+    let artifact_dir = out.path().join("Vault.sol");
+    std::fs::create_dir_all(&artifact_dir).unwrap();
+    std::fs::write(artifact_dir.join("Vault.json"), &artifact).unwrap();
+    std::fs::write(artifact_dir.join("IVault.json"), &artifact).unwrap();
+    std::fs::create_dir_all(out.path().join("build-info")).unwrap();
+    std::fs::write(out.path().join("build-info").join("abc.json"), "not json").unwrap();
+    std::fs::write(out.path().join("cache.json"), "{}").unwrap();
+
+    let diagram = sol2seq::generate_diagram_from_foundry(out.path(), Config::default()).unwrap();
+    assert_eq!(
+        diagram.matches("User->>+Vault: deposit()").count(),
+        1,
+        "{}",
+        diagram
+    );
+}
+
+#[test]
+fn test_hardhat_build_info_sources_are_extracted() {
+    let dir = tempfile::tempdir().unwrap();
+    let vault = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function("deposit", "external", vec![], vec![])],
+    )]);
+    let token = source_unit(vec![contract(
+        "Token",
+        "contract",
+        vec![function("mint", "public", vec![], vec![])],
+    )]);
+    let build_info = json!({
+        "id": "abc",
+        "output": {
+            "sources": {
+                "contracts/Vault.sol": { "id": 0, "ast": vault },
+                "contracts/Token.sol": { "id": 1, "ast": token },
+            },
+        },
+    });
+    let build_info_file = dir.path().join("abc.json");
+    std::fs::write(&build_info_file, build_info.to_string()).unwrap();
+
+    let diagram =
+        sol2seq::generate_diagram_from_hardhat(&build_info_file, Config::default()).unwrap();
+    assert!(diagram.contains("User->>+Vault: deposit()"));
+    assert!(diagram.contains("User->>+Token: mint()"));
+
+    let from_dir = sol2seq::generate_diagram_from_hardhat(dir.path(), Config::default()).unwrap();
+    assert_eq!(from_dir.matches("User->>+Vault: deposit()").count(), 1);
+}
+
+#[test]
+fn test_standard_json_output_is_recognized() {
+    let standard_json = json!({
+        "contracts": {},
+        "sources": {
+            "src/Vault.sol": {
+                "id": 0,
+                "ast": source_unit(vec![contract(
+                    "Vault",
+                    "contract",
+                    vec![function("deposit", "external", vec![], vec![])],
+                )]),
+            },
+        },
+    });
+
+    let diagram = render(&standard_json);
+    assert!(diagram.contains("User->>+Vault: deposit()"));
+}
+
+#[test]
+fn test_contract_filter_keeps_named_contracts_and_call_targets() {
+    let ast = source_unit(vec![
+        contract(
+            "Oracle",
+            "contract",
+            vec![function("latest", "external", vec![], vec![])],
+        ),
+        contract(
+            "Vault",
+            "contract",
+            vec![function(
+                "deposit",
+                "external",
+                vec![],
+                vec![member_call(
+                    identifier("oracle", Some("contract Oracle")),
+                    "latest",
+                    vec![],
+                )],
+            )],
+        ),
+        contract(
+            "Treasury",
+            "contract",
+            vec![function("sweep", "external", vec![], vec![])],
+        ),
+    ]);
+
+    let config = Config::builder().contract("Vault").build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.contains("User->>+Vault: deposit()"));
+    assert!(diagram.contains("Vault->>+Oracle: latest()"));
+    assert!(!diagram.contains("User->>+Oracle: latest()"));
+    assert!(!diagram.contains("Treasury"));
+
+    let config = Config::builder().contract("Missing").build();
+    let error = generate_sequence_diagram_with_config(&ast, config).unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("Contract not found in AST: Missing"));
+}
+
+#[test]
+fn test_visibility_filter_selects_entrypoints() {
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![
+            function("deposit", "external", vec![], vec![]),
+            function("_accrue", "internal", vec![], vec![]),
+        ],
+    )]);
+
+    let config = Config::builder().include_visibilities(["internal"]).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.contains("User->>+Vault: _accrue()"));
+    assert!(!diagram.contains("User->>+Vault: deposit()"));
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("User->>+Vault: deposit()"));
+    assert!(!diagram.contains("User->>+Vault: _accrue()"));
+}
+
+#[test]
+fn test_max_interactions_truncates_at_group_boundaries() {
+    let guarded_call = json!({
+        "nodeType": "IfStatement",
+        "condition": identifier("ready", None),
+        "trueBody": {
+            "nodeType": "Block",
+            "statements": [member_call(
+                identifier("oracle", Some("contract Oracle")),
+                "latest",
+                vec![],
+            )],
+        },
+    });
+    let ast = source_unit(vec![
+        contract(
+            "Oracle",
+            "contract",
+            vec![function("latest", "external", vec![], vec![])],
+        ),
+        contract(
+            "Vault",
+            "contract",
+            vec![
+                function("deposit", "external", vec![], vec![guarded_call]),
+                function("withdraw", "external", vec![], vec![]),
+            ],
+        ),
+    ]);
+
+    let full = render(&ast);
+    assert!(!full.contains("diagram truncated"));
+
+    let config = Config::builder().max_interactions(4).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.contains("diagram truncated"), "{}", diagram);
+    assert!(!diagram.contains("Vault->>+Oracle: latest()"));
+
+    let opened = diagram
+        .lines()
+        .filter(|l| {
+            ["alt ", "loop ", "rect "]
+                .iter()
+                .any(|k| l.trim().starts_with(k))
+        })
+        .count();
+    let closed = diagram.lines().filter(|l| l.trim() == "end").count();
+    assert_eq!(opened, closed);
+    assert_eq!(
+        diagram.matches("->>+").count(),
+        diagram.matches("-->>-").count()
+    );
+
+    let config = Config::builder()
+        .max_interactions(4)
+        .output_format(OutputFormat::PlantUml)
+        .build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.contains("diagram truncated"), "{}", diagram);
+    assert!(diagram.contains("User -> Vault ++ : deposit()"));
+    assert!(!diagram.contains("Vault -> Oracle"));
+}
+
+/// Assert that no participant is deactivated while inactive and every block closes
+/// the activations opened inside it
+fn assert_balanced_activations(diagram: &str) {
+    let mut active: std::collections::HashMap<String, i32> = Default::default();
+    let mut blocks = Vec::new();
+
+    for line in diagram.lines().map(str::trim) {
+        let keyword = line.split_whitespace().next().unwrap_or("");
+        if ["alt", "loop", "opt", "rect"].contains(&keyword) {
+            blocks.push(active.clone());
+        } else if keyword == "end" {
+            let start = blocks.pop().expect("unmatched end");
+            for (participant, count) in &active {
+                let open = start.get(participant).copied().unwrap_or(0);
+                assert!(
+                    *count <= open,
+                    "{} left active in block:\n{}",
+                    participant,
+                    diagram
+                );
+            }
+        } else if let Some(participant) = line.strip_prefix("deactivate ") {
+            *active.entry(participant.to_string()).or_default() -= 1;
+        } else if let Some((head, _)) = line.split_once(':') {
+            if let Some((from, _)) = head.split_once("-->>-") {
+                let count = active.entry(from.to_string()).or_default();
+                *count -= 1;
+                assert!(
+                    *count >= 0,
+                    "{} deactivated while inactive:\n{}",
+                    from,
+                    diagram
+                );
+            } else if let Some((_, to)) = head.split_once("->>+") {
+                *active.entry(to.to_string()).or_default() += 1;
+            }
+        }
+    }
+    assert!(blocks.is_empty(), "unclosed block:\n{}", diagram);
+}
+
+#[test]
+fn test_activations_balanced_in_if_inside_loop() {
+    let guarded = json!({
+        "nodeType": "IfStatement",
+        "condition": identifier("ready", None),
+        "trueBody": {
+            "nodeType": "Block",
+            "statements": [
+                member_call(identifier("oracle", Some("contract IOracle")), "latest", vec![]),
+                member_call(
+                    identifier("token", Some("contract IERC20")),
+                    "transfer",
+                    vec![identifier("to", None)],
+                ),
+            ],
+        },
+        "falseBody": {
+            "nodeType": "Block",
+            "statements": [member_call(
+                json!({
+                    "nodeType": "FunctionCall",
+                    "expression": {
+                        "nodeType": "MemberAccess",
+                        "memberName": "pool",
+                        "expression": identifier("registry", Some("contract IRegistry")),
+                    },
+                    "arguments": [],
+                }),
+                "sync",
+                vec![],
+            )],
+        },
+    });
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function(
+            "rebalance",
+            "external",
+            vec![],
+            vec![json!({
+                "nodeType": "ForStatement",
+                "body": { "nodeType": "Block", "statements": [guarded] },
+            })],
+        )],
+    )]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("loop "));
+    assert!(diagram.contains("Vault->>+IOracle: latest()"));
+    assert_balanced_activations(&diagram);
+}
+
+#[test]
+fn test_collapse_duplicates_merges_repeated_lines() {
+    let emit = json!({
+        "nodeType": "EmitStatement",
+        "eventCall": {
+            "nodeType": "FunctionCall",
+            "expression": identifier("Ping", None),
+            "arguments": [],
+        },
+    });
+    let nested_loops = json!({
+        "nodeType": "ForStatement",
+        "body": {
+            "nodeType": "Block",
+            "statements": [{
+                "nodeType": "ForStatement",
+                "body": { "nodeType": "Block", "statements": [emit.clone()] },
+            }],
+        },
+    });
+    let latest = || {
+        member_call(
+            identifier("oracle", Some("contract IOracle")),
+            "latest",
+            vec![],
+        )
+    };
+    let ast = source_unit(vec![
+        contract(
+            "IOracle",
+            "interface",
+            vec![function("latest", "external", vec![], vec![])],
+        ),
+        contract(
+            "Beacon",
+            "contract",
+            vec![function(
+                "ping",
+                "external",
+                vec![],
+                vec![
+                    emit.clone(),
+                    emit.clone(),
+                    emit,
+                    nested_loops,
+                    latest(),
+                    latest(),
+                ],
+            )],
+        ),
+    ]);
+
+    let diagram = render(&ast);
+    let emits = diagram
+        .lines()
+        .filter(|l| *l == "Beacon->>Events: emit Ping()");
+    assert_eq!(emits.count(), 3);
+
+    let config = Config::builder().collapse_duplicates(true).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(
+        diagram.contains("Beacon->>Events: emit Ping() (x3)"),
+        "{}",
+        diagram
+    );
+    assert!(!diagram.contains("end (x2)"));
+    // A repeated call collapses together with its return
+    assert!(diagram.contains("Beacon->>+IOracle: latest() (x2)\nIOracle-->>-Beacon: return\n"));
+    assert_balanced_activations(&diagram);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_diagrams_per_contract_are_self_contained() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("Vault.sol");
+    std::fs::write(&source, "// compiled by the fake solc below\n").unwrap();
+
+    let ast = source_unit(vec![
+        contract(
+            "IOracle",
+            "interface",
+            vec![function("latest", "external", vec![], vec![])],
+        ),
+        contract(
+            "Vault",
+            "contract",
+            vec![function(
+                "deposit",
+                "external",
+                vec![],
+                vec![member_call(
+                    identifier("oracle", Some("contract IOracle")),
+                    "latest",
+                    vec![],
+                )],
+            )],
+        ),
+        contract(
+            "Treasury",
+            "contract",
+            vec![function("sweep", "external", vec![], vec![])],
+        ),
+    ]);
+    let output = json!({ "sources": { "Vault.sol": { "AST": ast } } });
+    let solc = fake_solc(dir.path(), &output, None);
+
+    let config = Config::builder().solc_path(solc).build();
+    let diagrams = sol2seq::generate_diagrams_per_contract(&[&source], config).unwrap();
+
+    let mut names: Vec<&String> = diagrams.keys().collect();
+    names.sort();
+    assert_eq!(names, ["Treasury", "Vault"]);
+    assert!(diagrams["Vault"].contains("Vault->>+IOracle: latest()"));
+    assert!(!diagrams["Vault"].contains("Treasury"));
+    assert!(diagrams["Treasury"].contains("User->>+Treasury: sweep()"));
+    assert!(!diagrams["Treasury"].contains("Vault"));
+}
+
+#[test]
+fn test_diagrams_per_contract_from_ast_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let ast_file = dir.path().join("ast.json");
+    let ast = source_unit(vec![
+        contract(
+            "Vault",
+            "contract",
+            vec![function(
+                "deposit",
+                "external",
+                vec![],
+                vec![member_call(
+                    identifier("treasury", Some("contract Treasury")),
+                    "sweep",
+                    vec![],
+                )],
+            )],
+        ),
+        contract(
+            "Treasury",
+            "contract",
+            vec![function("sweep", "external", vec![], vec![])],
+        ),
+    ]);
+    std::fs::write(&ast_file, ast.to_string()).unwrap();
+
+    let diagrams =
+        sol2seq::generate_diagrams_per_contract_from_file(&ast_file, Config::default()).unwrap();
+
+    assert_eq!(diagrams.len(), 2);
+    assert!(diagrams["Vault"].contains("Vault->>+Treasury: sweep()"));
+    assert!(!diagrams["Vault"].contains("User->>+Treasury"));
+    assert!(diagrams["Treasury"].contains("User->>+Treasury: sweep()"));
+    assert!(!diagrams["Treasury"].contains("Vault"));
+}
+
+#[test]
+fn test_important_variables_match_at_word_boundaries() {
+    let vault = contract(
+        "Vault",
+        "contract",
+        vec![
+            typed_param("brokenFlag", "bool"),
+            typed_param("tokenomicsDisabled", "bool"),
+            typed_param("stakingToken", "IERC20"),
+            typed_param("ADMIN_ROLE", "bytes32"),
+        ],
+    );
+    let feed = contract(
+        "Feed",
+        "contract",
+        vec![typed_param("priceOracle", "IOracle")],
+    );
+    let ast = source_unit(vec![vault, feed]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("(stakingToken: IERC20, ADMIN_ROLE: bytes32)"));
+    assert!(!diagram.contains("brokenFlag"));
+    assert!(!diagram.contains("tokenomicsDisabled"));
+    assert!(!diagram.contains("priceOracle"));
+
+    let config = Config::builder()
+        .important_variable_prefix("oracle")
+        .build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.contains("(priceOracle: IOracle)"));
+}
+
+#[test]
+fn test_mermaid_special_characters_are_escaped() {
+    let balances = json!({
+        "nodeType": "VariableDeclaration",
+        "name": "balances",
+        "typeName": {
+            "nodeType": "Mapping",
+            "keyType": { "nodeType": "ElementaryTypeName", "name": "address" },
+            "valueType": { "nodeType": "ElementaryTypeName", "name": "uint256" },
+        },
+    });
+    let label = json!({
+        "nodeType": "Literal",
+        "kind": "string",
+        "value": "fees: 1%; see #42 (docs",
+    });
+    let mut vault = contract(
+        "Vault",
+        "contract",
+        vec![function(
+            "sync",
+            "external",
+            vec![balances],
+            vec![member_call(
+                identifier("registry", Some("contract IRegistry")),
+                "record",
+                vec![label],
+            )],
+        )],
+    );
+    vault["documentation"] = json!("Holds funds; see #docs");
+    let ast = source_unit(vec![vault]);
+
+    let diagram = render(&ast);
+    assert!(diagram.contains("User->>+Vault: sync(balances: mapping(address=>uint256))"));
+    assert!(diagram
+        .contains("Vault->>+IRegistry: record(\"fees: 1%#59; see #35;42 #40;docs\": string)"));
+    assert!(diagram.contains("participant Vault as \"Vault<br/>Holds funds#59; see #35;docs"));
+    assert!(diagram.contains("'primaryColor': '#f5f5f5'"));
+}
+
+#[test]
+fn test_class_diagram_lists_members_and_relationships() {
+    let mut oracle_var = typed_param("oracle", "IOracle");
+    oracle_var["stateVariable"] = json!(true);
+    let allowances = json!({
+        "nodeType": "VariableDeclaration",
+        "name": "allowances",
+        "stateVariable": true,
+        "typeName": {
+            "nodeType": "Mapping",
+            "keyType": { "nodeType": "ElementaryTypeName", "name": "address" },
+            "valueType": {
+                "nodeType": "Mapping",
+                "keyType": { "nodeType": "ElementaryTypeName", "name": "address" },
+                "valueType": { "nodeType": "ElementaryTypeName", "name": "uint256" },
+            },
+        },
+    });
+    let mut vault = contract(
+        "Vault",
+        "contract",
+        vec![
+            oracle_var,
+            allowances,
+            function(
+                "deposit",
+                "external",
+                vec![param("amount", "uint256")],
+                vec![],
+            ),
+            function("_sync", "internal", vec![], vec![]),
+        ],
+    );
+    vault["baseContracts"] = json!([{ "baseName": { "name": "Base" } }]);
+    let ast = source_unit(vec![
+        contract(
+            "IOracle",
+            "interface",
+            vec![function("latest", "external", vec![], vec![])],
+        ),
+        contract("Base", "abstract", vec![]),
+        vault,
+    ]);
+
+    let config = Config::builder().diagram_type(DiagramType::Class).build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config.clone()).unwrap();
+    assert_eq!(diagram, generate_class_diagram(&ast, config).unwrap());
+
+    assert!(diagram.contains("classDiagram"));
+    assert!(diagram.contains("class Vault {"));
+    assert!(
+        diagram.contains("    +deposit(uint256 amount)"),
+        "{}",
+        diagram
+    );
+    assert!(diagram.contains("    #_sync()"));
+    assert!(diagram.contains("    IOracle oracle"));
+    // Parentheses would turn the member into a method
+    assert!(diagram.contains("    mapping~address,mapping~address,uint256~~ allowances"));
+    assert!(diagram.contains("    <<interface>>"));
+    assert!(diagram.contains("Base <|-- Vault"));
+    assert!(diagram.contains("Vault --> IOracle : references"));
+    assert!(!diagram.contains("sequenceDiagram"));
+}
+
+#[test]
+fn test_mapping_of_contracts_references_the_value_type() {
+    let vaults = json!({
+        "nodeType": "VariableDeclaration",
+        "name": "vaults",
+        "stateVariable": true,
+        "typeName": {
+            "nodeType": "Mapping",
+            "keyType": { "nodeType": "ElementaryTypeName", "name": "uint256" },
+            "valueType": {
+                "nodeType": "UserDefinedTypeName",
+                "name": "Vault",
+                "typeDescriptions": { "typeString": "contract Vault" },
+            },
+        },
+    });
+    // Vault is declared after the registry that stores it
+    let ast = source_unit(vec![
+        contract("Registry", "contract", vec![vaults]),
+        contract("Vault", "contract", vec![]),
+    ]);
+
+    let data = analyze_ast(&ast, &Config::default()).unwrap();
+    let references: Vec<(&str, &str)> = data
+        .contract_relationships
+        .iter()
+        .filter(|rel| rel.relation_type == "references")
+        .map(|rel| (rel.source.as_str(), rel.target.as_str()))
+        .collect();
+    assert_eq!(references, vec![("Registry", "Vault")]);
+
+    let config = Config::builder().diagram_type(DiagramType::Class).build();
+    let diagram = generate_class_diagram(&ast, config).unwrap();
+    assert!(
+        diagram.contains("Registry --> Vault : references"),
+        "{}",
+        diagram
+    );
+}
+
+#[test]
+fn test_call_graph_links_functions_across_contracts() {
+    let ast = source_unit(vec![
+        contract(
+            "IOracle",
+            "interface",
+            vec![function("latest", "external", vec![], vec![])],
+        ),
+        contract(
+            "Vault",
+            "contract",
+            vec![
+                function(
+                    "deposit",
+                    "external",
+                    vec![],
+                    vec![identifier_call("_sync", vec![])],
+                ),
+                function(
+                    "_sync",
+                    "internal",
+                    vec![],
+                    vec![member_call(
+                        identifier("oracle", Some("contract IOracle")),
+                        "latest",
+                        vec![],
+                    )],
+                ),
+            ],
+        ),
+    ]);
+
+    let config = Config::builder()
+        .diagram_type(DiagramType::CallGraph)
+        .build();
+    let diagram = generate_sequence_diagram_with_config(&ast, config.clone()).unwrap();
+    assert_eq!(diagram, sol2seq::generate_call_graph(&ast, config).unwrap());
+
+    assert!(diagram.contains("flowchart LR"));
+    assert!(diagram.contains("    subgraph c1[\"Vault\"]"));
+    assert!(diagram.contains("        f0[\"IOracle.latest\"]"));
+    assert!(diagram.contains("        f1[\"Vault._sync\"]"));
+    assert!(diagram.contains("        f2[\"Vault.deposit\"]"));
+    assert!(diagram.contains("    f2 --> f1"), "{}", diagram);
+    assert!(diagram.contains("    f1 --> f0"));
+    assert_eq!(diagram.matches(" --> ").count(), 2);
+}
+
+#[test]
+fn test_natspec_notice_replaces_heuristic_purpose() {
+    let mut deposit = function("deposit", "external", vec![], vec![]);
+    deposit["documentation"] = json!({
+        "nodeType": "StructuredDocumentation",
+        "text": "@notice Stake collateral and\n mint shares\n@dev rounds down",
+    });
+    let mut withdraw = function("withdraw", "external", vec![], vec![]);
+    withdraw["documentation"] = json!("Burn shares for collateral");
+    let mut vault = contract("Vault", "contract", vec![deposit, withdraw]);
+    vault["documentation"] = json!({
+        "nodeType": "StructuredDocumentation",
+        "text": "@title Vault\n@notice Holds \"user\" collateral",
+    });
+
+    let diagram = render(&source_unit(vec![vault]));
+    assert!(diagram.contains("Note over User,Vault: Stake collateral and mint shares"));
+    assert!(diagram.contains("Note over User,Vault: Burn shares for collateral"));
+    assert!(!diagram.contains("Deposit funds"));
+    assert!(
+        diagram.contains("Vault<br/>Holds 'user' collateral"),
+        "{}",
+        diagram
+    );
+}
+
+#[test]
+fn test_custom_function_purposes_override_builtins() {
+    let ast = source_unit(vec![contract(
+        "Pool",
+        "contract",
+        vec![
+            function("liquidate", "external", vec![], vec![]),
+            function("withdraw", "external", vec![], vec![]),
+            function("deposit", "external", vec![], vec![]),
+            function("rebalanceAll", "external", vec![], vec![]),
+            function("autoRebalance", "external", vec![], vec![]),
+        ],
+    )]);
+
+    let dir = tempfile::tempdir().unwrap();
+    let purposes_file = dir.path().join("purposes.json");
+    std::fs::write(
+        &purposes_file,
+        r#"{ "liquidate": "Seize undercollateralized position" }"#,
+    )
+    .unwrap();
+
+    let mut config = Config::builder()
+        .function_purpose("withdraw", "Redeem shares for assets")
+        .function_purpose("rebalance", "Move liquidity between strategies")
+        .build();
+    config
+        .function_purposes
+        .extend(sol2seq::read_purposes_file(&purposes_file).unwrap());
+
+    let diagram = generate_sequence_diagram_with_config(&ast, config).unwrap();
+    assert!(diagram.contains("Note over User,Pool: Seize undercollateralized position"));
+    assert!(diagram.contains("Note over User,Pool: Redeem shares for assets"));
+    assert!(diagram.contains("Note over User,Pool: Deposit funds"));
+    assert!(!diagram.contains("Withdraw funds"));
+
+    // Custom keys match whole names or prefixes, never the middle of a name
+    let lines: Vec<&str> = diagram.lines().collect();
+    let rebalance = lines.iter().position(|line| line.contains("rebalanceAll()")).unwrap();
+    assert_eq!(lines[rebalance - 1], "Note over User,Pool: Move liquidity between strategies");
+    assert_eq!(diagram.matches("Move liquidity between strategies").count(), 1);
+}
+
+#[cfg(feature = "config-file")]
+#[test]
+fn test_config_file_overrides_defaults_for_listed_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_file = dir.path().join("sol2seq.toml");
+    std::fs::write(
+        &config_file,
+        r#"
+theme = "dark"
+participant_order = "first-seen"
+solc_path = "/opt/solc-0.8.24"
+remappings = ["@oz/=lib/oz/"]
+contracts = ["Vault"]
+
+[function_purposes]
+liquidate = "Seize undercollateralized position"
+"#,
+    )
+    .unwrap();
+
+    let config = sol2seq::read_config_file(&config_file).unwrap();
+    assert_eq!(config.theme, Theme::Dark);
+    assert_eq!(config.participant_order, ParticipantOrder::FirstSeen);
+    assert_eq!(config.solc_path, Some("/opt/solc-0.8.24".into()));
+    assert_eq!(config.remappings, vec!["@oz/=lib/oz/".to_string()]);
+    assert_eq!(config.contracts, vec!["Vault".to_string()]);
+    assert_eq!(
+        config.function_purposes["liquidate"],
+        "Seize undercollateralized position"
+    );
+    // Omitted keys keep their defaults
+    assert!(config.show_storage_updates);
+    assert_eq!(config.include_visibilities, vec!["public", "external"]);
+
+    // Misspelled keys are rejected instead of silently ignored
+    std::fs::write(&config_file, "light_color = true\n").unwrap();
+    let error = sol2seq::read_config_file(&config_file).unwrap_err();
+    assert!(error.to_string().contains("Failed to parse config file"));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_command_line_flags_override_the_config_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_file = dir.path().join("sol2seq.toml");
+    std::fs::write(
+        &config_file,
+        "light_colors = true\nonly_public_api = true\nshow_legend = false\n",
+    )
+    .unwrap();
+    let ast_file = dir.path().join("ast.json");
+    let ast = source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function("deposit", "external", vec![], vec![])],
+    )]);
+    std::fs::write(&ast_file, ast.to_string()).unwrap();
+
+    let run = |flags: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_sol2seq"))
+            .arg("--config")
+            .arg(&config_file)
+            .args(flags)
+            .arg("ast")
+            .arg(&ast_file)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // The file alone: light colors, signatures only, no legend
+    let diagram = run(&[]);
+    assert!(
+        diagram.contains("'primaryColor': '#fafbfc',"),
+        "{}",
+        diagram
+    );
+    assert!(!diagram.contains("User->>+Vault: deposit()"));
+    assert!(!diagram.contains("Diagram Legend"));
+
+    // Flags switch off what the file switches on, and `--theme` replaces `light_colors`
+    let diagram = run(&[
+        "--theme",
+        "dark",
+        "--only-public-api=false",
+        "--no-legend=false",
+    ]);
+    assert!(
+        diagram.contains("'primaryColor': '#1f2937',"),
+        "{}",
+        diagram
+    );
+    assert!(diagram.contains("User->>+Vault: deposit()"));
+    assert!(diagram.contains("Diagram Legend"));
+
+    // Both color flags at once are rejected rather than one silently winning
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_sol2seq"))
+        .args(["--light-colors", "--theme", "dark", "ast"])
+        .arg(&ast_file)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_per_contract_without_an_output_directory_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let ast_file = dir.path().join("ast.json");
+    let ast = source_unit(vec![contract("Vault", "contract", vec![])]);
+    std::fs::write(&ast_file, ast.to_string()).unwrap();
+
+    for flags in [&["--per-contract"][..], &["--stdout", "--per-contract"]] {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_sol2seq"))
+            .arg("ast")
+            .arg(&ast_file)
+            .args(flags)
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "{:?}", flags);
+        assert!(output.stdout.is_empty());
+        assert!(String::from_utf8_lossy(&output.stderr).starts_with("error:"));
+    }
+}
+
+#[test]
+fn test_builtin_purposes_match_names_by_prefix_not_substring() {
+    let names = [
+        "terminate",
+        "disapproveList",
+        "sunburn",
+        "mintTo",
+        "Burn",
+        "unstake",
+    ];
+    let ast = source_unit(vec![contract(
+        "Token",
+        "contract",
+        names
+            .iter()
+            .map(|name| function(name, "external", vec![], vec![]))
+            .collect(),
+    )]);
+
+    let diagram = render(&ast);
+    let purpose_of = |name: &str| {
+        let call = format!("User->>+Token: {}()", name);
+        let lines: Vec<&str> = diagram.lines().collect();
+        let index = lines.iter().position(|line| *line == call).unwrap();
+        lines[index - 1]
+            .strip_prefix("Note over User,Token: ")
+            .map(str::to_string)
+    };
+
+    assert_eq!(purpose_of("terminate"), None);
+    assert_eq!(purpose_of("disapproveList"), None);
+    assert_eq!(purpose_of("sunburn"), None);
+    assert_eq!(purpose_of("mintTo").as_deref(), Some("Create new tokens"));
+    assert_eq!(purpose_of("Burn").as_deref(), Some("Destroy tokens"));
+    assert_eq!(purpose_of("unstake").as_deref(), Some("Unstake tokens"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_sources_compile_in_one_solc_run_with_per_file_fallback() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["A.sol", "B.sol"] {
+        std::fs::write(dir.path().join(name), "").unwrap();
+    }
+    let output = json!({ "sources": { "A.sol": { "AST": source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function("deposit", "external", vec![], vec![])],
+    )]) } } });
+
+    // Records each invocation; fails when asked to compile more than `max_files` files
+    let solc_accepting = |max_files: usize| {
+        let solc_dir = dir.path().join(format!("solc-{}", max_files));
+        std::fs::create_dir(&solc_dir).unwrap();
+        let script = format!(
+            "echo \"$@\" >> {}\n[ $(($# - 2)) -gt {} ] && exit 1\ncat \"$out\"",
+            solc_dir.join("calls.txt").display(),
+            max_files
+        );
+        fake_solc(&solc_dir, &output, Some(&script))
+    };
+    let calls = |max_files: usize| {
+        let calls_file = dir
+            .path()
+            .join(format!("solc-{}", max_files))
+            .join("calls.txt");
+        std::fs::read_to_string(calls_file).unwrap()
+    };
+
+    let config = Config::builder().solc_path(solc_accepting(2)).build();
+    let diagram = sol2seq::generate_diagram_from_sources(&[dir.path()], config).unwrap();
+    assert!(diagram.contains("User->>+Vault: deposit()"));
+    assert_eq!(calls(2).lines().count(), 1);
+    assert!(calls(2).contains("A.sol") && calls(2).contains("B.sol"));
+
+    let config = Config::builder().solc_path(solc_accepting(1)).build();
+    let diagram = sol2seq::generate_diagram_from_sources(&[dir.path()], config).unwrap();
+    assert!(diagram.contains("User->>+Vault: deposit()"));
+    assert_eq!(calls(1).lines().count(), 3);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_overlapping_source_paths_compile_each_file_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let token = dir.path().join("Token.sol");
+    std::fs::write(&token, "").unwrap();
+    let output = json!({ "sources": { "Token.sol": { "AST": source_unit(vec![contract(
+        "Token",
+        "contract",
+        vec![function("mint", "external", vec![], vec![])],
+    )]) } } });
+
+    // Fails when asked to compile more than one file, forcing the per-file fallback
+    let script = format!(
+        "echo \"$@\" >> {}\n[ $(($# - 2)) -gt 1 ] && exit 1\ncat \"$out\"",
+        dir.path().join("calls.txt").display()
+    );
+    let solc = fake_solc(dir.path(), &output, Some(&script));
+
+    let config = Config::builder().solc_path(solc).build();
+    let diagram = sol2seq::generate_diagram_from_sources(&[dir.path(), &token], config).unwrap();
+    assert_eq!(
+        diagram.matches("User->>+Token: mint()").count(),
+        1,
+        "{}",
+        diagram
+    );
+    let calls = std::fs::read_to_string(dir.path().join("calls.txt")).unwrap();
+    assert_eq!(calls.lines().count(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_per_file_outputs_keep_shared_imports_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let common = || {
+        let mut unit = source_unit(vec![contract(
+            "Registry",
+            "contract",
+            vec![function("lookup", "external", vec![], vec![])],
+        )]);
+        unit["absolutePath"] = json!("Common.sol");
+        json!({ "AST": unit })
+    };
+    for name in ["A", "B"] {
+        std::fs::write(dir.path().join(format!("{}.sol", name)), "").unwrap();
+        let mut unit = source_unit(vec![contract(
+            name,
+            "contract",
+            vec![function("run", "external", vec![], vec![])],
+        )]);
+        unit["absolutePath"] = json!(format!("{}.sol", name));
+        let output = json!({
+            "sources": { format!("{}.sol", name): { "AST": unit }, "Common.sol": common() },
+            "sourceList": [format!("{}.sol", name), "Common.sol"],
+        });
+        std::fs::write(
+            dir.path().join(format!("{}.json", name)),
+            output.to_string(),
+        )
+        .unwrap();
+    }
+
+    // Fails the combined run; each per-file run prints that file's output
+    let script = format!(
+        "[ $(($# - 2)) -gt 1 ] && exit 1\nfor f; do :; done\ncat {}/$(basename $f .sol).json",
+        dir.path().display()
+    );
+    let solc = fake_solc(dir.path(), &Value::Null, Some(&script));
+
+    let config = Config::builder().solc_path(solc).build();
+    let diagram = sol2seq::generate_diagram_from_sources(&[dir.path()], config).unwrap();
+    assert!(diagram.contains("User->>+A: run()"), "{}", diagram);
+    assert!(diagram.contains("User->>+B: run()"));
+    assert_eq!(diagram.matches("User->>+Registry: lookup()").count(), 1);
+    assert_eq!(
+        diagram
+            .matches("Note over Registry: Functions: lookup")
+            .count(),
+        1
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_per_file_outputs_do_not_share_node_ids() {
+    let dir = tempfile::tempdir().unwrap();
+
+    // Each run numbers nodes from scratch: both files declare their variable as id 1 and
+    // the shared import gets different ids in each run
+    let common = |first_id: i64| {
+        let mut feed = typed_param("feed", "IFeed");
+        feed["id"] = json!(first_id + 1);
+        let mut base = contract("Base", "contract", vec![feed]);
+        base["id"] = json!(first_id);
+        let interface = |name: &str, function_name: &str| {
+            contract(
+                name,
+                "interface",
+                vec![function(function_name, "external", vec![], vec![])],
+            )
+        };
+        let mut unit = source_unit(vec![
+            interface("IOracle", "latest"),
+            interface("IVault", "deposit"),
+            interface("IFeed", "read"),
+            base,
+        ]);
+        unit["absolutePath"] = json!("Common.sol");
+        json!({ "AST": unit })
+    };
+    let reference = |name: &str, id: i64| {
+        let mut receiver = identifier(name, None);
+        receiver["referencedDeclaration"] = json!(id);
+        receiver
+    };
+    let caller = |name: &str, variable: Value, calls: Vec<Value>| {
+        let mut node = contract(
+            name,
+            "contract",
+            vec![variable, function("run", "external", vec![], calls)],
+        );
+        node["baseContracts"] = json!([{ "baseName": { "name": "Base" } }]);
+        let mut unit = source_unit(vec![node]);
+        unit["absolutePath"] = json!(format!("{}.sol", name));
+        json!({ "AST": unit })
+    };
+
+    let mut oracle = typed_param("oracle", "IOracle");
+    oracle["id"] = json!(1);
+    let a = caller(
+        "A",
+        oracle,
+        vec![
+            member_call(reference("oracle", 1), "latest", vec![]),
+            member_call(reference("feed", 11), "read", vec![]),
+        ],
+    );
+    let mut vault = typed_param("vault", "IVault");
+    vault["id"] = json!(1);
+    let b = caller(
+        "B",
+        vault,
+        vec![
+            member_call(reference("vault", 1), "deposit", vec![]),
+            member_call(reference("feed", 21), "read", vec![]),
+        ],
+    );
+    for (name, unit, common_id) in [("A", a, 10), ("B", b, 20)] {
+        std::fs::write(dir.path().join(format!("{}.sol", name)), "").unwrap();
+        let output = json!({
+            "sources": { format!("{}.sol", name): unit, "Common.sol": common(common_id) },
+        });
+        std::fs::write(
+            dir.path().join(format!("{}.json", name)),
+            output.to_string(),
+        )
+        .unwrap();
+    }
+
+    // Fails the combined run; each per-file run prints that file's output
+    let script = format!(
+        "[ $(($# - 2)) -gt 1 ] && exit 1\nfor f; do :; done\ncat {}/$(basename $f .sol).json",
+        dir.path().display()
+    );
+    let solc = fake_solc(dir.path(), &Value::Null, Some(&script));
+
+    let config = Config::builder().solc_path(solc).build();
+    let diagram = sol2seq::generate_diagram_from_sources(&[dir.path()], config).unwrap();
+    assert!(diagram.contains("A->>+IOracle: latest()"), "{}", diagram);
+    assert!(diagram.contains("B->>+IVault: deposit()"));
+    assert!(diagram.contains("A->>+IFeed: read()"));
+    assert!(diagram.contains("B->>+IFeed: read()"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_solc_output_is_cached_by_source_contents() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("Vault.sol");
+    std::fs::write(&source, "import \"./Math.sol\"; contract Vault {}").unwrap();
+    let import = dir.path().join("Math.sol");
+    std::fs::write(&import, "library Math {}").unwrap();
+
+    let mut output = json!({ "sources": { "Vault.sol": { "AST": source_unit(vec![contract(
+        "Vault",
+        "contract",
+        vec![function("deposit", "external", vec![], vec![])],
+    )]) } } });
+    output["sourceList"] = json!([source, import]);
+    let calls_file = dir.path().join("calls.txt");
+    let script = format!("echo run >> {}\ncat \"$out\"", calls_file.display());
+    let solc = fake_solc(dir.path(), &output, Some(&script));
+
+    let cache_dir = dir.path().join("cache");
+    let config = Config::builder()
+        .solc_path(&solc)
+        .cache_dir(&cache_dir)
+        .build();
+    let calls = || {
+        std::fs::read_to_string(&calls_file)
+            .unwrap()
+            .lines()
+            .count()
+    };
+
+    let first = sol2seq::generate_diagram_from_sources(&[&source], config.clone()).unwrap();
+    let second = sol2seq::generate_diagram_from_sources(&[&source], config.clone()).unwrap();
+    assert_eq!(first, second);
+    assert_eq!(calls(), 1);
+
+    std::fs::write(&source, "contract Vault { uint256 x; }").unwrap();
+    sol2seq::generate_diagram_from_sources(&[&source], config.clone()).unwrap();
+    assert_eq!(calls(), 2);
+
+    // Editing an imported file invalidates the entry too
+    std::fs::write(&import, "library Math { uint256 constant ONE = 1; }").unwrap();
+    sol2seq::generate_diagram_from_sources(&[&source], config.clone()).unwrap();
+    assert_eq!(calls(), 3);
+    sol2seq::generate_diagram_from_sources(&[&source], config.clone()).unwrap();
+    assert_eq!(calls(), 3);
+
+    sol2seq::clean_cache(&cache_dir).unwrap();
+    assert!(!cache_dir.exists());
+    sol2seq::generate_diagram_from_sources(&[&source], config).unwrap();
+    assert_eq!(calls(), 4);
+}